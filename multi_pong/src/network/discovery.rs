@@ -0,0 +1,206 @@
+//! LAN discovery of hosted games.
+//!
+//! The browser broadcasts `QUERY` to `DISCOVERY_PORT` every second, and the hosting side answers
+//! with `ANSWER` followed by the CBOR encoded `GameInfo`. The answer is sent from the discovery
+//! port, so the game address is the source IP of the answer with the port in `GameInfo`.
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+pub const DISCOVERY_PORT: u16 = 47_474;
+const QUERY: &[u8] = b"MULTI_PONG?";
+const ANSWER: &[u8] = b"MULTI_PONG!";
+/// Interval for checking whether the thread should stop, and for resending queries.
+const POLL_INTERVAL: Duration = Duration::from_millis(1000);
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Debug)]
+pub struct GameInfo {
+    pub host_name: String,
+    pub player_name: String,
+    pub version: String,
+    pub port: u16,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct DiscoveredGame {
+    pub info: GameInfo,
+    pub address: SocketAddr,
+}
+
+/// A background thread, which runs until `running` is cleared.
+struct Worker {
+    running: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+lazy_static::lazy_static! {
+    static ref RESPONDER: Mutex<Option<Worker>> = Mutex::new(None);
+    static ref SEARCH: Mutex<Option<Worker>> = Mutex::new(None);
+    pub static ref DISCOVERED: Mutex<Vec<DiscoveredGame>> = Mutex::new(Vec::new());
+}
+
+fn host_name() -> String {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| {
+            std::fs::read_to_string("/etc/hostname")
+                .ok()
+                .map(|name| name.trim().to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Replace the thread of the kind with a new one running `body` until the flag is cleared. The
+/// new thread waits for the old one to exit first, as the old one could hold the discovery port
+/// for up to `POLL_INTERVAL`.
+fn replace_running<F>(slot: &Mutex<Option<Worker>>, body: F)
+where
+    F: FnOnce(&AtomicBool) + Send + 'static,
+{
+    let mut slot = slot.lock().unwrap();
+    let old = slot.take().map(|old| {
+        old.running.store(false, Ordering::Relaxed);
+        old.thread
+    });
+    let running = Arc::new(AtomicBool::new(true));
+    let flag = running.clone();
+    let thread = thread::spawn(move || {
+        if let Some(old) = old {
+            let _ = old.join();
+        }
+        body(&flag);
+    });
+    *slot = Some(Worker { running, thread });
+}
+
+/// Ask the thread of the kind to stop. It is kept, so that the next one waits for it.
+fn stop_running(slot: &Mutex<Option<Worker>>) {
+    if let Some(worker) = slot.lock().unwrap().as_ref() {
+        worker.running.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Answer discovery queries for the game hosted on `port`, until `stop_responder` is called.
+pub fn start_responder(port: u16, player_name: String) {
+    let mut answer = ANSWER.to_vec();
+    let info = GameInfo {
+        host_name: host_name(),
+        player_name,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        port,
+    };
+    serde_cbor::to_writer(&mut answer, &info).unwrap();
+    replace_running(&RESPONDER, move |running| {
+        let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT)) {
+            Ok(socket) => socket,
+            Err(e) => {
+                log::warn!("Cannot answer discovery queries: {}", e);
+                return;
+            }
+        };
+        socket.set_read_timeout(Some(POLL_INTERVAL)).unwrap();
+        let mut buffer = [0u8; 64];
+        while running.load(Ordering::Relaxed) {
+            if let Ok((len, from)) = socket.recv_from(&mut buffer) {
+                if &buffer[..len] == QUERY {
+                    let _ = socket.send_to(&answer, from);
+                }
+            }
+        }
+        log::debug!("Discovery responder stopped");
+    });
+}
+
+pub fn stop_responder() {
+    stop_running(&RESPONDER);
+}
+
+/// Broadcast queries and collect the answers into `DISCOVERED`, until `stop_search` is called.
+pub fn start_search() {
+    DISCOVERED.lock().unwrap().clear();
+    replace_running(&SEARCH, |running| {
+        let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)) {
+            Ok(socket) => socket,
+            Err(e) => {
+                log::warn!("Cannot search for games: {}", e);
+                return;
+            }
+        };
+        socket.set_broadcast(true).unwrap();
+        socket.set_read_timeout(Some(POLL_INTERVAL)).unwrap();
+        let mut buffer = [0u8; 1024];
+        while running.load(Ordering::Relaxed) {
+            if let Err(e) = socket.send_to(QUERY, (Ipv4Addr::BROADCAST, DISCOVERY_PORT)) {
+                log::warn!("Error broadcasting discovery query: {}", e);
+            }
+            while let Ok((len, from)) = socket.recv_from(&mut buffer) {
+                if len <= ANSWER.len() || &buffer[..ANSWER.len()] != ANSWER {
+                    continue;
+                }
+                if let Ok(info) = serde_cbor::from_slice::<GameInfo>(&buffer[ANSWER.len()..len]) {
+                    let game = DiscoveredGame {
+                        address: SocketAddr::new(from.ip(), info.port),
+                        info,
+                    };
+                    let mut discovered = DISCOVERED.lock().unwrap();
+                    if !discovered.contains(&game) {
+                        discovered.push(game);
+                    }
+                }
+            }
+        }
+        log::debug!("Discovery search stopped");
+    });
+}
+
+pub fn stop_search() {
+    stop_running(&SEARCH);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Instant;
+
+    /// Query the responder on loopback until it answers, return the port of the hosted game.
+    fn query(deadline: Instant) -> Option<u16> {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let mut buffer = [0u8; 1024];
+        while Instant::now() < deadline {
+            socket
+                .send_to(QUERY, (Ipv4Addr::LOCALHOST, DISCOVERY_PORT))
+                .unwrap();
+            if let Ok(len) = socket.recv(&mut buffer) {
+                if len > ANSWER.len() && &buffer[..ANSWER.len()] == ANSWER {
+                    let info: GameInfo =
+                        serde_cbor::from_slice(&buffer[ANSWER.len()..len]).unwrap();
+                    return Some(info.port);
+                }
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_restart_responder() {
+        start_responder(4000, "first".to_string());
+        assert_eq!(query(Instant::now() + 3 * POLL_INTERVAL), Some(4000));
+        // the new responder binds the port once the old one released it
+        start_responder(4001, "second".to_string());
+        let deadline = Instant::now() + 3 * POLL_INTERVAL;
+        let mut port = query(deadline);
+        while port == Some(4000) {
+            port = query(deadline);
+        }
+        assert_eq!(port, Some(4001));
+        stop_responder();
+    }
+}
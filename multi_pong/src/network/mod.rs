@@ -1,14 +1,19 @@
+pub mod discovery;
+
 use futures::{
-    channel::mpsc::{UnboundedReceiver, UnboundedSender},
+    channel::mpsc::UnboundedReceiver,
     future::FutureExt,
     pin_mut, select,
 };
-use rudp::hand_shake::{client_connect, server_listen};
-use rudp::{start_udp_loop, BypassResult};
+use rudp::hand_shake::{
+    address_family, bind_dual_stack, client_connect_any_resumable, schema_magic,
+    server_accept_resumable,
+};
+use rudp::{start_udp_loop, BypassResult, Config, PacketSender};
 use rudp_derive::PacketDesc;
 use std::sync::{
     atomic::{AtomicI64, Ordering::Relaxed},
-    Arc, Mutex,
+    Mutex,
 };
 use std::thread;
 use std::time::Duration;
@@ -23,14 +28,14 @@ pub enum Side {
 #[derive(Default)]
 pub struct NetworkCommunication {
     pub(crate) receiver: Option<UnboundedReceiver<Packet>>,
-    pub(crate) sender: Option<Arc<UnboundedSender<Packet>>>,
+    pub(crate) sender: Option<PacketSender<Packet>>,
     side: Option<Side>,
 }
 
 impl NetworkCommunication {
     pub fn new(
         receiver: UnboundedReceiver<Packet>,
-        sender: Arc<UnboundedSender<Packet>>,
+        sender: PacketSender<Packet>,
         side: Side,
     ) -> Self {
         Self {
@@ -55,23 +60,35 @@ impl NetworkCommunication {
 
 #[derive(PacketDesc, serde::Serialize, serde::Deserialize)]
 pub enum Packet {
-    #[packet(reliable)]
+    #[packet(reliable, id = 0)]
     Handshake { player_name: String },
-    #[packet(ordered)]
-    PaddleDisplace { position: f32, rotation: f32 },
-    #[packet(ordered)]
+    // the paddles stay within the scene of 100 units, while the ball leaves it by its radius
+    // and the distance of a frame before a side scores, see `WinnerSystem`. The rotation comes
+    // from `atan2`, and the speed of the ball stays at about 45 units per second
+    #[packet(ordered, state, id = 1)]
+    PaddleDisplace {
+        #[quantize(min = 0.0, max = 100.0, bits = 12)]
+        position: f32,
+        #[quantize(min = -3.1416, max = 3.1416, bits = 10)]
+        rotation: f32,
+    },
+    #[packet(ordered, compress, id = 2)]
     BallPosVel {
         generation: u32,
+        // microseconds since the start of the game, enough for 12 days
+        #[quantize(bits = 40)]
         timestamp: u128,
+        #[quantize(min = -5.0, max = 105.0, bits = 12)]
         position: [f32; 2],
+        #[quantize(min = -50.0, max = 50.0, bits = 12)]
         velocity: [f32; 2],
     },
-    #[packet(ordered)]
+    #[packet(ordered, id = 3)]
     Ping {
         client_time: i128,
         expected_arrival: i128,
     },
-    #[packet(ordered)]
+    #[packet(ordered, id = 4)]
     Pong {
         client_time: i128,
         remote_time: i128,
@@ -187,11 +204,32 @@ async fn create_server_background_loop(port: u16) {
     BG_TERMINATE.notify();
     BG_TERMINATE.notified().await;
     let f = async move {
-        log::info!("Server linstening on 0.0.0.0:{}", port);
-        let socket = server_listen(format!("0.0.0.0:{}", port).as_str(), MAGIC).await;
-        log::info!("Connected!");
-        let timeout = Duration::new(0, 20_000_000);
-        let (send, recv) = start_udp_loop::<Packet, _>(socket, timeout, 10, 10, bypass, 0);
+        let socket = match bind_dual_stack(port).await {
+            Ok(socket) => socket,
+            Err(e) => {
+                log::error!("Cannot listen on port {}: {}", port, e);
+                return;
+            }
+        };
+        let magic = schema_magic::<Packet>(MAGIC);
+        let (socket, session) = match server_accept_resumable(socket, &magic).await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                log::error!("Error accepting client: {}", e);
+                return;
+            }
+        };
+        log::info!(
+            "Connected to {} over {}!",
+            session.peer,
+            address_family(&session.peer)
+        );
+        let config = Config {
+            session: Some(session),
+            state_interval: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let (send, recv, _) = start_udp_loop::<Packet, _>(socket, config, bypass);
         let ping_send = send.clone();
         let interval = Duration::new(0, 100_000_000);
         let mut network = Some(NetworkCommunication::new(recv, send, Side::Server));
@@ -228,10 +266,29 @@ async fn create_client_background_loop(addr: &str) {
     BG_TERMINATE.notified().await;
     let f = async move {
         log::info!("Client connecting...");
-        let socket = client_connect("0.0.0.0:0", addr, MAGIC).await;
+        // keep trying until the host is up, resolving the address again for every attempt
+        let attempt_timeout = Duration::new(1, 0);
+        let magic = schema_magic::<Packet>(MAGIC);
+        let (socket, session) = loop {
+            match client_connect_any_resumable(addr, &magic, attempt_timeout).await {
+                Ok(connected) => break connected,
+                // the host runs another version of the game
+                Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                    log::error!("Cannot connect to {}: {}", addr, e);
+                    return;
+                }
+                Err(e) => log::warn!("Cannot connect to {}: {}", addr, e),
+            }
+            delay_for(attempt_timeout).await;
+        };
         log::info!("Client connected!");
-        let timeout = Duration::new(0, 20_000_000);
-        let (send, recv) = start_udp_loop::<Packet, _>(socket, timeout, 10, 10, bypass, 0);
+        // keep the match going if our address changes, e.g. the Wi-Fi reconnected
+        let config = Config {
+            session: Some(session),
+            state_interval: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let (send, recv, _) = start_udp_loop::<Packet, _>(socket, config, bypass);
         let ping_send = send.clone();
         let interval = Duration::new(0, 100_000_000);
         let mut network = Some(NetworkCommunication::new(recv, send, Side::Client));
@@ -0,0 +1,93 @@
+use crate::network::discovery::{start_search, stop_search, DiscoveredGame, DISCOVERED};
+use crate::network::init_client;
+use amethyst::ecs::{Entity, World};
+use amethyst::input::{is_close_requested, StringBindings};
+use amethyst::prelude::WorldExt;
+use amethyst::ui::{Anchor, UiButtonBuilder, UiCreator, UiEventType, UiImage};
+use amethyst::{GameData, SimpleState, SimpleTrans, StateData, StateEvent, Trans};
+
+/// List the games found on the LAN, clicking one of them connects to it.
+#[derive(Default)]
+pub struct ServerBrowser {
+    games: Vec<(Entity, DiscoveredGame)>,
+}
+
+impl ServerBrowser {
+    /// Create the UI and search from scratch, the buttons of the games are added by `update`.
+    fn show(&mut self, world: &mut World) {
+        world.exec(|mut creator: UiCreator<'_>| {
+            creator.create("ui/server_browser.ron", ());
+        });
+        self.games.clear();
+        start_search();
+    }
+}
+
+impl SimpleState for ServerBrowser {
+    fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
+        self.show(data.world);
+    }
+
+    fn on_stop(&mut self, _data: StateData<'_, GameData<'_, '_>>) {
+        stop_search();
+    }
+
+    fn on_pause(&mut self, data: StateData<'_, GameData<'_, '_>>) {
+        stop_search();
+        data.world.delete_all();
+    }
+
+    fn on_resume(&mut self, data: StateData<'_, GameData<'_, '_>>) {
+        self.show(data.world);
+    }
+
+    fn handle_event(
+        &mut self,
+        _data: StateData<'_, GameData<'_, '_>>,
+        event: StateEvent<StringBindings>,
+    ) -> SimpleTrans {
+        match event {
+            StateEvent::Window(event) => {
+                if is_close_requested(&event) {
+                    Trans::Quit
+                } else {
+                    Trans::None
+                }
+            }
+            StateEvent::Ui(event) => {
+                if event.event_type == UiEventType::Click {
+                    if let Some((_, game)) = self.games.iter().find(|g| g.0 == event.target) {
+                        log::info!("Joining {} at {}", game.info.host_name, game.address);
+                        init_client(game.address.to_string());
+                        return Trans::Push(Box::new(super::ClientConnecting));
+                    }
+                }
+                Trans::None
+            }
+            _ => Trans::None,
+        }
+    }
+
+    fn update(&mut self, data: &mut StateData<'_, GameData<'_, '_>>) -> SimpleTrans {
+        let discovered = match DISCOVERED.try_lock() {
+            Ok(discovered) => discovered.clone(),
+            Err(_) => return Trans::None,
+        };
+        for game in discovered.into_iter().skip(self.games.len()) {
+            let mut text = format!("{} @ {}", game.info.player_name, game.info.host_name);
+            if game.info.version != env!("CARGO_PKG_VERSION") {
+                text.push_str(&format!(" (v{})", game.info.version));
+            }
+            let (_, button) = UiButtonBuilder::<(), u32>::new(text)
+                .with_position(250., -250. - 50. * self.games.len() as f32)
+                .with_size(400., 40.)
+                .with_anchor(Anchor::TopLeft)
+                .with_font_size(20.)
+                .with_text_color([0.0, 0.0, 0.0, 1.0])
+                .with_hover_image(UiImage::SolidColor([0.7, 0.7, 0.7, 1.0]))
+                .build_from_world(data.world);
+            self.games.push((button.image_entity, game));
+        }
+        Trans::None
+    }
+}
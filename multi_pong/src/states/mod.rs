@@ -1,4 +1,5 @@
 mod in_game;
+mod browser;
 mod client;
 mod server;
 mod mode_select;
@@ -6,7 +7,8 @@ mod player_name;
 
 pub use mode_select::ModeSelect;
 pub use player_name::{PlayerName, PlayerNameResource};
-pub use client::ClientAddrInput;
+pub use browser::ServerBrowser;
+pub use client::{ClientAddrInput, ClientConnecting};
 pub use server::ServerWait;
 pub use server::ServerPortInput;
 pub use in_game::InGame;
@@ -8,6 +8,7 @@ use amethyst::ui::{UiCreator, UiFinder, UiEventType};
 pub struct ModeSelect {
     server_button: Option<Entity>,
     client_button: Option<Entity>,
+    browse_button: Option<Entity>,
 }
 
 impl SimpleState for ModeSelect {
@@ -44,6 +45,11 @@ impl SimpleState for ModeSelect {
                             return Trans::Push(Box::new(super::ServerPortInput::default()))
                         }
                     }
+                    if let Some(button) = self.browse_button {
+                        if button == event.target {
+                            return Trans::Push(Box::new(super::ServerBrowser::default()))
+                        }
+                    }
                 }
                 Trans::None
             }
@@ -66,6 +72,12 @@ impl SimpleState for ModeSelect {
             });
         }
 
+        if self.browse_button.is_none() {
+            world.exec(|finder: UiFinder| {
+                self.browse_button = finder.find("browse");
+            });
+        }
+
         Trans::None
     }
 }
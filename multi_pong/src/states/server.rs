@@ -1,5 +1,6 @@
+use crate::network::discovery::{start_responder, stop_responder};
 use crate::network::{init_server, NETWORK};
-use crate::states::CurrentState;
+use crate::states::{CurrentState, PlayerNameResource};
 use amethyst::ecs::Entity;
 use amethyst::input::{is_close_requested, StringBindings};
 use amethyst::prelude::WorldExt;
@@ -10,6 +11,16 @@ use amethyst::{GameData, SimpleState, SimpleTrans, StateData, StateEvent, Trans}
 pub struct ServerPortInput {
     button: Option<Entity>,
     input: Option<Entity>,
+    notice: Option<Entity>,
+}
+
+/// Parse the port typed by the player, 0 is refused as the clients could not know the port
+/// picked by the system.
+fn parse_port(text: &str) -> Option<u16> {
+    match text.trim().parse() {
+        Ok(0) | Err(_) => None,
+        Ok(port) => Some(port),
+    }
 }
 
 impl SimpleState for ServerPortInput {
@@ -41,12 +52,24 @@ impl SimpleState for ServerPortInput {
                 if let Some(button) = self.button {
                     if event.event_type == UiEventType::Click && event.target == button {
                         if let Some(input) = self.input {
-                            let storage = data.world.write_storage::<UiText>();
-                            let text = storage.get(input).unwrap();
-                            let port = text.text.clone();
-                            std::mem::drop(storage);
-                            init_server(port.parse().unwrap());
-                            return Trans::Push(Box::new(ServerWait));
+                            let mut storage = data.world.write_storage::<UiText>();
+                            let port = storage.get(input).and_then(|text| parse_port(&text.text));
+                            let name = data.world.read_resource::<PlayerNameResource>();
+                            let error = match (port, &name.my_name) {
+                                (Some(port), Some(name)) => {
+                                    std::mem::drop(storage);
+                                    init_server(port);
+                                    start_responder(port, name.clone());
+                                    return Trans::Push(Box::new(ServerWait));
+                                }
+                                (None, _) => "INVALID PORT, ENTER 1-65535:",
+                                (_, None) => "SET A PLAYER NAME FIRST",
+                            };
+                            if let Some(notice) =
+                                self.notice.and_then(|notice| storage.get_mut(notice))
+                            {
+                                notice.text = error.to_string();
+                            }
                         }
                     }
                 }
@@ -71,6 +94,12 @@ impl SimpleState for ServerPortInput {
             });
         }
 
+        if self.notice.is_none() {
+            world.exec(|finder: UiFinder| {
+                self.notice = finder.find("notice");
+            });
+        }
+
         Trans::None
     }
 }
@@ -93,9 +122,14 @@ impl SimpleState for ServerWait {
         *data.world.write_resource::<CurrentState>() = CurrentState::Ui;
     }
 
+    fn on_stop(&mut self, _data: StateData<'_, GameData<'_, '_>>) {
+        stop_responder();
+    }
+
     fn update(&mut self, data: &mut StateData<'_, GameData<'_, '_>>) -> SimpleTrans {
         if let Ok(mut network) = NETWORK.try_lock() {
             if let Some((network, start_time)) = network.take() {
+                stop_responder();
                 data.world.insert(network);
                 data.world.insert(Some(start_time));
             }
@@ -1,5 +1,5 @@
 use lazy_static::lazy_static;
-use rudp::{hand_shake::*, start_udp_loop, BypassResult};
+use rudp::{hand_shake::*, start_udp_loop, BypassResult, Config};
 use rudp_derive::PacketDesc;
 use std::env;
 use std::sync::Mutex;
@@ -124,9 +124,7 @@ async fn main() {
         return;
     };
     println!("Connected!");
-    // 20ms
-    let timeout = Duration::new(0, 20_000_000);
-    let (send, mut recv) = start_udp_loop::<Packet, _>(socket, timeout, 10, 10, bypass, 0);
+    let (send, mut recv, _) = start_udp_loop::<Packet, _>(socket, Config::default(), bypass);
     let recv_task = tokio::spawn(async move {
         loop {
             let p = recv.next().await;
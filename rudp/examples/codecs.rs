@@ -0,0 +1,75 @@
+use rudp::{
+    codec::{bincode::Options, Codec},
+    DeserializeError, PacketDesc,
+};
+use rudp_derive::PacketDesc;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Bincode with variable length integers, as an example of a codec outside of `rudp`.
+struct Varint;
+
+impl Codec for Varint {
+    fn serialize<T: Serialize>(value: &T, writer: &mut Vec<u8>) {
+        rudp::codec::bincode::DefaultOptions::new()
+            .serialize_into(writer, value)
+            .unwrap();
+    }
+
+    fn deserialize<T: DeserializeOwned>(data: &[u8]) -> Result<T, DeserializeError> {
+        rudp::codec::bincode::DefaultOptions::new()
+            .deserialize(data)
+            .map_err(|err| DeserializeError(format!("bincode error: {:?}", err)))
+    }
+}
+
+/// The same packets with every codec.
+macro_rules! packets {
+    ($($name:ident: $codec:literal,)*) => {
+        $(
+            #[derive(Serialize, Deserialize, PacketDesc, PartialEq, Debug)]
+            #[packet(codec = $codec)]
+            enum $name {
+                #[packet(reliable)]
+                Hello { name: String },
+                #[packet(unreliable, ordered)]
+                Position { x: f32, y: f32, frame: u64 },
+            }
+        )*
+    };
+}
+
+packets! {
+    Cbor: "cbor",
+    Bincode: "bincode",
+    Postcard: "postcard",
+    Custom: "Varint",
+}
+
+/// Round trip the packet, and print the size of the payload.
+fn measure<T: PacketDesc + PartialEq + std::fmt::Debug>(codec: &str, packet: T) {
+    let mut payload = Vec::new();
+    packet.serialize(&mut payload);
+    let decoded = T::deserialize(packet.id(), &payload).unwrap();
+    assert_eq!(decoded, packet);
+    println!("{:>8}: {:>3} bytes {:?}", codec, payload.len(), packet);
+}
+
+macro_rules! measure_all {
+    ($packet:ident $fields:tt) => {
+        measure("cbor", Cbor::$packet $fields);
+        measure("bincode", Bincode::$packet $fields);
+        measure("postcard", Postcard::$packet $fields);
+        measure("varint", Custom::$packet $fields);
+    };
+}
+
+fn main() {
+    measure_all!(Hello {
+        name: "player".to_string()
+    });
+    measure_all!(Position {
+        x: 1.5,
+        y: -3.0,
+        frame: 1200
+    });
+}
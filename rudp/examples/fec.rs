@@ -0,0 +1,72 @@
+use futures::StreamExt;
+use rudp::{hand_shake::*, start_udp_loop, BypassResult, Config, Redundancy};
+use rudp_derive::PacketDesc;
+use std::collections::HashSet;
+use tokio::{
+    net::UdpSocket,
+    time::{sleep, Duration, Instant},
+};
+
+const MAGIC: &[u8] = "MULTIPONG".as_bytes();
+const EVENTS: u32 = 1000;
+
+#[derive(serde::Serialize, serde::Deserialize, PacketDesc)]
+enum Packet {
+    #[packet(reliable)]
+    Event { index: u32, sent_micros: u64 },
+}
+
+/// Send events through a lossy link and report how late they arrived.
+async fn run(fec: Redundancy) {
+    // Both sides run in this process on loopback, dropping packets on both ends.
+    let config = move || Config {
+        drop_percentage: 10,
+        fec,
+        ..Default::default()
+    };
+    let start = Instant::now();
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let server_address = server.local_addr().unwrap();
+    let receiving = tokio::spawn(async move {
+        let socket = server_accept(server, MAGIC).await;
+        let (_send, mut recv, stats) =
+            start_udp_loop::<Packet, _>(socket, config(), BypassResult::ToUser);
+        let mut seen = HashSet::new();
+        let mut latencies = Vec::new();
+        while seen.len() < EVENTS as usize {
+            let Packet::Event { index, sent_micros } = recv.next().await.unwrap();
+            if seen.insert(index) {
+                latencies.push(start.elapsed().as_micros() as u64 - sent_micros);
+            }
+        }
+        (latencies, stats.recovered())
+    });
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.connect(server_address).await.unwrap();
+    let socket = client_handshake(socket, MAGIC).await;
+    let (send, _recv, stats) = start_udp_loop::<Packet, _>(socket, config(), BypassResult::ToUser);
+    for index in 0..EVENTS {
+        let sent_micros = start.elapsed().as_micros() as u64;
+        let _ = send.unbounded_send(Packet::Event { index, sent_micros });
+        sleep(Duration::from_millis(2)).await;
+    }
+    let (mut latencies, recovered) = receiving.await.unwrap();
+    latencies.sort_unstable();
+    println!(
+        "{:<32} avg {:>5.1}ms  p99 {:>5.1}ms  retransmissions {:>3}  parity {:>3}  recovered {:>3}",
+        format!("{:?}", fec),
+        latencies.iter().sum::<u64>() as f64 / latencies.len() as f64 / 1000.0,
+        latencies[latencies.len() * 99 / 100] as f64 / 1000.0,
+        stats.retransmissions(),
+        stats.parity_sent(),
+        recovered
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    run(Redundancy::Off).await;
+    run(Redundancy::Fixed(4)).await;
+    run(Redundancy::Adaptive { max_group: 16 }).await;
+}
@@ -0,0 +1,78 @@
+use rudp::{relay, rendezvous, BypassResult, Config};
+use rudp_derive::PacketDesc;
+use std::env;
+use std::net::SocketAddr;
+use tokio::{join, time::Duration};
+use tokio_stream::StreamExt;
+
+const MAGIC: &[u8] = "MULTIPONG".as_bytes();
+const RENDEZVOUS: &str = "127.0.0.1:4010";
+const RELAY: &str = "127.0.0.1:4011";
+
+#[derive(serde::Serialize, serde::Deserialize, PacketDesc, PartialEq, Debug)]
+enum Packet {
+    #[packet(reliable)]
+    Hello { role: String },
+}
+
+async fn peer(rendezvous_server: SocketAddr, relay_server: SocketAddr) {
+    let connection = relay::connect_with_fallback(
+        "127.0.0.1:0",
+        rendezvous_server,
+        relay_server,
+        "example",
+        MAGIC,
+        Duration::from_secs(2),
+    )
+    .await
+    .unwrap();
+    let role = connection.role;
+    println!(
+        "{:?} connected to {}",
+        role,
+        connection.socket.peer_addr().unwrap()
+    );
+    let (send, mut recv, stats) =
+        connection.start::<Packet, _>(Config::default(), BypassResult::ToUser);
+    let receipt = send.send_with_receipt(
+        Packet::Hello {
+            role: format!("{:?}", role),
+        },
+        Duration::from_secs(1),
+    );
+    let p = recv.next().await.unwrap();
+    // wait for the ACK of our own packet before closing the loop
+    let delivery = receipt.await;
+    println!(
+        "{:?} received {:?}, own packet: {:?}, path: {:?}, datagrams sent: {}",
+        role,
+        p,
+        delivery,
+        stats.path(),
+        stats.datagrams_sent()
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    let env = env_logger::Env::default()
+        .filter_or("MY_LOG_LEVEL", "INFO")
+        .write_style_or("MY_LOG_STYLE", "always");
+    env_logger::init_from_env(env);
+
+    // Both peers and the servers run in this process on loopback. With the `relay` argument, the
+    // rendezvous server is not started so the peers have to fall back to the relay.
+    let args: Vec<String> = env::args().collect();
+    let use_relay = args.len() == 2 && args[1] == "relay";
+    if !use_relay {
+        tokio::spawn(async { rendezvous::serve(RENDEZVOUS).await.unwrap() });
+    }
+    tokio::spawn(async { relay::serve(RELAY).await.unwrap() });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let rendezvous_server = RENDEZVOUS.parse().unwrap();
+    let relay_server = RELAY.parse().unwrap();
+    join!(
+        peer(rendezvous_server, relay_server),
+        peer(rendezvous_server, relay_server)
+    );
+}
@@ -0,0 +1,19 @@
+//! An inspector decoding the packets of an application, which would replace `Packet` with its
+//! own. Forward a session through it with:
+//! ```text
+//! cargo run --example inspect -- --proxy "127.0.0.1:4000" "127.0.0.1:4001"
+//! ```
+use rudp::inspect::{main as inspect, Decode};
+use rudp_derive::PacketDesc;
+
+#[derive(PacketDesc, Debug)]
+enum Packet {
+    #[packet(reliable)]
+    Hello { name: String },
+    #[packet(unreliable, ordered, state)]
+    Position { x: f32, y: f32 },
+}
+
+fn main() {
+    inspect(Decode::<Packet>::new());
+}
@@ -0,0 +1,86 @@
+use rudp::rpc::{respond, CallOptions, Rpc};
+use rudp::{hand_shake::*, start_udp_loop, BypassResult, Config};
+use rudp_derive::PacketDesc;
+use std::sync::Arc;
+use tokio::{net::UdpSocket, time::Duration};
+
+const MAGIC: &[u8] = "MULTIPONG".as_bytes();
+
+#[derive(serde::Serialize, serde::Deserialize, PacketDesc, Clone, PartialEq, Debug)]
+enum Packet {
+    Add {
+        a: i32,
+        b: i32,
+        #[packet(correlation)]
+        call: u64,
+    },
+    #[packet(response_to = "Add")]
+    Sum {
+        sum: i32,
+        #[packet(correlation)]
+        call: u64,
+    },
+}
+
+fn server_bypass(p: Packet) -> BypassResult<Packet> {
+    match p {
+        Packet::Add { a, b, .. } => BypassResult::ToSender(respond(
+            &p,
+            Packet::Sum {
+                sum: a + b,
+                call: 0,
+            },
+        )),
+        p => BypassResult::ToUser(p),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let env = env_logger::Env::default()
+        .filter_or("MY_LOG_LEVEL", "INFO")
+        .write_style_or("MY_LOG_STYLE", "always");
+    env_logger::init_from_env(env);
+
+    // Both sides run in this process on loopback, dropping packets to show the retries.
+    let config = || Config {
+        drop_percentage: 30,
+        ..Default::default()
+    };
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let server_address = server.local_addr().unwrap();
+    tokio::spawn(async move {
+        let socket = server_accept(server, MAGIC).await;
+        let (_send, _recv, _) = start_udp_loop::<Packet, _>(socket, config(), server_bypass);
+        futures::future::pending::<()>().await;
+    });
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.connect(server_address).await.unwrap();
+    let socket = client_handshake(socket, MAGIC).await;
+    let rpc = Arc::new(Rpc::new());
+    let dispatch = rpc.clone();
+    let (send, _recv, _) =
+        start_udp_loop::<Packet, _>(socket, config(), move |p| match dispatch.dispatch(p) {
+            Some(p) => BypassResult::ToUser(p),
+            None => BypassResult::Discard,
+        });
+    let options = CallOptions {
+        timeout: Duration::from_millis(100),
+        retries: 5,
+    };
+    for a in 0..10 {
+        let request = Packet::Add {
+            a,
+            b: a * 10,
+            call: 0,
+        };
+        match rpc.call(&send, request, options).await {
+            Ok(Packet::Sum { sum, call }) => {
+                println!("{} + {} = {} (call {})", a, a * 10, sum, call)
+            }
+            Ok(p) => println!("Unexpected response {:?}", p),
+            Err(e) => println!("Call failed: {}", e),
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use rudp::{hand_shake::*, PacketDesc};
+use rudp_derive::PacketDesc;
+use tokio::net::UdpSocket;
+
+const MAGIC: &[u8] = "MULTIPONG".as_bytes();
+
+#[derive(PacketDesc, Debug)]
+enum Packet {
+    #[packet(reliable, id = 0)]
+    Hello { name: String },
+    #[packet(unreliable, ordered, state, id = 1)]
+    Position { x: f32, y: f32 },
+    #[packet(reliable, compress, id = 2)]
+    Map(Vec<[u8; 4]>, Option<String>),
+}
+
+/// The packets of an older build, without `Map`.
+mod old {
+    use rudp_derive::PacketDesc;
+
+    #[derive(PacketDesc, Debug)]
+    pub enum Packet {
+        #[packet(reliable, id = 0)]
+        Hello { name: String },
+        #[packet(unreliable, ordered, state, id = 1)]
+        Position { x: f32, y: f32 },
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    print!("{}", Packet::schema().unwrap().to_json());
+
+    // An old client connecting to a new server is refused during the handshake.
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let server_address = server.local_addr().unwrap();
+    tokio::spawn(async move {
+        let magic = schema_magic::<Packet>(MAGIC);
+        server_accept(server, &magic).await;
+    });
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.connect(server_address).await.unwrap();
+    let magic = schema_magic::<old::Packet>(MAGIC);
+    match try_client_handshake(socket, &magic).await {
+        Ok(_) => println!("Connected"),
+        Err(e) => println!("Old client: {}", e),
+    }
+}
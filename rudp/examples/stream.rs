@@ -0,0 +1,88 @@
+use rudp::{hand_shake::*, start_udp_loop, BypassResult, Config};
+use rudp_derive::PacketDesc;
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+};
+use tokio::{
+    net::UdpSocket,
+    time::{sleep, Duration, Instant},
+};
+use tokio_stream::StreamExt;
+
+const MAGIC: &[u8] = "MULTIPONG".as_bytes();
+const SIZE: usize = 1 << 20;
+
+#[derive(serde::Serialize, serde::Deserialize, PacketDesc, PartialEq, Debug)]
+enum Packet {
+    #[packet(ordered)]
+    Tick(u32),
+}
+
+#[tokio::main]
+async fn main() {
+    let env = env_logger::Env::default()
+        .filter_or("MY_LOG_LEVEL", "INFO")
+        .write_style_or("MY_LOG_STYLE", "always");
+    env_logger::init_from_env(env);
+
+    // Both sides run in this process on loopback. The server sends 1 MiB through a stream while
+    // sending a tick every 10ms, which the client keeps receiving during the transfer.
+    let config = || Config {
+        drop_percentage: 5,
+        ..Default::default()
+    };
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let server_address = server.local_addr().unwrap();
+    tokio::spawn(async move {
+        let socket = server_accept(server, MAGIC).await;
+        let (send, _recv, _) = start_udp_loop::<Packet, _>(socket, config(), BypassResult::ToUser);
+        let ticks = send.clone();
+        tokio::spawn(async move {
+            for i in 0.. {
+                if ticks.unbounded_send(Packet::Tick(i)).is_err() {
+                    return;
+                }
+                sleep(Duration::from_millis(10)).await;
+            }
+        });
+        let data: Vec<u8> = (0..SIZE).map(|i| (i % 251) as u8).collect();
+        let mut stream = send.open_stream();
+        stream.set_window(8);
+        for part in data.chunks(64 * 1024) {
+            stream.write(part).await.unwrap();
+            let progress = stream.progress();
+            println!(
+                "written {:>7}, acknowledged {:>7}",
+                progress.written, progress.acknowledged
+            );
+        }
+        stream.finish().await.unwrap();
+        futures::future::pending::<()>().await;
+    });
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.connect(server_address).await.unwrap();
+    let socket = client_handshake(socket, MAGIC).await;
+    let (send, mut recv, _) = start_udp_loop::<Packet, _>(socket, config(), BypassResult::ToUser);
+    // the server finishes the handshake on our first packet
+    send.unbounded_send(Packet::Tick(0)).unwrap();
+    let start = Instant::now();
+    let ticks = Arc::new(AtomicU32::new(0));
+    let counter = ticks.clone();
+    tokio::spawn(async move {
+        while let Some(Packet::Tick(_)) = recv.next().await {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+    let stream = send.accept_stream().await.unwrap();
+    let data = stream.read_to_end().await.unwrap();
+    let elapsed = start.elapsed();
+    assert!(data.iter().enumerate().all(|(i, &b)| b == (i % 251) as u8));
+    println!(
+        "received {} bytes in {:?}, and {} ticks meanwhile",
+        data.len(),
+        elapsed,
+        ticks.load(Ordering::Relaxed)
+    );
+}
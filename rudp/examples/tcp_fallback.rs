@@ -0,0 +1,75 @@
+use rudp::{tcp, BypassResult, Config};
+use rudp_derive::PacketDesc;
+use std::env;
+use tokio::{
+    net::{TcpListener, UdpSocket},
+    time::Duration,
+};
+use tokio_stream::StreamExt;
+
+const MAGIC: &[u8] = "MULTIPONG".as_bytes();
+const SERVER: &str = "127.0.0.1:4012";
+
+#[derive(serde::Serialize, serde::Deserialize, PacketDesc, PartialEq, Debug)]
+enum Packet {
+    #[packet(reliable)]
+    Hello { name: String },
+    #[packet(unreliable)]
+    Position { x: f32, y: f32 },
+}
+
+/// Echo every packet back to the client.
+async fn server(socket: UdpSocket, listener: TcpListener) {
+    let transport = tcp::server_accept_any(socket, &listener, MAGIC)
+        .await
+        .unwrap();
+    let (_send, _recv, stats) =
+        transport.start::<Packet, _>(Config::default(), BypassResult::ToSender);
+    println!("Server accepted a client, path: {:?}", stats.path());
+    // keep the loop running until the example exits
+    std::future::pending::<()>().await;
+}
+
+#[tokio::main]
+async fn main() {
+    let env = env_logger::Env::default()
+        .filter_or("MY_LOG_LEVEL", "INFO")
+        .write_style_or("MY_LOG_STYLE", "always");
+    env_logger::init_from_env(env);
+
+    // Both ends run in this process on loopback. With the `blocked` argument, the UDP socket of
+    // the server is bound to another port, so the client falls back to TCP.
+    let args: Vec<String> = env::args().collect();
+    let blocked = args.len() == 2 && args[1] == "blocked";
+    let socket = if blocked {
+        UdpSocket::bind("127.0.0.1:0").await.unwrap()
+    } else {
+        UdpSocket::bind(SERVER).await.unwrap()
+    };
+    let listener = TcpListener::bind(SERVER).await.unwrap();
+    tokio::spawn(server(socket, listener));
+
+    let transport = tcp::connect_with_fallback(SERVER, MAGIC, Duration::from_millis(500))
+        .await
+        .unwrap();
+    let (send, mut recv, stats) =
+        transport.start::<Packet, _>(Config::default(), BypassResult::ToUser);
+    // The first datagram after the UDP handshake is consumed by `server_accept`, so start with a
+    // reliable packet, which is resent until the echo arrives.
+    let receipt = send.send_with_receipt(
+        Packet::Hello {
+            name: "client".to_string(),
+        },
+        Duration::from_secs(1),
+    );
+    println!("Client received {:?}", recv.next().await.unwrap());
+    send.unbounded_send(Packet::Position { x: 1.0, y: 2.0 })
+        .unwrap();
+    println!("Client received {:?}", recv.next().await.unwrap());
+    println!(
+        "Own packet: {:?}, path: {:?}, datagrams sent: {}",
+        receipt.await,
+        stats.path(),
+        stats.datagrams_sent()
+    );
+}
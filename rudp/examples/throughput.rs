@@ -0,0 +1,145 @@
+use futures::StreamExt;
+use rudp::{hand_shake::*, start_udp_loop, BypassResult, Config, ConnectionStats, PacketSender};
+use rudp_derive::PacketDesc;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::{net::UdpSocket, sync::mpsc, task::yield_now, time::Instant};
+
+/// Counts the heap allocations of the whole process, both ends of the connection included.
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const MAGIC: &[u8] = "MULTIPONG".as_bytes();
+const WARMUP: u32 = 2_000;
+const RELIABLE: u32 = 20_000;
+const UNRELIABLE: u32 = 100_000;
+/// Slots of the bulk connection, so that many reliable packets are in flight.
+const BULK_SLOTS: usize = 4096;
+
+#[derive(serde::Serialize, serde::Deserialize, PacketDesc)]
+enum Packet {
+    #[packet(reliable)]
+    Event { index: u32 },
+    #[packet(unreliable)]
+    Position { x: f32, y: f32 },
+}
+
+async fn connect(
+    slot_capacity: usize,
+) -> (
+    PacketSender<Packet>,
+    mpsc::UnboundedReceiver<u32>,
+    Arc<ConnectionStats>,
+) {
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let server_address = server.local_addr().unwrap();
+    let (events, received) = mpsc::unbounded_channel();
+    let config = move || Config {
+        slot_capacity,
+        ..Default::default()
+    };
+    tokio::spawn(async move {
+        let socket = server_accept(server, MAGIC).await;
+        let (_send, mut recv, _) =
+            start_udp_loop::<Packet, _>(socket, config(), BypassResult::ToUser);
+        while let Some(packet) = recv.next().await {
+            if let Packet::Event { index } = packet {
+                let _ = events.send(index);
+            }
+        }
+    });
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.connect(server_address).await.unwrap();
+    let socket = client_handshake(socket, MAGIC).await;
+    let (send, _recv, stats) = start_udp_loop::<Packet, _>(socket, config(), BypassResult::ToUser);
+    (send, received, stats)
+}
+
+/// Send the reliable packets and wait until the remote received them all.
+async fn reliable(
+    send: &PacketSender<Packet>,
+    received: &mut mpsc::UnboundedReceiver<u32>,
+    from: u32,
+    count: u32,
+) {
+    for index in from..from + count {
+        let _ = send.unbounded_send(Packet::Event { index });
+    }
+    for _ in 0..count {
+        received.recv().await.unwrap();
+    }
+}
+
+fn report(name: &str, packets: u32, start: Instant, allocations: u64) {
+    let elapsed = start.elapsed();
+    println!(
+        "{:<11} {:>7} packets in {:>8.3}s  {:>9.0} packets/s  {:>6.2} allocations/packet",
+        name,
+        packets,
+        elapsed.as_secs_f64(),
+        packets as f64 / elapsed.as_secs_f64(),
+        (ALLOCATIONS.load(Ordering::Relaxed) - allocations) as f64 / packets as f64
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    let (send, mut received, stats) = connect(Config::default().slot_capacity).await;
+    reliable(&send, &mut received, 0, WARMUP).await;
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    reliable(&send, &mut received, WARMUP, RELIABLE).await;
+    report("reliable", RELIABLE, start, allocations);
+
+    // the remote may drop some of them when overwhelmed, so only the sending side is measured
+    let sent = stats.datagrams_sent();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for i in 0..UNRELIABLE {
+        let _ = send.unbounded_send(Packet::Position {
+            x: i as f32,
+            y: 0.0,
+        });
+        if i % 64 == 0 {
+            // let the loop catch up instead of queueing everything
+            yield_now().await;
+        }
+    }
+    while stats.datagrams_sent() < sent + UNRELIABLE as u64 {
+        yield_now().await;
+    }
+    report("unreliable", UNRELIABLE, start, allocations);
+
+    let (send, mut received, _) = connect(BULK_SLOTS).await;
+    reliable(&send, &mut received, 0, WARMUP).await;
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    reliable(&send, &mut received, WARMUP, RELIABLE).await;
+    report("bulk", RELIABLE, start, allocations);
+}
@@ -0,0 +1,54 @@
+use rudp::{hand_shake::*, start_udp_loop, BypassResult, Config};
+use rudp_derive::PacketDesc;
+use tokio::{net::UdpSocket, time::Duration};
+use tokio_stream::StreamExt;
+use tracing_subscriber::EnvFilter;
+
+const MAGIC: &[u8] = "MULTIPONG".as_bytes();
+
+#[derive(serde::Serialize, serde::Deserialize, PacketDesc, Debug)]
+enum Packet {
+    #[packet(reliable)]
+    Event { index: u32 },
+    #[packet(unreliable, ordered)]
+    Position { x: f32, y: f32 },
+}
+
+#[tokio::main]
+async fn main() {
+    // RUST_LOG=rudp=debug only prints the retransmissions and the dropped packets
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("rudp=trace")),
+        )
+        .init();
+
+    // Both ends run in this process on loopback, dropping packets on both ends.
+    let config = || Config {
+        drop_percentage: 20,
+        ..Default::default()
+    };
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let server_address = server.local_addr().unwrap();
+    tokio::spawn(async move {
+        let socket = server_accept(server, MAGIC).await;
+        let (_send, mut recv, _) =
+            start_udp_loop::<Packet, _>(socket, config(), BypassResult::ToUser);
+        while recv.next().await.is_some() {}
+    });
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.connect(server_address).await.unwrap();
+    let socket = client_handshake(socket, MAGIC).await;
+    let (send, _recv, _) = start_udp_loop::<Packet, _>(socket, config(), BypassResult::ToUser);
+    let mut receipts = Vec::new();
+    for index in 0..5 {
+        receipts.push(send.send_with_receipt(Packet::Event { index }, Duration::from_secs(1)));
+        let _ = send.unbounded_send(Packet::Position {
+            x: index as f32,
+            y: 0.0,
+        });
+    }
+    for receipt in receipts {
+        receipt.await;
+    }
+}
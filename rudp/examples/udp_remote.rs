@@ -1,4 +1,7 @@
-use rudp::{hand_shake::*, start_udp_loop, BypassResult, DeserializeError, PacketDesc};
+use rudp::{
+    capture::CaptureWriter, hand_shake::*, start_udp_loop, BypassResult, Config,
+    DeserializeError, PacketDesc,
+};
 use std::convert::TryInto;
 use std::env;
 use std::mem::size_of;
@@ -92,12 +95,18 @@ async fn main() {
         println!("Server: <bind address with port>");
         println!("Client: <bind address with port> <server address with port>");
         println!("Note: The bind address should not be 127.0.0.1, better use 0.0.0.0");
+        println!("Set RUDP_CAPTURE to a file path to record the traffic.");
         return;
     };
     println!("Connected!");
-    // 20ms
-    let timeout = Duration::new(0, 20_000_000);
-    let (send, mut recv) = start_udp_loop::<Packet, _>(socket, timeout, 10, 10, bypass, 0);
+    let capture = env::var("RUDP_CAPTURE")
+        .ok()
+        .map(|path| CaptureWriter::create(path).unwrap());
+    let config = Config {
+        capture,
+        ..Default::default()
+    };
+    let (send, mut recv, _) = start_udp_loop::<Packet, _>(socket, config, bypass);
     let start = Instant::now();
     const WINDOW_SIZE: usize = 1000;
     let mut window: [u128; WINDOW_SIZE] = [0; WINDOW_SIZE];
@@ -0,0 +1,271 @@
+//! Batched socket I/O.
+//!
+//! The sender collects the datagrams it writes until the loop would wait, and the receiver reads
+//! into a batch of buffers. With the `batch` feature on Linux, a batch is written with one
+//! `sendmmsg` call and read with one `recvmmsg` call. Runs of datagrams with the same length, such
+//! as the chunks of a stream, are passed to the kernel as a single GSO buffer (`UDP_SEGMENT`) if
+//! it supports that. A link falls back to one syscall per datagram for the rest of the connection
+//! if a call is not supported. Resumable links read the source address of every datagram in a
+//! batch, and the server gives the address of the client to every message it writes, as its
+//! socket is not connected.
+//!
+//! GRO is not used, as a coalesced read needs a 64 KiB buffer for every entry of the batch, which
+//! is too much for a server carrying many sessions.
+use super::protocol::RECV_CAPACITY;
+#[cfg(all(target_os = "linux", feature = "batch"))]
+use std::net::SocketAddr;
+
+/// Maximum number of datagrams moved by one call.
+pub const BATCH: usize = 32;
+
+/// Datagrams waiting to be sent, stored back to back.
+pub struct SendBatch {
+    buffer: Vec<u8>,
+    ends: Vec<usize>,
+    capacity: usize,
+}
+
+impl SendBatch {
+    pub fn new(capacity: usize) -> Self {
+        SendBatch {
+            buffer: Vec::new(),
+            ends: Vec::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, datagram: &[u8]) {
+        self.buffer.extend_from_slice(datagram);
+        self.ends.push(self.buffer.len());
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.ends.len() >= self.capacity
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ends.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    pub fn get(&self, i: usize) -> &[u8] {
+        let start = if i == 0 { 0 } else { self.ends[i - 1] };
+        &self.buffer[start..self.ends[i]]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> {
+        (0..self.len()).map(move |i| self.get(i))
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+        self.ends.clear();
+    }
+}
+
+/// Datagrams read by one call, each in a buffer of `RECV_CAPACITY` bytes.
+pub struct RecvBatch {
+    buffer: Vec<u8>,
+    lens: [usize; BATCH],
+    #[cfg(all(target_os = "linux", feature = "batch"))]
+    from: [Option<SocketAddr>; BATCH],
+    count: usize,
+}
+
+impl RecvBatch {
+    pub fn new() -> Self {
+        RecvBatch {
+            buffer: vec![0; BATCH * RECV_CAPACITY],
+            lens: [0; BATCH],
+            #[cfg(all(target_os = "linux", feature = "batch"))]
+            from: [None; BATCH],
+            count: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn get(&self, i: usize) -> &[u8] {
+        let start = i * RECV_CAPACITY;
+        &self.buffer[start..start + self.lens[i]]
+    }
+
+    /// Buffer for reading a single datagram, followed by `set_one`.
+    pub fn first_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer[..RECV_CAPACITY]
+    }
+
+    pub fn set_one(&mut self, len: usize) {
+        self.lens[0] = len;
+        self.count = 1;
+    }
+
+    /// Source address of a datagram read by `recvmmsg`.
+    #[cfg(all(target_os = "linux", feature = "batch"))]
+    pub fn from(&self, i: usize) -> Option<SocketAddr> {
+        self.from[i]
+    }
+
+    /// Drop the datagrams which are not kept, moving the others to the front.
+    #[cfg(all(target_os = "linux", feature = "batch"))]
+    pub fn keep(&mut self, keep: &[bool; BATCH]) {
+        let mut count = 0;
+        for i in (0..self.count).filter(|&i| keep[i]) {
+            if i != count {
+                let start = i * RECV_CAPACITY;
+                self.buffer
+                    .copy_within(start..start + self.lens[i], count * RECV_CAPACITY);
+                self.lens[count] = self.lens[i];
+                self.from[count] = self.from[i];
+            }
+            count += 1;
+        }
+        self.count = count;
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "batch"))]
+pub mod sys {
+    use super::{RecvBatch, SendBatch, BATCH, RECV_CAPACITY};
+    use socket2::SockAddr;
+    use std::{io, mem, os::unix::io::AsRawFd, ptr};
+    use tokio::net::UdpSocket;
+
+    /// Largest GSO buffer accepted by the kernel.
+    const MAX_GSO_BYTES: usize = 65000;
+    /// Most segments in a GSO buffer accepted by the kernel.
+    const MAX_GSO_SEGMENTS: usize = 64;
+    const CONTROL_LEN: usize = 32;
+
+    /// Return if the kernel supports GSO for the socket.
+    pub fn gso_supported(socket: &UdpSocket) -> bool {
+        let mut value: libc::c_int = 0;
+        let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_UDP,
+                libc::UDP_SEGMENT,
+                &mut value as *mut _ as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        result == 0
+    }
+
+    /// Read as many datagrams as available without blocking, at least one, with their source
+    /// addresses.
+    pub fn recv(socket: &UdpSocket, batch: &mut RecvBatch) -> io::Result<usize> {
+        let mut iovecs: [libc::iovec; BATCH] = unsafe { mem::zeroed() };
+        let mut messages: [libc::mmsghdr; BATCH] = unsafe { mem::zeroed() };
+        let mut names: [libc::sockaddr_storage; BATCH] = unsafe { mem::zeroed() };
+        for (i, buffer) in batch.buffer.chunks_mut(RECV_CAPACITY).enumerate() {
+            iovecs[i].iov_base = buffer.as_mut_ptr() as *mut libc::c_void;
+            iovecs[i].iov_len = buffer.len();
+            messages[i].msg_hdr.msg_iov = &mut iovecs[i];
+            messages[i].msg_hdr.msg_iovlen = 1;
+            messages[i].msg_hdr.msg_name = &mut names[i] as *mut _ as *mut libc::c_void;
+            messages[i].msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+        }
+        let count = unsafe {
+            libc::recvmmsg(
+                socket.as_raw_fd(),
+                messages.as_mut_ptr(),
+                BATCH as _,
+                libc::MSG_DONTWAIT as _,
+                ptr::null_mut(),
+            )
+        };
+        if count < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let count = count as usize;
+        for (i, message) in messages[..count].iter().enumerate() {
+            batch.lens[i] = message.msg_len as usize;
+            let name = unsafe { SockAddr::new(names[i], message.msg_hdr.msg_namelen) };
+            batch.from[i] = name.as_socket();
+        }
+        batch.count = count;
+        Ok(count)
+    }
+
+    /// Write the datagrams from `from` without blocking, return the number of datagrams written.
+    /// `to` is required if the socket is not connected.
+    pub fn send(
+        socket: &UdpSocket,
+        batch: &SendBatch,
+        from: usize,
+        gso: bool,
+        to: Option<&SockAddr>,
+    ) -> io::Result<usize> {
+        let mut iovecs: [libc::iovec; BATCH] = unsafe { mem::zeroed() };
+        let mut messages: [libc::mmsghdr; BATCH] = unsafe { mem::zeroed() };
+        // aligned for `cmsghdr`
+        let mut controls = [[0u64; CONTROL_LEN / 8]; BATCH];
+        // number of datagrams in each message
+        let mut segments = [0usize; BATCH];
+        let mut count = 0;
+        let mut i = from;
+        while i < batch.len() && count < BATCH {
+            let datagram = batch.get(i);
+            let mut run = 1;
+            let mut len = datagram.len();
+            if gso {
+                // the last segment could be shorter
+                while i + run < batch.len()
+                    && run < MAX_GSO_SEGMENTS
+                    && len + batch.get(i + run).len() <= MAX_GSO_BYTES
+                    && batch.get(i + run - 1).len() == datagram.len()
+                    && batch.get(i + run).len() <= datagram.len()
+                {
+                    len += batch.get(i + run).len();
+                    run += 1;
+                }
+            }
+            iovecs[count].iov_base = datagram.as_ptr() as *mut libc::c_void;
+            iovecs[count].iov_len = len;
+            let header = &mut messages[count].msg_hdr;
+            header.msg_iov = &mut iovecs[count];
+            header.msg_iovlen = 1;
+            if let Some(to) = to {
+                header.msg_name = to.as_ptr() as *mut libc::c_void;
+                header.msg_namelen = to.len();
+            }
+            if run > 1 {
+                header.msg_control = controls[count].as_mut_ptr() as *mut libc::c_void;
+                header.msg_controllen =
+                    unsafe { libc::CMSG_SPACE(mem::size_of::<u16>() as _) } as _;
+                unsafe {
+                    let control = libc::CMSG_FIRSTHDR(header);
+                    (*control).cmsg_level = libc::SOL_UDP;
+                    (*control).cmsg_type = libc::UDP_SEGMENT;
+                    (*control).cmsg_len = libc::CMSG_LEN(mem::size_of::<u16>() as _) as _;
+                    ptr::write_unaligned(
+                        libc::CMSG_DATA(control) as *mut u16,
+                        datagram.len() as u16,
+                    );
+                }
+            }
+            segments[count] = run;
+            count += 1;
+            i += run;
+        }
+        let sent = unsafe {
+            libc::sendmmsg(
+                socket.as_raw_fd(),
+                messages.as_mut_ptr(),
+                count as _,
+                libc::MSG_DONTWAIT as _,
+            )
+        };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(segments[..sent as usize].iter().sum())
+    }
+}
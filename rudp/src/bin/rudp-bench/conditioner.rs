@@ -0,0 +1,75 @@
+//! Link conditioner adding delay, jitter and loss.
+use std::{cmp::Reverse, collections::BinaryHeap, io, net::SocketAddr};
+use tokio::{
+    net::UdpSocket,
+    select,
+    time::{sleep_until, Duration, Instant},
+};
+
+/// Release time, arrival order, destination and datagram.
+type Delayed = Reverse<(Instant, u64, SocketAddr, Vec<u8>)>;
+
+/// Link conditioner forwarding datagrams between a client and a server with delay, jitter and
+/// loss, in both directions.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Conditioner {
+    /// Added to every datagram.
+    pub delay: Duration,
+    /// Upper bound of the random delay added on top of `delay`, which could reorder datagrams.
+    pub jitter: Duration,
+    /// Percentage of the datagrams dropped, should be within 0..100.
+    pub loss_percentage: u64,
+}
+
+impl Conditioner {
+    /// Forward datagrams received on the socket to the server, and the ones from the server to
+    /// the last client seen. Runs until the socket fails.
+    pub async fn forward(self, socket: UdpSocket, server: SocketAddr) -> io::Result<()> {
+        let mut client = None;
+        let mut buffer = vec![0u8; 2048];
+        let mut pending: BinaryHeap<Delayed> = BinaryHeap::new();
+        let mut order = 0u64;
+        loop {
+            let release = match pending.peek() {
+                Some(Reverse((release, ..))) => *release,
+                None => Instant::now() + Duration::from_secs(3600),
+            };
+            select! {
+                result = socket.recv_from(&mut buffer) => {
+                    let (len, from) = result?;
+                    let to = if from == server {
+                        match client {
+                            Some(client) => client,
+                            None => continue,
+                        }
+                    } else {
+                        client = Some(from);
+                        server
+                    };
+                    if rand::random::<u64>() % 100 < self.loss_percentage {
+                        continue;
+                    }
+                    let delay = self.delay + self.jitter.mul_f64(rand::random::<f64>());
+                    if delay == Duration::from_millis(0) {
+                        // the timer would add a millisecond
+                        socket.send_to(&buffer[..len], to).await?;
+                        continue;
+                    }
+                    let datagram = buffer[..len].to_vec();
+                    pending.push(Reverse((Instant::now() + delay, order, to, datagram)));
+                    order += 1;
+                }
+                _ = sleep_until(release) => {
+                    let now = Instant::now();
+                    while let Some(Reverse((release, ..))) = pending.peek() {
+                        if *release > now {
+                            break;
+                        }
+                        let Reverse((_, _, to, datagram)) = pending.pop().unwrap();
+                        socket.send_to(&datagram, to).await?;
+                    }
+                }
+            }
+        }
+    }
+}
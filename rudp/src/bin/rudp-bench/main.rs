@@ -0,0 +1,259 @@
+//! Benchmark and soak test.
+//!
+//! Both endpoints run in this process, so the one-way latency is measured with a single clock.
+//! The client sends a mix of reliable and unreliable probes to the server at a fixed rate,
+//! optionally through a `Conditioner` adding delay, jitter and loss, and the run is summarized as
+//! text or as JSON for comparing runs. A long run with `--report` prints the progress and the
+//! memory use periodically, so leaks show up as a growing RSS.
+mod conditioner;
+mod options;
+mod report;
+
+use futures::StreamExt;
+use options::Options;
+use report::{progress, report, Class, Tally};
+use rudp::hand_shake::{client_handshake, server_accept};
+use rudp::{
+    start_udp_loop, BypassResult, Config, ConnectionStats, Delivery, DeserializeError, PacketDesc,
+    PacketSender,
+};
+use std::{
+    convert::TryInto,
+    env, io,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    net::UdpSocket,
+    time::{sleep, Duration, Instant},
+};
+
+const MAGIC: &[u8] = b"RUDPBENCH";
+/// Sequence number and send time.
+const PROBE_HEADER: usize = 16;
+/// Longest payload fitting into a datagram.
+const MAX_SIZE: usize = 1000;
+/// Reliable probes not delivered within this duration after the run are counted as lost.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+/// Sequence number of the probe finishing the handshake, which is not counted, as the server
+/// consumes the first datagram after the magic.
+const HANDSHAKE_PROBE: u64 = u64::MAX;
+
+/// Probe sent by the client, padded with zeros to the payload size.
+struct Probe {
+    reliable: bool,
+    seq: u64,
+    /// Microseconds from the start of the run.
+    sent: u64,
+    size: usize,
+}
+
+impl PacketDesc for Probe {
+    fn id(&self) -> u32 {
+        self.reliable as u32
+    }
+
+    fn serialize(&self, writer: &mut Vec<u8>) {
+        writer.extend_from_slice(&self.seq.to_be_bytes());
+        writer.extend_from_slice(&self.sent.to_be_bytes());
+        writer.resize(writer.len() + self.size - PROBE_HEADER, 0);
+    }
+
+    fn reliable(&self) -> bool {
+        self.reliable
+    }
+
+    fn ordered(_id: u32) -> bool {
+        false
+    }
+
+    fn deserialize(id: u32, data: &[u8]) -> Result<Self, DeserializeError> {
+        if id > 1 || data.len() < PROBE_HEADER {
+            return Err(DeserializeError("invalid probe".to_string()));
+        }
+        Ok(Probe {
+            reliable: id == 1,
+            seq: u64::from_be_bytes(data[..8].try_into().unwrap()),
+            sent: u64::from_be_bytes(data[8..16].try_into().unwrap()),
+            size: data.len(),
+        })
+    }
+}
+
+/// Connect the endpoints, through the conditioner if there is one.
+async fn connect(
+    options: &Options,
+    tally: Arc<Mutex<Tally>>,
+    epoch: Instant,
+) -> io::Result<(PacketSender<Probe>, Arc<ConnectionStats>)> {
+    let config = || Config {
+        slot_capacity: options.slot_capacity,
+        timeout: options.timeout,
+        fec: options.fec,
+        ..Default::default()
+    };
+    let server = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut remote = server.local_addr()?;
+    let server_config = config();
+    tokio::spawn(async move {
+        let socket = server_accept(server, MAGIC).await;
+        let (_send, mut recv, _) =
+            start_udp_loop::<Probe, _>(socket, server_config, BypassResult::ToUser);
+        while let Some(probe) = recv.next().await {
+            if probe.seq == HANDSHAKE_PROBE {
+                continue;
+            }
+            let now = Instant::now();
+            let latency = (now.duration_since(epoch).as_micros() as u64).saturating_sub(probe.sent);
+            let mut tally = tally.lock().unwrap();
+            tally.bytes_delivered += probe.size as u64;
+            tally.last_delivery = Some(now);
+            let class = tally.class(probe.reliable);
+            class.delivered += 1;
+            class.latency.record(latency);
+        }
+    });
+    if let Some(conditioner) = options.conditioner {
+        let proxy = UdpSocket::bind("127.0.0.1:0").await?;
+        let server = remote;
+        remote = proxy.local_addr()?;
+        tokio::spawn(async move {
+            if let Err(e) = conditioner.forward(proxy, server).await {
+                eprintln!("Conditioner stopped: {}", e);
+            }
+        });
+    }
+    let socket = UdpSocket::bind("127.0.0.1:0").await?;
+    socket.connect(remote).await?;
+    let socket = client_handshake(socket, MAGIC).await;
+    let (send, _recv, stats) = start_udp_loop::<Probe, _>(socket, config(), BypassResult::ToUser);
+    let probe = Probe {
+        reliable: true,
+        seq: HANDSHAKE_PROBE,
+        sent: 0,
+        size: PROBE_HEADER,
+    };
+    if send.send_with_receipt(probe, DRAIN_TIMEOUT).await != Delivery::Delivered {
+        return Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "the server did not answer",
+        ));
+    }
+    Ok((send, stats))
+}
+
+/// Send the probes for the duration of the run, then wait for the reliable ones.
+async fn send_probes(
+    options: &Options,
+    send: &PacketSender<Probe>,
+    tally: &Mutex<Tally>,
+    epoch: Instant,
+    start: Instant,
+) {
+    let (min, max) = options.size;
+    let mut seq = 0u64;
+    let mut next_report = options.report.map(|report| start + report);
+    loop {
+        let elapsed = start.elapsed();
+        if elapsed >= options.duration {
+            break;
+        }
+        let due = (elapsed.as_secs_f64() * options.rate as f64) as u64;
+        {
+            let mut tally = tally.lock().unwrap();
+            while seq < due {
+                let reliable = rand::random::<u64>() % 100 < options.reliable_percentage;
+                let size = min + rand::random::<usize>() % (max - min + 1);
+                let probe = Probe {
+                    reliable,
+                    seq,
+                    sent: epoch.elapsed().as_micros() as u64,
+                    size,
+                };
+                if send.unbounded_send(probe).is_err() {
+                    return;
+                }
+                tally.bytes_sent += size as u64;
+                tally.class(reliable).sent += 1;
+                seq += 1;
+            }
+            if let Some(report) = next_report.filter(|&report| report <= Instant::now()) {
+                progress(&tally, elapsed);
+                next_report = options.report.map(|interval| report + interval);
+            }
+        }
+        sleep(Duration::from_millis(1)).await;
+    }
+    // unreliable probes still in flight are given the longest delay of the conditioner
+    let grace = options
+        .conditioner
+        .map_or(Duration::from_millis(0), |c| c.delay + c.jitter)
+        + Duration::from_millis(100);
+    let drained = Instant::now() + DRAIN_TIMEOUT;
+    sleep(grace).await;
+    while Instant::now() < drained {
+        let delivered = {
+            let tally = tally.lock().unwrap();
+            tally.reliable.delivered >= tally.reliable.sent
+        };
+        if delivered {
+            break;
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+}
+
+fn usage() {
+    println!("Usage: [options]");
+    println!("--duration <seconds>     length of the run, 10 by default");
+    println!("--rate <packets/s>       probes sent per second, 1000 by default");
+    println!("--reliable <percentage>  share of reliable probes, 50 by default");
+    println!("--size <bytes|min-max>   payload size, 64 by default");
+    println!("--slots <count>          slot capacity of the sender");
+    println!("--timeout <ms>           retransmission timeout, 20 by default");
+    println!("--fec <off|group|adaptive>");
+    println!("--delay <ms>, --jitter <ms>, --loss <percentage>");
+    println!("                         pass the traffic through a link conditioner");
+    println!("--report <seconds>       print the progress and memory use periodically");
+    println!("--json                   print the result as JSON");
+}
+
+/// Run the benchmark and return the report.
+async fn bench(options: &Options) -> io::Result<String> {
+    let tally = Arc::new(Mutex::new(Tally {
+        reliable: Class::new(),
+        unreliable: Class::new(),
+        bytes_sent: 0,
+        bytes_delivered: 0,
+        last_delivery: None,
+    }));
+    // the send times of the probes are relative to this
+    let epoch = Instant::now();
+    let (send, stats) = connect(options, tally.clone(), epoch).await?;
+    let start = Instant::now();
+    send_probes(options, &send, &tally, epoch, start).await;
+    let tally = tally.lock().unwrap();
+    Ok(report(options, &tally, &stats, start))
+}
+
+/// Run with the arguments, excluding the program name.
+async fn run(args: &[String]) -> io::Result<()> {
+    if args.iter().any(|arg| arg == "--help") {
+        usage();
+        return Ok(());
+    }
+    let options = Options::parse(args)?;
+    print!("{}", bench(&options).await?);
+    if options.json {
+        println!();
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(e) = run(&args).await {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
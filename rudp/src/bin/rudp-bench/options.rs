@@ -0,0 +1,120 @@
+//! Command line options.
+use super::conditioner::Conditioner;
+use super::{MAX_SIZE, PROBE_HEADER};
+use rudp::{Config, Redundancy};
+use std::io;
+use tokio::time::Duration;
+
+/// Parameters of a run.
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub duration: Duration,
+    /// Probes sent per second.
+    pub rate: u64,
+    /// Percentage of the probes sent as reliable.
+    pub reliable_percentage: u64,
+    /// Payload sizes are picked uniformly from this range, inclusive.
+    pub size: (usize, usize),
+    pub slot_capacity: usize,
+    /// Retransmission timeout of the endpoints.
+    pub timeout: Duration,
+    pub fec: Redundancy,
+    /// Pass the traffic through a link conditioner.
+    pub conditioner: Option<Conditioner>,
+    /// Print the progress with this interval.
+    pub report: Option<Duration>,
+    pub json: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            duration: Duration::from_secs(10),
+            rate: 1000,
+            reliable_percentage: 50,
+            size: (64, 64),
+            slot_capacity: Config::default().slot_capacity,
+            timeout: Config::default().timeout,
+            fec: Redundancy::Off,
+            conditioner: None,
+            report: None,
+            json: false,
+        }
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn number<T: std::str::FromStr>(flag: &str, value: &str) -> io::Result<T> {
+    value
+        .parse()
+        .map_err(|_| invalid(format!("invalid value for {}: {}", flag, value)))
+}
+
+fn millis(flag: &str, value: &str) -> io::Result<Duration> {
+    Ok(Duration::from_millis(number(flag, value)?))
+}
+
+impl Options {
+    /// Parse the command line arguments, excluding the program name.
+    pub fn parse(args: &[String]) -> io::Result<Self> {
+        let mut options = Options::default();
+        let mut conditioner = Conditioner::default();
+        let mut conditioned = false;
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            if flag == "--json" {
+                options.json = true;
+                continue;
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| invalid(format!("missing value for {}", flag)))?;
+            match flag.as_str() {
+                "--duration" => options.duration = Duration::from_secs(number(flag, value)?),
+                "--rate" => options.rate = number(flag, value)?,
+                "--reliable" => options.reliable_percentage = number(flag, value)?,
+                "--size" => {
+                    options.size = match value.split_once('-') {
+                        Some((min, max)) => (number(flag, min)?, number(flag, max)?),
+                        None => (number(flag, value)?, number(flag, value)?),
+                    }
+                }
+                "--slots" => options.slot_capacity = number(flag, value)?,
+                "--timeout" => options.timeout = millis(flag, value)?,
+                "--fec" => {
+                    options.fec = match value.as_str() {
+                        "off" => Redundancy::Off,
+                        "adaptive" => Redundancy::Adaptive { max_group: 16 },
+                        group => Redundancy::Fixed(number(flag, group)?),
+                    }
+                }
+                "--delay" => conditioner.delay = millis(flag, value)?,
+                "--jitter" => conditioner.jitter = millis(flag, value)?,
+                "--loss" => conditioner.loss_percentage = number(flag, value)?,
+                "--report" => options.report = Some(Duration::from_secs(number(flag, value)?)),
+                _ => return Err(invalid(format!("unknown option {}", flag))),
+            }
+            conditioned |= matches!(flag.as_str(), "--delay" | "--jitter" | "--loss");
+        }
+        if conditioned {
+            options.conditioner = Some(conditioner);
+        }
+        let (min, max) = options.size;
+        if min < PROBE_HEADER || max > MAX_SIZE || min > max {
+            return Err(invalid(format!(
+                "sizes should be within {}-{}",
+                PROBE_HEADER, MAX_SIZE
+            )));
+        }
+        if options.reliable_percentage > 100 || conditioner.loss_percentage >= 100 {
+            return Err(invalid("percentages should be within 0..100".to_string()));
+        }
+        if options.rate == 0 || options.slot_capacity == 0 {
+            return Err(invalid("rate and slots should be positive".to_string()));
+        }
+        Ok(options)
+    }
+}
@@ -0,0 +1,217 @@
+//! Latency histograms and the report of a run.
+use super::options::Options;
+use rudp::{ConnectionStats, Redundancy};
+use std::{fmt::Write, fs};
+use tokio::time::{Duration, Instant};
+
+/// Resolution of the latency histogram, each power of two is split into this many buckets.
+const SUB_BUCKETS: u64 = 64;
+
+/// Log-linear histogram of microseconds, with a relative error below 1/`SUB_BUCKETS`.
+pub struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+    max: u64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Histogram {
+            counts: vec![0; (2 * SUB_BUCKETS + 64 * SUB_BUCKETS) as usize],
+            total: 0,
+            max: 0,
+        }
+    }
+
+    fn index(value: u64) -> usize {
+        if value < 2 * SUB_BUCKETS {
+            return value as usize;
+        }
+        // keep the highest 7 bits
+        let shift = 64 - value.leading_zeros() as u64 - 7;
+        (2 * SUB_BUCKETS + (shift - 1) * SUB_BUCKETS + (value >> shift) - SUB_BUCKETS) as usize
+    }
+
+    /// Return the lowest value of the bucket.
+    fn value(index: usize) -> u64 {
+        let index = index as u64;
+        if index < 2 * SUB_BUCKETS {
+            return index;
+        }
+        let shift = (index - 2 * SUB_BUCKETS) / SUB_BUCKETS + 1;
+        ((index - 2 * SUB_BUCKETS) % SUB_BUCKETS + SUB_BUCKETS) << shift
+    }
+
+    pub fn record(&mut self, value: u64) {
+        self.counts[Self::index(value)] += 1;
+        self.total += 1;
+        self.max = self.max.max(value);
+    }
+
+    fn percentile(&self, percentile: f64) -> u64 {
+        let rank = ((self.total as f64 * percentile / 100.0).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Self::value(index).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+/// Probes of one kind.
+pub struct Class {
+    pub sent: u64,
+    pub delivered: u64,
+    pub latency: Histogram,
+}
+
+impl Class {
+    pub fn new() -> Self {
+        Class {
+            sent: 0,
+            delivered: 0,
+            latency: Histogram::new(),
+        }
+    }
+
+    fn json(&self) -> String {
+        format!(
+            "{{\"sent\": {}, \"delivered\": {}, \"latency_us\": {{\"p50\": {}, \"p90\": {}, \"p99\": {}, \"p999\": {}, \"max\": {}}}}}",
+            self.sent,
+            self.delivered,
+            self.latency.percentile(50.0),
+            self.latency.percentile(90.0),
+            self.latency.percentile(99.0),
+            self.latency.percentile(99.9),
+            self.latency.max
+        )
+    }
+
+    fn text(&self, name: &str) -> String {
+        format!(
+            "{:<11} sent {:>8}  delivered {:>8}  p50 {:>8.3}ms  p90 {:>8.3}ms  p99 {:>8.3}ms  max {:>8.3}ms",
+            name,
+            self.sent,
+            self.delivered,
+            self.latency.percentile(50.0) as f64 / 1000.0,
+            self.latency.percentile(90.0) as f64 / 1000.0,
+            self.latency.percentile(99.0) as f64 / 1000.0,
+            self.latency.max as f64 / 1000.0
+        )
+    }
+}
+
+/// Counters of a run, shared by the sending and the receiving endpoint.
+pub struct Tally {
+    pub reliable: Class,
+    pub unreliable: Class,
+    pub bytes_sent: u64,
+    pub bytes_delivered: u64,
+    pub last_delivery: Option<Instant>,
+}
+
+impl Tally {
+    pub fn class(&mut self, reliable: bool) -> &mut Class {
+        if reliable {
+            &mut self.reliable
+        } else {
+            &mut self.unreliable
+        }
+    }
+}
+
+/// Read a field of `/proc/self/status` in bytes, `None` on other platforms.
+fn memory(field: &str) -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with(field))?;
+    let kib: u64 = line[field.len()..]
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kib * 1024)
+}
+
+fn json_option(value: Option<u64>) -> String {
+    value.map_or_else(|| "null".to_string(), |value| value.to_string())
+}
+
+pub fn progress(tally: &Tally, elapsed: Duration) {
+    eprintln!(
+        "{:>6.0}s  sent {:>9}  delivered {:>9}  rss {:>6} KiB",
+        elapsed.as_secs_f64(),
+        tally.reliable.sent + tally.unreliable.sent,
+        tally.reliable.delivered + tally.unreliable.delivered,
+        json_option(memory("VmRSS:").map(|rss| rss / 1024))
+    );
+}
+
+fn fec_name(fec: Redundancy) -> String {
+    match fec {
+        Redundancy::Off => "off".to_string(),
+        Redundancy::Fixed(group) => group.to_string(),
+        Redundancy::Adaptive { .. } => "adaptive".to_string(),
+    }
+}
+
+pub fn report(options: &Options, tally: &Tally, stats: &ConnectionStats, start: Instant) -> String {
+    let elapsed = tally.last_delivery.unwrap_or(start).duration_since(start);
+    let goodput = tally.bytes_delivered as f64 / elapsed.as_secs_f64().max(1e-9);
+    let retransmission_ratio = stats.retransmissions() as f64 / stats.reliable_sent().max(1) as f64;
+    // bytes on the wire for every byte of payload, including headers, ACKs and retransmissions
+    let overhead_ratio = stats.bytes_sent() as f64 / tally.bytes_sent.max(1) as f64;
+    let peak_rss = memory("VmHWM:");
+    let conditioner = options.conditioner.unwrap_or_default();
+    let mut result = String::new();
+    if options.json {
+        let _ = write!(
+            result,
+            "{{\"options\": {{\"duration_secs\": {}, \"rate\": {}, \"reliable_percentage\": {}, \
+             \"size_min\": {}, \"size_max\": {}, \"slots\": {}, \"timeout_ms\": {}, \"fec\": \"{}\", \
+             \"conditioned\": {}, \"delay_ms\": {}, \"jitter_ms\": {}, \"loss_percentage\": {}}}, \
+             \"elapsed_secs\": {:.3}, \"reliable\": {}, \"unreliable\": {}, \
+             \"goodput_bytes_per_sec\": {:.0}, \"datagrams_sent\": {}, \"bytes_sent\": {}, \
+             \"retransmissions\": {}, \"retransmission_ratio\": {:.4}, \"overhead_ratio\": {:.4}, \
+             \"peak_rss_bytes\": {}}}",
+            options.duration.as_secs(),
+            options.rate,
+            options.reliable_percentage,
+            options.size.0,
+            options.size.1,
+            options.slot_capacity,
+            options.timeout.as_millis(),
+            fec_name(options.fec),
+            options.conditioner.is_some(),
+            conditioner.delay.as_millis(),
+            conditioner.jitter.as_millis(),
+            conditioner.loss_percentage,
+            elapsed.as_secs_f64(),
+            tally.reliable.json(),
+            tally.unreliable.json(),
+            goodput,
+            stats.datagrams_sent(),
+            stats.bytes_sent(),
+            stats.retransmissions(),
+            retransmission_ratio,
+            overhead_ratio,
+            json_option(peak_rss)
+        );
+    } else {
+        let _ = writeln!(result, "{}", tally.reliable.text("reliable"));
+        let _ = writeln!(result, "{}", tally.unreliable.text("unreliable"));
+        let _ = writeln!(
+            result,
+            "goodput {:.1} KiB/s  retransmissions {} ({:.2}%)  overhead {:.2}x  peak rss {} KiB",
+            goodput / 1024.0,
+            stats.retransmissions(),
+            retransmission_ratio * 100.0,
+            overhead_ratio,
+            json_option(peak_rss.map(|rss| rss / 1024))
+        );
+    }
+    result
+}
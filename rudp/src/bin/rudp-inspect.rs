@@ -0,0 +1,3 @@
+fn main() {
+    rudp::inspect::main(rudp::inspect::Hex);
+}
@@ -0,0 +1,19 @@
+use std::env;
+
+#[tokio::main]
+async fn main() {
+    let env = env_logger::Env::default()
+        .filter_or("MY_LOG_LEVEL", "INFO")
+        .write_style_or("MY_LOG_STYLE", "always");
+    env_logger::init_from_env(env);
+
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 2 {
+        println!("Usage: <bind address with port>");
+        return;
+    }
+    if let Err(e) = rudp::relay::serve(&args[1]).await {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
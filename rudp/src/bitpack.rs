@@ -0,0 +1,209 @@
+//! Bit-packed encoding of quantized fields, for high-rate state packets.
+//!
+//! Fields marked with `#[quantize(...)]` in a `PacketDesc` derive are written with the given
+//! number of bits each, packed without padding, before the other fields of the variant which go
+//! through the codec:
+//! ```ignore
+//! #[derive(PacketDesc)]
+//! enum Packet {
+//!     #[packet(unreliable, ordered, state)]
+//!     Paddle {
+//!         #[quantize(min = 0.0, max = 100.0, bits = 12)]
+//!         position: f32,
+//!         #[quantize(min = -3.1416, max = 3.1416, bits = 10)]
+//!         rotation: f32,
+//!     },
+//! }
+//! ```
+//! Floats are clamped to `min..=max` and rounded to the closest of `2^bits` evenly spaced
+//! levels, so the error is at most `Quantization::precision`. Integers take `bits`, and
+//! optionally `min` and `max`, and are stored exactly as the offset from `min`, clamped to
+//! `min..=max` where `max` defaults to `min + 2^bits - 1`. Arrays are quantized element by
+//! element. A value takes at most 64 bits.
+use super::protocol::DeserializeError;
+use std::convert::TryFrom;
+
+/// How a field is quantized, from the `#[quantize(...)]` attribute.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Quantization {
+    pub min: f64,
+    pub max: f64,
+    pub bits: u32,
+}
+
+impl Quantization {
+    /// Distance between two consecutive levels.
+    pub fn step(&self) -> f64 {
+        (self.max - self.min) / self.levels()
+    }
+
+    /// Largest error of a value within `min..=max`, half a step.
+    pub fn precision(&self) -> f64 {
+        self.step() / 2.0
+    }
+
+    fn levels(&self) -> f64 {
+        (mask(self.bits) as f64).max(1.0)
+    }
+}
+
+fn mask(bits: u32) -> u64 {
+    if bits >= 64 {
+        u64::MAX
+    } else {
+        (1 << bits) - 1
+    }
+}
+
+/// Append values of any width up to 64 bits, least significant bit first.
+pub struct BitWriter<'a> {
+    writer: &'a mut Vec<u8>,
+    pending: u128,
+    len: u32,
+}
+
+impl<'a> BitWriter<'a> {
+    pub fn new(writer: &'a mut Vec<u8>) -> Self {
+        BitWriter {
+            writer,
+            pending: 0,
+            len: 0,
+        }
+    }
+
+    /// Write the lowest `bits` bits of the value.
+    pub fn write(&mut self, value: u64, bits: u32) {
+        self.pending |= ((value & mask(bits)) as u128) << self.len;
+        self.len += bits;
+        while self.len >= 8 {
+            self.writer.push(self.pending as u8);
+            self.pending >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    /// Write the last partial byte, padded with zeros.
+    pub fn finish(self) {
+        if self.len > 0 {
+            self.writer.push(self.pending as u8);
+        }
+    }
+}
+
+/// Read the values written by `BitWriter`.
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    /// Position in bits.
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        BitReader { data, position: 0 }
+    }
+
+    pub fn read(&mut self, bits: u32) -> Result<u64, DeserializeError> {
+        if self.position + bits as usize > self.data.len() * 8 {
+            return Err(DeserializeError(
+                "Data shorter than the bit-packed fields.".to_string(),
+            ));
+        }
+        let mut value = 0u128;
+        let mut read = 0;
+        while read < bits {
+            let byte = self.data[self.position / 8] as u128;
+            let offset = (self.position % 8) as u32;
+            let take = (8 - offset).min(bits - read);
+            value |= ((byte >> offset) & ((1 << take) - 1)) << read;
+            read += take;
+            self.position += take as usize;
+        }
+        Ok(value as u64)
+    }
+
+    /// The bytes after the last partial byte read.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.data[self.position.div_ceil(8)..]
+    }
+}
+
+/// Types which could be marked with `#[quantize(...)]`.
+pub trait Quantize: Sized {
+    fn quantize(&self, quantization: &Quantization, writer: &mut BitWriter);
+    fn dequantize(
+        quantization: &Quantization,
+        reader: &mut BitReader,
+    ) -> Result<Self, DeserializeError>;
+}
+
+macro_rules! quantize_float {
+    ($($t:ty),*) => {
+        $(
+            impl Quantize for $t {
+                fn quantize(&self, quantization: &Quantization, writer: &mut BitWriter) {
+                    // NaN is written as `min`
+                    let value = (*self as f64).max(quantization.min).min(quantization.max);
+                    let level = ((value - quantization.min) / quantization.step()).round();
+                    writer.write(level as u64, quantization.bits);
+                }
+
+                fn dequantize(
+                    quantization: &Quantization,
+                    reader: &mut BitReader,
+                ) -> Result<Self, DeserializeError> {
+                    let level = reader.read(quantization.bits)?;
+                    let value = quantization.min + level as f64 * quantization.step();
+                    Ok(value.min(quantization.max) as $t)
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! quantize_integer {
+    ($($t:ty),*) => {
+        $(
+            impl Quantize for $t {
+                fn quantize(&self, quantization: &Quantization, writer: &mut BitWriter) {
+                    let min = quantization.min as i128;
+                    let max = min
+                        .saturating_add(mask(quantization.bits) as i128)
+                        .min(quantization.max as i128);
+                    // saturating, as the largest u128 are out of range for i128
+                    let value = i128::try_from(*self).unwrap_or(i128::MAX).max(min).min(max);
+                    writer.write((value - min) as u64, quantization.bits);
+                }
+
+                fn dequantize(
+                    quantization: &Quantization,
+                    reader: &mut BitReader,
+                ) -> Result<Self, DeserializeError> {
+                    let offset = reader.read(quantization.bits)?;
+                    Ok((quantization.min as i128 + offset as i128) as $t)
+                }
+            }
+        )*
+    };
+}
+
+quantize_float!(f32, f64);
+quantize_integer!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+impl<T: Quantize + Copy + Default, const N: usize> Quantize for [T; N] {
+    fn quantize(&self, quantization: &Quantization, writer: &mut BitWriter) {
+        for value in self.iter() {
+            value.quantize(quantization, writer);
+        }
+    }
+
+    fn dequantize(
+        quantization: &Quantization,
+        reader: &mut BitReader,
+    ) -> Result<Self, DeserializeError> {
+        let mut values = [T::default(); N];
+        for value in values.iter_mut() {
+            *value = T::dequantize(quantization, reader)?;
+        }
+        Ok(values)
+    }
+}
@@ -0,0 +1,256 @@
+//! Traffic capture and offline replay.
+//!
+//! Captures are written in the pcap format with the `USER0` link type, so they could be opened by
+//! the usual tools. Each record contains one byte for the direction (0 for sent, 1 for received)
+//! followed by the raw datagram, which starts with the `PacketHeader`. Timestamps are monotonic,
+//! measured from the creation of the capture instead of the wall clock.
+use super::protocol::{DeserializeError, PacketDesc, PacketHeader};
+use super::receiver::Receiver;
+use std::{
+    convert::TryInto,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+const SNAPLEN: u32 = 65535;
+/// LINKTYPE_USER0, reserved for private use.
+const LINKTYPE: u32 = 147;
+const GLOBAL_HEADER_LEN: usize = 24;
+const RECORD_HEADER_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+impl Direction {
+    fn to_byte(self) -> u8 {
+        match self {
+            Direction::Sent => 0,
+            Direction::Received => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Direction::Sent),
+            1 => Some(Direction::Received),
+            _ => None,
+        }
+    }
+}
+
+/// Record datagrams into a pcap file. Shared by the sender and receiver task of a UDP loop.
+pub struct CaptureWriter {
+    start: Instant,
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl CaptureWriter {
+    /// Create a capture file at the path, truncating the file if it exists.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+
+    /// Write the capture into a writer, the pcap global header is written immediately.
+    pub fn new<W: Write + Send + 'static>(mut writer: W) -> io::Result<Self> {
+        let mut header = Vec::with_capacity(GLOBAL_HEADER_LEN);
+        header.extend(PCAP_MAGIC.to_le_bytes().iter());
+        header.extend(PCAP_VERSION_MAJOR.to_le_bytes().iter());
+        header.extend(PCAP_VERSION_MINOR.to_le_bytes().iter());
+        // timezone offset and timestamp accuracy
+        header.extend(0i32.to_le_bytes().iter());
+        header.extend(0u32.to_le_bytes().iter());
+        header.extend(SNAPLEN.to_le_bytes().iter());
+        header.extend(LINKTYPE.to_le_bytes().iter());
+        writer.write_all(&header)?;
+        Ok(CaptureWriter {
+            start: Instant::now(),
+            writer: Mutex::new(Box::new(writer)),
+        })
+    }
+
+    /// Record a datagram. IO errors are ignored as capture is only for debugging.
+    pub fn record(&self, direction: Direction, datagram: &[u8]) {
+        let time = self.start.elapsed();
+        let len = datagram.len() as u32 + 1;
+        let mut record = Vec::with_capacity(RECORD_HEADER_LEN + len as usize);
+        record.extend((time.as_secs() as u32).to_le_bytes().iter());
+        record.extend(time.subsec_micros().to_le_bytes().iter());
+        record.extend(len.to_le_bytes().iter());
+        record.extend(len.to_le_bytes().iter());
+        record.push(direction.to_byte());
+        record.extend_from_slice(datagram);
+        if let Ok(mut writer) = self.writer.lock() {
+            let _ = writer.write_all(&record);
+        }
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        match self.writer.lock() {
+            Ok(mut writer) => writer.flush(),
+            Err(_) => Err(io::Error::other("capture lock poisoned")),
+        }
+    }
+}
+
+impl Drop for CaptureWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// A datagram read from a capture.
+#[derive(Debug, Clone)]
+pub struct CaptureRecord {
+    /// Time since the capture started.
+    pub time: Duration,
+    pub direction: Direction,
+    /// The raw datagram, including the header.
+    pub data: Vec<u8>,
+}
+
+impl CaptureRecord {
+    /// Decode the header, return the header and the payload.
+    pub fn header(&self) -> Result<(PacketHeader, &[u8]), DeserializeError> {
+        PacketHeader::deserialize(&self.data)
+    }
+}
+
+/// Read records from a pcap file written by `CaptureWriter`.
+pub struct CaptureReader<R: Read> {
+    inner: R,
+    big_endian: bool,
+}
+
+impl CaptureReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+impl<R: Read> CaptureReader<R> {
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut header = [0u8; GLOBAL_HEADER_LEN];
+        inner.read_exact(&mut header)?;
+        let magic: [u8; 4] = header[..4].try_into().unwrap();
+        let big_endian = if u32::from_le_bytes(magic) == PCAP_MAGIC {
+            false
+        } else if u32::from_be_bytes(magic) == PCAP_MAGIC {
+            true
+        } else {
+            return Err(invalid_data("Not a pcap file."));
+        };
+        let reader = CaptureReader { inner, big_endian };
+        if reader.u32_at(&header, 20) != LINKTYPE {
+            return Err(invalid_data("Not a rudp capture."));
+        }
+        Ok(reader)
+    }
+
+    fn u32_at(&self, data: &[u8], offset: usize) -> u32 {
+        let bytes: [u8; 4] = data[offset..offset + 4].try_into().unwrap();
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    /// Read the next record, return `None` at the end of the capture.
+    pub fn next_record(&mut self) -> io::Result<Option<CaptureRecord>> {
+        let mut header = [0u8; RECORD_HEADER_LEN];
+        match self.inner.read_exact(&mut header) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let secs = self.u32_at(&header, 0);
+        let micros = self.u32_at(&header, 4);
+        let len = self.u32_at(&header, 8) as usize;
+        if len == 0 || len > SNAPLEN as usize {
+            return Err(invalid_data("Invalid record length."));
+        }
+        let mut data = vec![0u8; len];
+        self.inner.read_exact(&mut data)?;
+        let direction =
+            Direction::from_byte(data[0]).ok_or_else(|| invalid_data("Invalid direction."))?;
+        data.remove(0);
+        Ok(Some(CaptureRecord {
+            time: Duration::new(secs as u64, micros * 1000),
+            direction,
+            data,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = io::Result<CaptureRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+/// Feed recorded datagrams into a fresh `Receiver`, reproducing what the application received.
+/// Only received datagrams are used, so the receiver state evolves exactly as it did in the
+/// recorded session. Datagrams discarded by the simulated packet drop are never recorded.
+pub struct Replay {
+    receiver: Receiver,
+}
+
+impl Replay {
+    /// `slot_capacity` should be the same as the one used by the remote sender.
+    pub fn new(slot_capacity: usize) -> Self {
+        Replay {
+            receiver: Receiver::detached(slot_capacity),
+        }
+    }
+
+    /// Feed a record, return the packets the application would receive. Besides the packet of
+    /// the record, this includes the one rebuilt from it by FEC.
+    pub fn feed<T: PacketDesc>(&mut self, record: &CaptureRecord) -> Vec<T> {
+        let mut delivered = Vec::new();
+        if record.direction != Direction::Received {
+            return delivered;
+        }
+        let (header, data) = match record.header() {
+            Ok(header) => header,
+            Err(_) => return delivered,
+        };
+        self.receiver
+            .handle_datagram(&header, &record.data, data, |receiver, p, data| {
+                delivered.extend(receiver.handle_packet(p, data));
+                true
+            });
+        delivered
+    }
+}
+
+/// Replay a whole capture, return the packets delivered to the application with the time they
+/// were received.
+pub fn replay<T: PacketDesc, R: Read>(
+    reader: CaptureReader<R>,
+    slot_capacity: usize,
+) -> io::Result<Vec<(Duration, T)>> {
+    let mut replay = Replay::new(slot_capacity);
+    let mut delivered = Vec::new();
+    for record in reader {
+        let record = record?;
+        for packet in replay.feed(&record) {
+            delivered.push((record.time, packet));
+        }
+    }
+    Ok(delivered)
+}
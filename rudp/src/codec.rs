@@ -0,0 +1,81 @@
+//! Serialization formats of the packets derived with `PacketDesc`.
+//!
+//! The derive serializes with CBOR unless the enum chooses another codec:
+//! ```ignore
+//! #[derive(PacketDesc)]
+//! #[packet(codec = "bincode")]
+//! enum Packet { ... }
+//! ```
+//! The built-in codecs are `"cbor"`, `"bincode"` (feature `bincode`) and `"postcard"` (feature
+//! `postcard`). Any other value is the path of a type implementing `Codec`, for example
+//! `#[packet(codec = "crate::MyCodec")]`. The generated code reaches the codecs through this
+//! module, so the crate deriving `PacketDesc` only depends on `serde` for the fields.
+//!
+//! Only the fields of the variant are serialized: nothing for a unit variant, the field itself
+//! for a variant with a single field, and a tuple of the fields otherwise. The variant is told by
+//! the packet ID in the header.
+use super::protocol::DeserializeError;
+use serde::{de::DeserializeOwned, Serialize};
+
+pub use serde_cbor;
+
+#[cfg(feature = "bincode")]
+pub use bincode;
+
+#[cfg(feature = "postcard")]
+pub use postcard;
+
+/// A serialization format for the packets.
+pub trait Codec {
+    /// Append the serialized value to the writer, which already contains the packet header.
+    /// Panics if the value could not be serialized.
+    fn serialize<T: Serialize>(value: &T, writer: &mut Vec<u8>);
+    /// Deserialize the value from the whole payload.
+    fn deserialize<T: DeserializeOwned>(data: &[u8]) -> Result<T, DeserializeError>;
+}
+
+/// CBOR with `serde_cbor`, the default of the derive.
+pub struct Cbor;
+
+impl Codec for Cbor {
+    fn serialize<T: Serialize>(value: &T, writer: &mut Vec<u8>) {
+        serde_cbor::to_writer(writer, value).unwrap();
+    }
+
+    fn deserialize<T: DeserializeOwned>(data: &[u8]) -> Result<T, DeserializeError> {
+        serde_cbor::from_slice(data)
+            .map_err(|err| DeserializeError(format!("serde_cbor error: {:?}", err)))
+    }
+}
+
+/// Bincode with the default options, smaller than CBOR as the field names are not sent.
+#[cfg(feature = "bincode")]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+    fn serialize<T: Serialize>(value: &T, writer: &mut Vec<u8>) {
+        bincode::serialize_into(writer, value).unwrap();
+    }
+
+    fn deserialize<T: DeserializeOwned>(data: &[u8]) -> Result<T, DeserializeError> {
+        bincode::deserialize(data)
+            .map_err(|err| DeserializeError(format!("bincode error: {:?}", err)))
+    }
+}
+
+/// Postcard, with variable length integers, usually the most compact of the built-in codecs.
+#[cfg(feature = "postcard")]
+pub struct Postcard;
+
+#[cfg(feature = "postcard")]
+impl Codec for Postcard {
+    fn serialize<T: Serialize>(value: &T, writer: &mut Vec<u8>) {
+        *writer = postcard::to_extend(value, std::mem::take(writer)).unwrap();
+    }
+
+    fn deserialize<T: DeserializeOwned>(data: &[u8]) -> Result<T, DeserializeError> {
+        postcard::from_bytes(data)
+            .map_err(|err| DeserializeError(format!("postcard error: {:?}", err)))
+    }
+}
@@ -0,0 +1,228 @@
+use super::protocol::PacketDesc;
+use super::stream::{StreamReader, StreamWriter};
+use futures::{
+    channel::{
+        mpsc::UnboundedReceiver,
+        oneshot::{self, Canceled},
+    },
+    lock::Mutex,
+    stream::StreamExt,
+};
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+use tokio::{
+    sync::mpsc::UnboundedSender,
+    time::{Duration, Instant},
+};
+
+/// Outcome of a send with receipt.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Delivery {
+    /// The remote acknowledged the packet.
+    Delivered,
+    /// No ACK was received before the expiry, and the packet would not be resent anymore. The
+    /// remote may still have received it if the ACK was lost. Unreliable packets are never
+    /// acknowledged, so their receipts resolve to this once they are sent.
+    Expired,
+    /// The UDP loop stopped before the outcome was known.
+    ConnectionClosed,
+}
+
+/// Resolves to the `Delivery` of a packet sent with `PacketSender::send_with_receipt`. It could
+/// be awaited, or polled with `try_delivery` from a synchronous game loop.
+pub struct Receipt(oneshot::Receiver<Delivery>);
+
+impl Receipt {
+    /// Return the outcome if it is known already.
+    pub fn try_delivery(&mut self) -> Option<Delivery> {
+        match self.0.try_recv() {
+            Ok(delivery) => delivery,
+            Err(Canceled) => Some(Delivery::ConnectionClosed),
+        }
+    }
+}
+
+impl Future for Receipt {
+    type Output = Delivery;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Delivery> {
+        Pin::new(&mut self.0)
+            .poll(cx)
+            .map(|result| result.unwrap_or(Delivery::ConnectionClosed))
+    }
+}
+
+/// Tracks a packet in the sender until its outcome is known.
+pub(crate) struct Tracker {
+    pub deadline: Instant,
+    result: oneshot::Sender<Delivery>,
+}
+
+impl Tracker {
+    pub fn resolve(self, delivery: Delivery) {
+        // the application may not be interested anymore
+        let _ = self.result.send(delivery);
+    }
+}
+
+/// Either a packet of the application, or a message of the protocol with an ID reserved by the
+/// protocol, which is always reliable.
+pub(crate) enum Message<T> {
+    Packet(T),
+    Raw { id: u32, data: Vec<u8> },
+}
+
+impl<T: PacketDesc> Message<T> {
+    pub fn id(&self) -> u32 {
+        match self {
+            Message::Packet(packet) => packet.id(),
+            Message::Raw { id, .. } => *id,
+        }
+    }
+
+    pub fn reliable(&self) -> bool {
+        match self {
+            Message::Packet(packet) => packet.reliable(),
+            Message::Raw { .. } => true,
+        }
+    }
+
+    pub fn state(&self) -> bool {
+        match self {
+            Message::Packet(packet) => T::state(packet.id()),
+            Message::Raw { .. } => false,
+        }
+    }
+
+    /// Return if the packet is marked for compression.
+    pub fn compress(&self) -> bool {
+        match self {
+            Message::Packet(packet) => T::compress(packet.id()),
+            Message::Raw { .. } => false,
+        }
+    }
+
+    pub fn serialize(&self, writer: &mut Vec<u8>) {
+        match self {
+            Message::Packet(packet) => packet.serialize(writer),
+            Message::Raw { data, .. } => writer.extend_from_slice(data),
+        }
+    }
+}
+
+pub(crate) struct Outgoing<T> {
+    pub message: Message<T>,
+    pub tracker: Option<Tracker>,
+}
+
+impl<T> Outgoing<T> {
+    /// The packet would not be acknowledged, because it is unreliable or was replaced.
+    pub fn expire(self) {
+        if let Some(tracker) = self.tracker {
+            tracker.resolve(Delivery::Expired);
+        }
+    }
+}
+
+/// The packet could not be sent as the UDP loop stopped.
+pub struct Closed<T>(pub T);
+
+impl<T> fmt::Debug for Closed<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Closed(..)")
+    }
+}
+
+impl<T> fmt::Display for Closed<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the connection is closed")
+    }
+}
+
+/// Handle for sending packets into the UDP loop, which could be cloned freely.
+pub struct PacketSender<T> {
+    inner: UnboundedSender<Outgoing<T>>,
+    next_stream: Arc<AtomicU32>,
+    incoming_streams: Arc<Mutex<UnboundedReceiver<StreamReader>>>,
+}
+
+impl<T> Clone for PacketSender<T> {
+    fn clone(&self) -> Self {
+        PacketSender {
+            inner: self.inner.clone(),
+            next_stream: self.next_stream.clone(),
+            incoming_streams: self.incoming_streams.clone(),
+        }
+    }
+}
+
+impl<T> PacketSender<T> {
+    pub(crate) fn new(
+        inner: UnboundedSender<Outgoing<T>>,
+        incoming_streams: UnboundedReceiver<StreamReader>,
+    ) -> Self {
+        PacketSender {
+            inner,
+            next_stream: Arc::new(AtomicU32::new(0)),
+            incoming_streams: Arc::new(Mutex::new(incoming_streams)),
+        }
+    }
+
+    /// Send the packet without tracking it, never blocks.
+    pub fn unbounded_send(&self, packet: T) -> Result<(), Closed<T>> {
+        self.inner
+            .send(Outgoing {
+                message: Message::Packet(packet),
+                tracker: None,
+            })
+            .map_err(|e| match e.0.message {
+                Message::Packet(packet) => Closed(packet),
+                Message::Raw { .. } => unreachable!(),
+            })
+    }
+
+    /// Send the packet and return a receipt for it. A reliable packet which is not acknowledged
+    /// within `expiry`, including the time waiting for a free slot, is given up and its receipt
+    /// resolves to `Delivery::Expired`.
+    pub fn send_with_receipt(&self, packet: T, expiry: Duration) -> Receipt {
+        self.send_message(Message::Packet(packet), expiry)
+    }
+
+    pub(crate) fn send_message(&self, message: Message<T>, expiry: Duration) -> Receipt {
+        let (result, receipt) = oneshot::channel();
+        let tracker = Tracker {
+            deadline: Instant::now() + expiry,
+            result,
+        };
+        // if the loop stopped, the tracker is dropped and the receipt resolves to
+        // `ConnectionClosed`
+        let _ = self.inner.send(Outgoing {
+            message,
+            tracker: Some(tracker),
+        });
+        Receipt(receipt)
+    }
+
+    /// Open a stream for sending bytes larger than a datagram, see `stream`.
+    pub fn open_stream(&self) -> StreamWriter<T> {
+        let id = self.next_stream.fetch_add(1, Ordering::Relaxed);
+        StreamWriter::new(id, self.clone())
+    }
+
+    /// Wait for the next stream opened by the remote. Returns `None` if the loop stopped.
+    pub async fn accept_stream(&self) -> Option<StreamReader> {
+        self.incoming_streams.lock().await.next().await
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+    }
+}
@@ -0,0 +1,340 @@
+//! Forward error correction with XOR parity, for links losing too many packets to wait for the
+//! retransmission timeout.
+//!
+//! The datagrams sent for the first time, reliable or not, have consecutive generations, so they
+//! are grouped by generation without changing their format. After every group of datagrams, the
+//! sender sends a parity datagram with the `PARITY` flag, where the ID is the number of datagrams
+//! in the group and the generation is the first generation of the group. Its payload is the XOR
+//! of the datagram lengths (u16, big endian), followed by the XOR of the datagrams padded with
+//! zeros to the longest one. If exactly one datagram of a group is lost, the receiver rebuilds it
+//! from the parity and the rest of the group, and handles it as if it was received, so a lost
+//! reliable packet is acknowledged without being resent.
+//!
+//! A group which is not complete within `FLUSH_DELAY` is closed early, so that sparse packets are
+//! protected as well. Retransmissions and ACKs are not protected. The receiver starts keeping
+//! datagrams for recovery when the first parity datagram arrives, so nothing is needed on the
+//! receiving side.
+use super::protocol::{PacketHeader, HEADER_LEN, PARITY, RECV_CAPACITY};
+use super::stats::ConnectionStats;
+use tokio::time::{Duration, Instant};
+
+/// A group which is not complete within this duration is closed with fewer datagrams.
+const FLUSH_DELAY: Duration = Duration::from_millis(5);
+/// Longer datagrams are not protected, as the parity would not fit into the receive buffer.
+const MAX_PROTECTED: usize = RECV_CAPACITY - HEADER_LEN - 2;
+/// The retransmission rate is measured over at least this interval.
+const ADAPT_INTERVAL: Duration = Duration::from_secs(1);
+/// Fewer reliable packets are not enough for measuring the retransmission rate.
+const MIN_SAMPLES: u64 = 20;
+/// Larger groups are not accepted by the receiver.
+const MAX_GROUP: usize = 64;
+/// Number of recent generations kept by the receiver.
+const WINDOW: usize = 4 * MAX_GROUP;
+
+/// How much parity the sender adds, see `Config::fec`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Redundancy {
+    #[default]
+    Off,
+    /// One parity datagram for every group of this many datagrams, so one datagram in every
+    /// group could be lost. Smaller groups recover more losses but use more bandwidth.
+    Fixed(usize),
+    /// Pick the group size from the retransmission rate of the connection, sending no parity
+    /// while the rate is below `1 / (4 * max_group)`.
+    Adaptive { max_group: usize },
+}
+
+/// Builds the parity of the datagrams sent by the sender.
+pub(crate) struct Encoder {
+    redundancy: Redundancy,
+    /// Current group size, 0 if no parity is sent.
+    group: usize,
+    first: i64,
+    count: usize,
+    started: Instant,
+    lengths: u16,
+    parity: Vec<u8>,
+    measured_at: Instant,
+    sent_base: u64,
+    resent_base: u64,
+}
+
+impl Encoder {
+    pub fn new(redundancy: Redundancy) -> Self {
+        let group = match redundancy {
+            Redundancy::Fixed(group) => group.clamp(1, MAX_GROUP),
+            _ => 0,
+        };
+        let now = Instant::now();
+        Encoder {
+            redundancy,
+            group,
+            first: 0,
+            count: 0,
+            started: now,
+            lengths: 0,
+            parity: Vec::new(),
+            measured_at: now,
+            sent_base: 0,
+            resent_base: 0,
+        }
+    }
+
+    /// Add a datagram sent for the first time, and write the parity datagram into `parity` if
+    /// it completed the group. Returns if the parity was written.
+    pub fn add(&mut self, datagram: &[u8], stats: &ConnectionStats, parity: &mut Vec<u8>) -> bool {
+        let now = Instant::now();
+        self.adapt(stats, now);
+        if self.group == 0 {
+            self.count = 0;
+            return false;
+        }
+        if datagram.len() > MAX_PROTECTED {
+            return self.flush(parity);
+        }
+        let generation = match PacketHeader::deserialize(datagram) {
+            Ok((p, _)) => p.generation,
+            Err(_) => return false,
+        };
+        if self.count > 0 && generation != self.first + self.count as i64 {
+            // the receiver could not tell which datagrams the parity covers
+            self.count = 0;
+        }
+        if self.count == 0 {
+            self.first = generation;
+            self.started = now;
+            self.lengths = 0;
+            self.parity.clear();
+        }
+        self.lengths ^= datagram.len() as u16;
+        if self.parity.len() < datagram.len() {
+            self.parity.resize(datagram.len(), 0);
+        }
+        for (p, d) in self.parity.iter_mut().zip(datagram) {
+            *p ^= d;
+        }
+        self.count += 1;
+        self.count >= self.group && self.flush(parity)
+    }
+
+    /// Return when the current group should be closed by `flush`.
+    pub fn deadline(&self) -> Option<Instant> {
+        if self.count > 0 {
+            Some(self.started + FLUSH_DELAY)
+        } else {
+            None
+        }
+    }
+
+    /// Close the current group, and write its parity datagram into `parity` unless it is empty.
+    /// Returns if the parity was written.
+    pub fn flush(&mut self, parity: &mut Vec<u8>) -> bool {
+        if self.count == 0 {
+            return false;
+        }
+        parity.clear();
+        let mut header = PacketHeader::new(self.count as u32, 0, self.first);
+        header.flags |= PARITY;
+        header.serialize(parity);
+        parity.extend(self.lengths.to_be_bytes().iter());
+        parity.extend_from_slice(&self.parity);
+        self.count = 0;
+        true
+    }
+
+    fn adapt(&mut self, stats: &ConnectionStats, now: Instant) {
+        let max_group = match self.redundancy {
+            Redundancy::Adaptive { max_group } => max_group.clamp(2, MAX_GROUP),
+            _ => return,
+        };
+        let sent = stats.reliable_sent() - self.sent_base;
+        if now < self.measured_at + ADAPT_INTERVAL || sent < MIN_SAMPLES {
+            return;
+        }
+        let resent = stats.retransmissions() - self.resent_base;
+        self.measured_at = now;
+        self.sent_base = stats.reliable_sent();
+        self.resent_base = stats.retransmissions();
+        // one loss in a group of this size is rare enough for most losses to be recovered
+        let target = if resent > 0 {
+            (sent / (4 * resent)) as usize
+        } else {
+            usize::MAX
+        };
+        let target = if target > max_group { 0 } else { target.max(2) };
+        self.group = match (self.group, target) {
+            (group, 0) if group == 0 || group >= max_group => 0,
+            // less parity one step at a time, as the parity itself lowers the rate
+            (group, target) if group != 0 && (target == 0 || target > group) => group + 1,
+            (_, target) => target,
+        };
+    }
+}
+
+struct Kept {
+    generation: i64,
+    received: bool,
+    /// Rebuilt from the parity, so the original is a duplicate if it arrives late.
+    rebuilt: bool,
+    /// First generation of the group covering it, once the parity arrived.
+    group: Option<i64>,
+    datagram: Vec<u8>,
+}
+
+struct Parity {
+    first: i64,
+    /// 0 once the group is recovered or complete.
+    count: i64,
+    payload: Vec<u8>,
+}
+
+fn index(generation: i64) -> usize {
+    generation.rem_euclid(WINDOW as i64) as usize
+}
+
+/// Keeps the recent datagrams of the remote for rebuilding a lost one from the parity. Both are
+/// kept in rings indexed by generation, so the buffers are reused.
+pub(crate) struct Decoder {
+    kept: Vec<Kept>,
+    // indexed by the first generation of the group
+    parity: Vec<Parity>,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder {
+            kept: (0..WINDOW)
+                .map(|_| Kept {
+                    generation: -1,
+                    received: false,
+                    rebuilt: false,
+                    group: None,
+                    datagram: Vec::new(),
+                })
+                .collect(),
+            parity: (0..WINDOW)
+                .map(|_| Parity {
+                    first: -1,
+                    count: 0,
+                    payload: Vec::new(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Decoder {
+    /// Return the entry of the generation, unless it is older than the window.
+    fn kept(&mut self, generation: i64) -> Option<&mut Kept> {
+        let kept = &mut self.kept[index(generation)];
+        if kept.generation > generation {
+            return None;
+        }
+        if kept.generation < generation {
+            kept.generation = generation;
+            kept.received = false;
+            kept.rebuilt = false;
+            kept.group = None;
+        }
+        Some(kept)
+    }
+
+    /// If the datagram of the generation was rebuilt from the parity.
+    pub fn rebuilt(&self, generation: i64) -> bool {
+        let kept = &self.kept[index(generation)];
+        kept.generation == generation && kept.rebuilt
+    }
+
+    /// Keep a datagram of the remote which is not an ACK. If it allows rebuilding a lost one,
+    /// write that into `recovered` and return true.
+    pub fn on_datagram(
+        &mut self,
+        generation: i64,
+        datagram: &[u8],
+        recovered: &mut Vec<u8>,
+    ) -> bool {
+        let kept = match self.kept(generation) {
+            Some(kept) if !kept.received => kept,
+            _ => return false,
+        };
+        kept.received = true;
+        kept.datagram.clear();
+        kept.datagram.extend_from_slice(datagram);
+        match kept.group {
+            Some(first) => self.recover(first, recovered),
+            None => false,
+        }
+    }
+
+    /// Keep a parity datagram. If it allows rebuilding a lost datagram, write that into
+    /// `recovered` and return true.
+    pub fn on_parity(&mut self, p: &PacketHeader, payload: &[u8], recovered: &mut Vec<u8>) -> bool {
+        let (first, count) = (p.generation, p.id as i64);
+        if count == 0 || count > MAX_GROUP as i64 || payload.len() < 2 {
+            return false;
+        }
+        for generation in first..first + count {
+            match self.kept(generation) {
+                Some(kept) => kept.group = Some(first),
+                None => return false,
+            }
+        }
+        let parity = &mut self.parity[index(first)];
+        parity.first = first;
+        parity.count = count;
+        parity.payload.clear();
+        parity.payload.extend_from_slice(payload);
+        self.recover(first, recovered)
+    }
+
+    fn recover(&mut self, first: i64, recovered: &mut Vec<u8>) -> bool {
+        let parity = &mut self.parity[index(first)];
+        if parity.first != first || parity.count == 0 {
+            return false;
+        }
+        let count = parity.count;
+        let mut missing = None;
+        for generation in first..first + count {
+            let kept = &self.kept[index(generation)];
+            if kept.generation == generation && kept.received {
+                continue;
+            }
+            if missing.is_some() {
+                // wait for more of the group
+                return false;
+            }
+            missing = Some(generation);
+        }
+        parity.count = 0;
+        let missing = match missing {
+            Some(missing) => missing,
+            None => return false,
+        };
+        let mut length = u16::from_be_bytes([parity.payload[0], parity.payload[1]]);
+        recovered.clear();
+        recovered.extend_from_slice(&parity.payload[2..]);
+        for generation in (first..first + count).filter(|&g| g != missing) {
+            let other = &self.kept[index(generation)].datagram;
+            length ^= other.len() as u16;
+            for (r, o) in recovered.iter_mut().zip(other) {
+                *r ^= o;
+            }
+        }
+        if length as usize > recovered.len() {
+            return false;
+        }
+        recovered.truncate(length as usize);
+        match PacketHeader::deserialize(recovered) {
+            Ok((p, _)) if p.generation == missing && !p.is_parity() => {
+                if let Some(kept) = self.kept(missing) {
+                    kept.received = true;
+                    kept.rebuilt = true;
+                    kept.datagram.clear();
+                    kept.datagram.extend_from_slice(recovered);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+}
@@ -1,20 +1,93 @@
-use tokio::{net::UdpSocket, time::sleep, time::Duration};
+use tokio::{
+    net::{lookup_host, UdpSocket},
+    time::{sleep, timeout, Duration},
+};
 
+use super::protocol::PacketDesc;
+use super::session::Session;
 use futures::{future::FutureExt, select};
+use log::{info, warn};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    convert::TryInto,
+    future::Future,
+    io,
+    mem::size_of,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+/// Put between the magic and the hash of the schema by `schema_magic`.
+const SCHEMA_MARKER: &[u8] = b"#schema";
+/// Appended by the server to the magic of a client with another schema.
+const REFUSED: u8 = b'!';
+
+/// The magic followed by the hash of the packet schema, so that only builds with the same
+/// packets connect. A server answers a client whose magic only differs in the hash with a
+/// refusal, and the handshake of the client fails with `InvalidData` instead of retrying. The
+/// magic is returned unchanged if `T` has no schema.
+pub fn schema_magic<T: PacketDesc>(magic: &[u8]) -> Vec<u8> {
+    let mut versioned = magic.to_vec();
+    if let Some(schema) = T::schema() {
+        versioned.extend_from_slice(SCHEMA_MARKER);
+        versioned.extend_from_slice(&schema.hash().to_be_bytes());
+    }
+    versioned
+}
+
+/// If the received magic is the one of another build with a different schema.
+pub(crate) fn schema_mismatch(magic: &[u8], received: &[u8]) -> bool {
+    let split = match magic.len().checked_sub(size_of::<u64>()) {
+        Some(split) => split,
+        None => return false,
+    };
+    received.len() == magic.len()
+        && magic[..split].ends_with(SCHEMA_MARKER)
+        && received[..split] == magic[..split]
+        && received[split..] != magic[split..]
+}
+
+/// The answer refusing a client with another schema.
+pub(crate) fn refusal(received: &[u8]) -> Vec<u8> {
+    let mut answer = received.to_vec();
+    answer.push(REFUSED);
+    answer
+}
+
+/// If the server answered the magic with a refusal.
+pub(crate) fn is_refusal(magic: &[u8], answer: &[u8]) -> bool {
+    answer.len() == magic.len() + 1 && answer.starts_with(magic) && answer[magic.len()] == REFUSED
+}
+
+pub(crate) fn refused_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "the server refused the packet schema, the builds differ",
+    )
+}
 
 pub async fn server_listen(bind: &str, magic: &[u8]) -> UdpSocket {
+    let socket = UdpSocket::bind(bind).await.unwrap();
+    server_accept(socket, magic).await
+}
+
+/// Wait for the magic on a socket which is already bound, and connect the socket to the first
+/// client sending it.
+pub async fn server_accept(socket: UdpSocket, magic: &[u8]) -> UdpSocket {
     const CAPACITY: usize = 2048;
     let mut buffer: Vec<u8> = Vec::with_capacity(CAPACITY);
     for _ in 0..CAPACITY {
         buffer.push(0);
     }
-    let socket = UdpSocket::bind(bind).await.unwrap();
     loop {
         let (len, from) = socket.recv_from(buffer.as_mut_slice()).await.unwrap();
         if len == magic.len() && &buffer[..magic.len()] == magic {
             socket.connect(from).await.unwrap();
             break;
         }
+        if schema_mismatch(magic, &buffer[..len]) {
+            warn!("Refused client from {} with another packet schema", from);
+            let _ = socket.send_to(&refusal(&buffer[..len]), from).await;
+        }
     }
     // send magic back to client to notify connection established,
     // and wait until the client send something different
@@ -26,24 +99,211 @@ pub async fn server_listen(bind: &str, magic: &[u8]) -> UdpSocket {
 }
 
 pub async fn client_connect(bind: &str, server: &str, magic: &[u8]) -> UdpSocket {
-    let timeout = Duration::new(0, 100_000_000);
-    let mut buffer: Vec<u8> = Vec::with_capacity(magic.len());
-    for _ in magic.iter() {
-        buffer.push(0);
-    }
     let socket = UdpSocket::bind(bind).await.unwrap();
     socket.connect(server).await.unwrap();
+    client_handshake(socket, magic).await
+}
+
+/// Send the magic through a socket which is already connected to the server, until the server
+/// answers.
+pub async fn client_handshake(socket: UdpSocket, magic: &[u8]) -> UdpSocket {
+    try_client_handshake(socket, magic).await.unwrap()
+}
+
+/// Same as `client_handshake`, but return the error if the magic could not be sent, for example
+/// when there is no route to the server.
+pub async fn try_client_handshake(socket: UdpSocket, magic: &[u8]) -> io::Result<UdpSocket> {
+    handshake(socket, magic).await.map(|(socket, _)| socket)
+}
+
+/// Same as `try_client_handshake`, for a server accepting with `server_accept_resumable`.
+/// Returns the session to be put into `Config::session`.
+pub async fn client_handshake_resumable(
+    socket: UdpSocket,
+    magic: &[u8],
+) -> io::Result<(UdpSocket, Session)> {
+    let peer = socket.peer_addr()?;
+    match handshake(socket, magic).await? {
+        (socket, Some(id)) => Ok((socket, Session { id, peer })),
+        (_, None) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the server does not support resumption",
+        )),
+    }
+}
+
+/// Send the magic until the server answers with the magic, which could be followed by the
+/// session ID.
+async fn handshake(socket: UdpSocket, magic: &[u8]) -> io::Result<(UdpSocket, Option<u64>)> {
+    let timeout = Duration::new(0, 100_000_000);
+    let mut buffer: Vec<u8> = vec![0; magic.len() + size_of::<u64>()];
     loop {
-        socket.send(magic).await.unwrap();
+        socket.send(magic).await?;
         select! {
-            _ = socket.recv(buffer.as_mut_slice()).fuse() => {
-                if buffer == magic {
-                    break;
+            result = socket.recv(buffer.as_mut_slice()).fuse() => {
+                match result {
+                    Ok(len) if &buffer[..len] == magic => return Ok((socket, None)),
+                    Ok(len) if len == buffer.len() && &buffer[..magic.len()] == magic => {
+                        let id = u64::from_be_bytes(buffer[magic.len()..].try_into().unwrap());
+                        return Ok((socket, Some(id)));
+                    }
+                    Ok(len) if is_refusal(magic, &buffer[..len]) => return Err(refused_error()),
+                    _ => (),
                 }
             },
             _ = sleep(timeout).fuse() => {
             }
         }
     }
-    socket
+}
+
+/// Same as `server_accept`, but the socket is left unconnected and the client is given a session
+/// ID, so that it could resume the connection from another address. See `session`.
+pub async fn server_accept_resumable(
+    socket: UdpSocket,
+    magic: &[u8],
+) -> io::Result<(UdpSocket, Session)> {
+    const CAPACITY: usize = 2048;
+    let mut buffer = vec![0u8; CAPACITY];
+    let peer = loop {
+        let (len, from) = socket.recv_from(&mut buffer).await?;
+        if &buffer[..len] == magic {
+            break from;
+        }
+        if schema_mismatch(magic, &buffer[..len]) {
+            warn!("Refused client from {} with another packet schema", from);
+            socket.send_to(&refusal(&buffer[..len]), from).await?;
+        }
+    };
+    let id = rand::random::<u64>();
+    let mut answer = magic.to_vec();
+    answer.extend(id.to_be_bytes().iter());
+    // same as `server_accept`, wait until the client send something different
+    loop {
+        socket.send_to(&answer, peer).await?;
+        let (len, from) = socket.recv_from(&mut buffer).await?;
+        if from == peer && &buffer[..len] != magic {
+            break;
+        }
+    }
+    Ok((socket, Session { id, peer }))
+}
+
+/// Listen on `port` for both IPv4 and IPv6 clients, by binding `[::]` with `IPV6_V6ONLY`
+/// disabled. Falls back to `0.0.0.0` if IPv6 is not available.
+pub async fn server_listen_dual_stack(port: u16, magic: &[u8]) -> io::Result<UdpSocket> {
+    let socket = bind_dual_stack(port).await?;
+    let socket = server_accept(socket, magic).await;
+    info!(
+        "Accepted client from {} over {}",
+        socket.peer_addr()?,
+        address_family(&socket.peer_addr()?)
+    );
+    Ok(socket)
+}
+
+/// Bind `port` for both IPv4 and IPv6 without waiting for a client, for `server_accept` or
+/// `server_accept_resumable`. Falls back to `0.0.0.0` if IPv6 is not available.
+pub async fn bind_dual_stack(port: u16) -> io::Result<UdpSocket> {
+    let socket = match bind_ipv6_any(port) {
+        Ok(socket) => socket,
+        Err(e) => {
+            warn!("Cannot listen on IPv6 ({}), falling back to IPv4 only", e);
+            UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)).await?
+        }
+    };
+    info!("Listening on {}", socket.local_addr()?);
+    Ok(socket)
+}
+
+fn bind_ipv6_any(port: u16) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_only_v6(false)?;
+    socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)).into())?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// Name of the address family for reporting, IPv4 clients of a dual-stack socket appear as
+/// IPv4-mapped IPv6 addresses.
+pub fn address_family(addr: &SocketAddr) -> &'static str {
+    match addr.ip() {
+        IpAddr::V4(_) => "IPv4",
+        IpAddr::V6(ip) if ip.to_ipv4().is_some() && ip.segments()[5] == 0xffff => "IPv4 (mapped)",
+        IpAddr::V6(_) => "IPv6",
+    }
+}
+
+/// Resolve the server, which could be a hostname with port, and try the handshake with every
+/// resolved address in turn until one answers within `attempt_timeout`. Returns the connected
+/// socket and the address used.
+pub async fn client_connect_any(
+    server: &str,
+    magic: &[u8],
+    attempt_timeout: Duration,
+) -> io::Result<(UdpSocket, SocketAddr)> {
+    connect_any(server, attempt_timeout, |socket| try_client_handshake(socket, magic)).await
+}
+
+/// Same as `client_connect_any`, with `client_handshake_resumable`.
+pub async fn client_connect_any_resumable(
+    server: &str,
+    magic: &[u8],
+    attempt_timeout: Duration,
+) -> io::Result<(UdpSocket, Session)> {
+    connect_any(server, attempt_timeout, |socket| {
+        client_handshake_resumable(socket, magic)
+    })
+    .await
+    .map(|(result, _)| result)
+}
+
+async fn connect_any<R, F, Fut>(
+    server: &str,
+    attempt_timeout: Duration,
+    handshake: F,
+) -> io::Result<(R, SocketAddr)>
+where
+    F: Fn(UdpSocket) -> Fut,
+    Fut: Future<Output = io::Result<R>>,
+{
+    let addresses: Vec<SocketAddr> = lookup_host(server).await?.collect();
+    if addresses.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} resolved to no address", server),
+        ));
+    }
+    for address in addresses {
+        info!("Trying {} over {}", address, address_family(&address));
+        let bind: SocketAddr = match address {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = match UdpSocket::bind(bind).await {
+            Ok(socket) => socket,
+            Err(e) => {
+                warn!("Cannot bind {}: {}", bind, e);
+                continue;
+            }
+        };
+        if let Err(e) = socket.connect(address).await {
+            warn!("Cannot reach {}: {}", address, e);
+            continue;
+        }
+        match timeout(attempt_timeout, handshake(socket)).await {
+            Ok(Ok(result)) => {
+                info!("Connected to {} over {}", address, address_family(&address));
+                return Ok((result, address));
+            }
+            // the server answered, trying the other addresses would not help
+            Ok(Err(e)) if e.kind() == io::ErrorKind::InvalidData => return Err(e),
+            Ok(Err(e)) => warn!("Cannot connect to {}: {}", address, e),
+            Err(_) => warn!("No answer from {}", address),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("no address of {} answered", server),
+    ))
 }
@@ -0,0 +1,370 @@
+//! Decode datagrams for debugging, used by the `rudp-inspect` binary.
+//!
+//! Datagrams are read from a capture, or from a proxy forwarding a live session between a client
+//! and a server. Payloads are decoded by a `Dissector`. The binary only prints them as hex, to
+//! decode the packets of an application, build a small binary calling
+//! `main(Decode::<Packet>::new())`.
+use super::capture::{CaptureReader, Direction};
+use super::protocol::{decompress, PacketDesc, PacketHeader};
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    env,
+    fmt::{Debug, Write},
+    io,
+    marker::PhantomData,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Turn the payload of a packet into human readable text.
+pub trait Dissector {
+    fn describe(&self, id: u32, payload: &[u8]) -> String;
+}
+
+/// Print the payload as hex, truncated to the first 32 bytes.
+pub struct Hex;
+
+impl Dissector for Hex {
+    fn describe(&self, _: u32, payload: &[u8]) -> String {
+        const LIMIT: usize = 32;
+        let mut result = String::with_capacity(LIMIT * 2 + 3);
+        for byte in payload.iter().take(LIMIT) {
+            let _ = write!(result, "{:02x}", byte);
+        }
+        if payload.len() > LIMIT {
+            result.push_str("...");
+        }
+        result
+    }
+}
+
+/// Decode the payload with a `PacketDesc` implementation and print it with `Debug`.
+pub struct Decode<T>(PhantomData<T>);
+
+impl<T> Decode<T> {
+    pub fn new() -> Self {
+        Decode(PhantomData)
+    }
+}
+
+impl<T> Default for Decode<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: PacketDesc + Debug> Dissector for Decode<T> {
+    fn describe(&self, id: u32, payload: &[u8]) -> String {
+        match T::deserialize(id, payload) {
+            Ok(packet) => format!("{:?}", packet),
+            Err(e) => format!("<{}>", e.0),
+        }
+    }
+}
+
+#[derive(Default)]
+struct IdStats {
+    data: u64,
+    unreliable: u64,
+    retransmissions: u64,
+    acks: u64,
+    ack_latency_total: Duration,
+    ack_latency_max: Duration,
+    gap_max: Duration,
+    last_seen: Option<Duration>,
+}
+
+/// Keep track of the datagrams seen so far, to report retransmissions, ACK latency and the gap
+/// between datagrams with the same ID.
+pub struct Inspector<D: Dissector> {
+    dissector: D,
+    stats: BTreeMap<(u32, bool), IdStats>,
+    // (sent by us, slot, generation) -> time of the first transmission, the direction is `None`
+    // when it is unknown
+    in_flight: HashMap<(Option<bool>, isize, i64), Duration>,
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn arrow(direction: Option<Direction>) -> &'static str {
+    match direction {
+        Some(Direction::Sent) => ">",
+        Some(Direction::Received) => "<",
+        None => " ",
+    }
+}
+
+impl<D: Dissector> Inspector<D> {
+    pub fn new(dissector: D) -> Self {
+        Inspector {
+            dissector,
+            stats: BTreeMap::new(),
+            in_flight: HashMap::new(),
+        }
+    }
+
+    /// Decode a datagram and return the description. `direction` is `None` when listening
+    /// passively, in which case everything is treated as coming from the same peer.
+    pub fn inspect(&mut self, time: Duration, direction: Option<Direction>, data: &[u8]) -> String {
+        let sent = direction == Some(Direction::Sent);
+        let side = direction.map(|direction| direction == Direction::Sent);
+        let (header, payload) = match PacketHeader::deserialize(data) {
+            Ok(result) => result,
+            Err(e) => {
+                return format!(
+                    "{:>12.6} {} <{}> len={}",
+                    time.as_secs_f64(),
+                    arrow(direction),
+                    e.0,
+                    data.len()
+                )
+            }
+        };
+        let mut line = format!(
+            "{:>12.6} {} id={:<4} slot={:<4} gen={:<8}",
+            time.as_secs_f64(),
+            arrow(direction),
+            header.id,
+            header.slot,
+            header.generation
+        );
+        if header.is_parity() {
+            // the ID is the number of datagrams covered
+            let _ = write!(line, " parity     len={:<5}", payload.len());
+            return line;
+        }
+        if header.is_control() {
+            // the ID is the control type and the generation the session ID, see `session`
+            let _ = write!(line, " control");
+            return line;
+        }
+        let stats = self.stats.entry((header.id, sent)).or_default();
+        if header.slot < 0 {
+            let slot = match header.slot.checked_neg() {
+                Some(slot) => slot,
+                None => {
+                    let _ = write!(line, " <invalid slot>");
+                    return line;
+                }
+            };
+            // the ACK answers a packet going in the opposite direction
+            let key = (side.map(|sent| !sent), slot, header.generation);
+            let _ = write!(line, " ack");
+            if let Some(start) = self.in_flight.remove(&key) {
+                let latency = time.checked_sub(start).unwrap_or_default();
+                stats.acks += 1;
+                stats.ack_latency_total += latency;
+                stats.ack_latency_max = stats.ack_latency_max.max(latency);
+                let _ = write!(line, "  latency={:.3}ms", millis(latency));
+            }
+            return line;
+        }
+        if header.slot > 0 {
+            let key = (side, header.slot, header.generation);
+            let _ = write!(line, " data       len={:<5}", payload.len());
+            match self.in_flight.entry(key) {
+                Entry::Occupied(_) => {
+                    stats.retransmissions += 1;
+                    let _ = write!(line, " retransmit");
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(time);
+                }
+            }
+            stats.data += 1;
+        } else {
+            let _ = write!(line, " unreliable len={:<5}", payload.len());
+            stats.unreliable += 1;
+        }
+        if let Some(last) = stats.last_seen {
+            let gap = time.checked_sub(last).unwrap_or_default();
+            stats.gap_max = stats.gap_max.max(gap);
+            let _ = write!(line, " gap={:.3}ms", millis(gap));
+        }
+        stats.last_seen = Some(time);
+        if header.is_compressed() {
+            let mut decompressed = Vec::new();
+            match decompress(payload, &mut decompressed) {
+                Ok(()) => {
+                    let _ = write!(
+                        line,
+                        " lz4 {}",
+                        self.dissector.describe(header.id, &decompressed)
+                    );
+                }
+                Err(e) => {
+                    let _ = write!(line, " lz4 <{}>", e.0);
+                }
+            }
+        } else {
+            let _ = write!(line, " {}", self.dissector.describe(header.id, payload));
+        }
+        line
+    }
+
+    /// Per ID summary of everything inspected so far.
+    pub fn summary(&self) -> String {
+        let mut result = String::from(
+            "dir   id    data  unreliable  retransmit  acked  avg ack ms  max ack ms  max gap ms\n",
+        );
+        for ((id, sent), stats) in self.stats.iter() {
+            let average = if stats.acks > 0 {
+                millis(stats.ack_latency_total) / stats.acks as f64
+            } else {
+                0.0
+            };
+            let _ = writeln!(
+                result,
+                "{:<5} {:<5} {:<5} {:<11} {:<11} {:<6} {:<11.3} {:<11.3} {:<.3}",
+                if *sent { "out" } else { "in" },
+                id,
+                stats.data,
+                stats.unreliable,
+                stats.retransmissions,
+                stats.acks,
+                average,
+                millis(stats.ack_latency_max),
+                millis(stats.gap_max)
+            );
+        }
+        result
+    }
+}
+
+fn usage() {
+    println!("Usage:");
+    println!("Read a capture: <capture file>");
+    println!("Forward a session: --proxy <bind address with port> <server address with port>");
+    println!("Receive datagrams: --listen <bind address with port>");
+}
+
+fn inspect_file<D: Dissector>(path: &str, dissector: D) -> io::Result<()> {
+    let mut inspector = Inspector::new(dissector);
+    for record in CaptureReader::open(path)? {
+        let record = record?;
+        println!(
+            "{}",
+            inspector.inspect(record.time, Some(record.direction), &record.data)
+        );
+    }
+    println!();
+    print!("{}", inspector.summary());
+    Ok(())
+}
+
+/// Receive the datagrams sent to the address. This is an endpoint of its own which never answers,
+/// not a tap on a running session, see `proxy` for that. The direction of the datagrams is
+/// unknown.
+fn listen<D: Dissector>(bind: &str, dissector: D) -> io::Result<()> {
+    const CAPACITY: usize = 2048;
+    let mut inspector = Inspector::new(dissector);
+    let socket = UdpSocket::bind(bind)?;
+    let mut buffer = vec![0u8; CAPACITY];
+    let start = Instant::now();
+    loop {
+        let (len, from) = socket.recv_from(&mut buffer)?;
+        println!(
+            "{} from {}",
+            inspector.inspect(start.elapsed(), None, &buffer[..len]),
+            from
+        );
+    }
+}
+
+/// Forward datagrams between the client sending to `bind` and the server. The client connects
+/// to the proxy instead of the server. Datagrams from the client are shown as sent and the ones
+/// from the server as received, so that ACKs are matched in both directions. Replies go to the
+/// address the client last sent from.
+fn proxy<D: Dissector + Send + 'static>(bind: &str, server: &str, dissector: D) -> io::Result<()> {
+    const CAPACITY: usize = 2048;
+    let server = server
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address for the server"))?;
+    let downstream = UdpSocket::bind(bind)?;
+    let upstream = UdpSocket::bind(if server.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    })?;
+    upstream.connect(server)?;
+    let client: Arc<Mutex<Option<SocketAddr>>> = Arc::new(Mutex::new(None));
+    let inspector = Arc::new(Mutex::new(Inspector::new(dissector)));
+    let start = Instant::now();
+    {
+        let downstream = downstream.try_clone()?;
+        let upstream = upstream.try_clone()?;
+        let client = client.clone();
+        let inspector = inspector.clone();
+        thread::spawn(move || {
+            let mut buffer = vec![0u8; CAPACITY];
+            loop {
+                let len = match upstream.recv(&mut buffer) {
+                    Ok(len) => len,
+                    // such as the server not listening yet
+                    Err(e) => {
+                        eprintln!("Error receiving from the server: {}", e);
+                        continue;
+                    }
+                };
+                let line = inspector.lock().unwrap().inspect(
+                    start.elapsed(),
+                    Some(Direction::Received),
+                    &buffer[..len],
+                );
+                println!("{}", line);
+                if let Some(client) = *client.lock().unwrap() {
+                    if let Err(e) = downstream.send_to(&buffer[..len], client) {
+                        eprintln!("Error sending to {}: {}", client, e);
+                    }
+                }
+            }
+        });
+    }
+    let mut buffer = vec![0u8; CAPACITY];
+    loop {
+        let (len, from) = downstream.recv_from(&mut buffer)?;
+        *client.lock().unwrap() = Some(from);
+        let line = inspector.lock().unwrap().inspect(
+            start.elapsed(),
+            Some(Direction::Sent),
+            &buffer[..len],
+        );
+        println!("{}", line);
+        if let Err(e) = upstream.send(&buffer[..len]) {
+            eprintln!("Error sending to {}: {}", server, e);
+        }
+    }
+}
+
+/// Run the command line interface with the arguments, excluding the program name.
+pub fn run<D: Dissector + Send + 'static>(args: &[String], dissector: D) -> io::Result<()> {
+    match args {
+        [flag, bind, server] if flag == "--proxy" => proxy(bind, server, dissector),
+        [flag, bind] if flag == "--listen" => listen(bind, dissector),
+        [path] if !path.starts_with("--") => inspect_file(path, dissector),
+        _ => {
+            usage();
+            Ok(())
+        }
+    }
+}
+
+/// Entry point of an inspector binary, running the command line interface with the arguments of
+/// the process. Plug in the packets of an application with:
+/// ```ignore
+/// fn main() {
+///     rudp::inspect::main(rudp::inspect::Decode::<Packet>::new());
+/// }
+/// ```
+pub fn main<D: Dissector + Send + 'static>(dissector: D) {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(e) = run(&args, dissector) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
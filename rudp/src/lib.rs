@@ -1,53 +1,172 @@
 #![recursion_limit = "256"]
+#[macro_use]
+mod trace;
+mod batch;
+pub mod bitpack;
+pub mod capture;
+pub mod codec;
+mod delivery;
+pub mod fec;
 pub mod hand_shake;
+pub mod inspect;
 mod protocol;
 mod receiver;
+pub mod relay;
+pub mod rendezvous;
+pub mod rpc;
+pub mod schema;
 mod sender;
+pub mod session;
+mod stats;
+pub mod stream;
+pub mod tcp;
 
+use capture::CaptureWriter;
+pub use delivery::{Closed, Delivery, PacketSender, Receipt};
+use delivery::Outgoing;
+pub use fec::Redundancy;
 use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
-pub use protocol::{DeserializeError, PacketDesc};
+pub use protocol::{DeserializeError, PacketDesc, PacketHeader};
+use protocol::Compression;
 pub use receiver::BypassResult;
 use receiver::Receiver;
-use sender::Sender;
+use sender::{SendSettings, Sender};
+use session::Link;
+use stream::StreamHub;
+pub use session::Session;
+pub use stats::{ConnectionStats, Path};
 use std::marker::{Send, Sync};
 use std::sync::Arc;
-use tokio::{net::UdpSocket, select, time::Duration};
+use tokio::{
+    net::{TcpStream, UdpSocket},
+    select,
+    sync::mpsc,
+    time::Duration,
+};
+
+/// Configuration of the UDP loop.
+pub struct Config {
+    /// Timeout for retransmission.
+    pub timeout: Duration,
+    /// Number of slots for sending reliable packets *in parallel*.
+    pub slot_capacity: usize,
+    /// Maximum number of consecutive send/recv attempts when the socket failed to work.
+    /// If reached, the respective task would exit. Note that this is not resend attempt.
+    pub max_retry: u32,
+    /// Packet drop rate for simulating packet drop. If 0, it would not attemp to simulate packet
+    /// drop. Should be within 0..100. Note that the probability is not really that accurate, this
+    /// is for testing only.
+    pub drop_percentage: u64,
+    /// If set, every datagram sent and received would be recorded into the capture.
+    pub capture: Option<CaptureWriter>,
+    /// How the socket reaches the remote, reported in the connection stats.
+    pub path: Path,
+    /// If set, the remote could resume the connection from another address, see `session`.
+    pub session: Option<Session>,
+    /// How long a session could be resumed after the last datagram received from the remote.
+    pub resumption_window: Duration,
+    /// Compress every packet, not only the ones marked for compression by `PacketDesc::compress`.
+    pub compress_all: bool,
+    /// Payloads shorter than this number of bytes are never compressed.
+    pub compression_threshold: usize,
+    /// Minimum interval between two packets with the same ID marked as state by
+    /// `PacketDesc::state`. A state packet sent within the interval is held back, and replaced
+    /// if a newer one comes before the interval passes.
+    pub state_interval: Duration,
+    /// Parity sent for rebuilding lost datagrams without waiting for the retransmission, see
+    /// `fec`.
+    pub fec: Redundancy,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            // 20ms
+            timeout: Duration::new(0, 20_000_000),
+            slot_capacity: 10,
+            max_retry: 10,
+            drop_percentage: 0,
+            capture: None,
+            path: Path::Direct,
+            session: None,
+            resumption_window: Duration::from_secs(10),
+            compress_all: false,
+            compression_threshold: 64,
+            state_interval: Duration::from_millis(0),
+            fec: Redundancy::Off,
+        }
+    }
+}
+
+/// Where a loop passes the received packets on: to the application, back into the loop, or to
+/// the incoming streams.
+pub(crate) struct Channels<T> {
+    pub to_fg: UnboundedSender<T>,
+    pub to_bg: PacketSender<T>,
+    pub streams: StreamHub,
+}
 
 async fn udp_loop<
     T: PacketDesc + Send + Sync + 'static,
     F: Fn(T) -> BypassResult<T> + Send + Sync + 'static,
 >(
     socket: UdpSocket,
-    timeout: Duration,
-    slot_capacity: usize,
-    max_retry: u32,
-    drop_percentage: u64,
-    from_fg: UnboundedReceiver<T>,
-    to_bg: Arc<UnboundedSender<T>>,
-    to_fg: UnboundedSender<T>,
+    config: Config,
+    stats: Arc<ConnectionStats>,
+    from_fg: mpsc::UnboundedReceiver<Outgoing<T>>,
+    channels: Channels<T>,
     bypass: F,
 ) {
-    let (ack_from, mut ack_to) = unbounded();
-    let socket = Arc::new(socket);
-    let mut sender = Sender::<T>::new(socket.clone(), timeout, slot_capacity, max_retry);
-    let mut receiver = Receiver::new(socket, &sender);
-    let send_task = tokio::spawn(async move {
+    let (ack_from, mut ack_to) = mpsc::unbounded_channel();
+    #[cfg(feature = "tracing")]
+    let span = trace::connection(socket.peer_addr().ok(), stats.path());
+    let socket = Arc::new(Link::new(
+        socket,
+        config.session,
+        config.resumption_window,
+    ));
+    let max_retry = config.max_retry;
+    let drop_percentage = config.drop_percentage;
+    let mut sender = Sender::<T>::new(
+        socket.clone(),
+        config.timeout,
+        config.slot_capacity,
+        max_retry,
+        config.capture.map(Arc::new),
+        stats,
+        SendSettings {
+            compression: Compression {
+                all: config.compress_all,
+                threshold: config.compression_threshold,
+            },
+            state_interval: config.state_interval,
+            redundancy: config.fec,
+        },
+    );
+    let mut receiver = Receiver::new(&sender);
+    let send_task = async move {
         let mut from_fg = from_fg;
         sender.send_loop(&mut from_fg, &mut ack_to).await;
-    });
-    let recv_task = tokio::spawn(async move {
-        let to_fg = to_fg;
+    };
+    let recv_task = async move {
         receiver
             .recv_loop(
+                &socket,
                 &ack_from,
-                &to_fg,
-                &to_bg,
+                channels,
                 max_retry,
                 drop_percentage,
                 bypass,
             )
             .await;
-    });
+    };
+    #[cfg(feature = "tracing")]
+    let (send_task, recv_task) = (
+        tracing::Instrument::instrument(send_task, span.clone()),
+        tracing::Instrument::instrument(recv_task, span),
+    );
+    let send_task = tokio::spawn(send_task);
+    let recv_task = tokio::spawn(recv_task);
     // Close the task when any finishes.
     select!(
         _ = send_task => (),
@@ -57,43 +176,67 @@ async fn udp_loop<
 
 /// Start the UDP loop.
 /// # Parameters
-/// * socket: Socket for communication, should be connected already.
-/// * timeout: Timeout for retransmission.
-/// * slot_capacity: Number of slots for sending reliable packets *in parallel*.
-/// * max_retry: Maximum number of consecutive send/recv attempts when the socket failed to work.
-///   If reached, the respective task would exit. Note that this is not resend attempt.
-/// * drop_percentage: Packet drop rate for simulating packet drop. If 0, it would not attemp to
-///   simulate packet drop. Should be within 0..100. Note that the probability is not really that
-///   accurate, this is for testing only.
+/// * socket: Socket for communication, should be connected already, unless it is the server side
+///   of a resumable session (`Config::session`).
+/// * config: Configuration of the loop, see `Config`.
+/// * bypass: Decide whether a received packet goes to the user, goes back to the sender, or is
+///   discarded.
+///
+/// Returns the handle for sending packets, the channel for receiving packets, and the stats of the
+/// connection.
 pub fn start_udp_loop<
     T: PacketDesc + Send + Sync + 'static,
     F: Fn(T) -> BypassResult<T> + Send + Sync + 'static,
 >(
     socket: UdpSocket,
-    timeout: Duration,
-    slot_capacity: usize,
-    max_retry: u32,
+    config: Config,
     bypass: F,
-    drop_percentage: u64,
-) -> (Arc<UnboundedSender<T>>, UnboundedReceiver<T>) {
-    debug_assert!(drop_percentage < 100);
-    let (to_background, from_foreground) = unbounded();
+) -> (PacketSender<T>, UnboundedReceiver<T>, Arc<ConnectionStats>) {
+    debug_assert!(config.drop_percentage < 100);
+    let (to_background, from_foreground) = mpsc::unbounded_channel();
     let (to_foreground, from_background) = unbounded();
-    let to_background = Arc::new(to_background);
+    let (streams, incoming_streams) = StreamHub::new();
+    let to_background = PacketSender::new(to_background, incoming_streams);
     let to_background_cloned = to_background.clone();
+    let stats = Arc::new(ConnectionStats::new(config.path));
+    let stats_cloned = stats.clone();
     tokio::spawn(async move {
-        udp_loop::<T, _>(
-            socket,
-            timeout,
-            slot_capacity,
-            max_retry,
-            drop_percentage,
-            from_foreground,
-            to_background_cloned,
-            to_foreground,
-            bypass,
-        )
+        let channels = Channels {
+            to_fg: to_foreground,
+            to_bg: to_background_cloned,
+            streams,
+        };
+        udp_loop::<T, _>(socket, config, stats_cloned, from_foreground, channels, bypass)
         .await;
     });
-    (to_background, from_background)
+    (to_background, from_background, stats)
+}
+
+/// Start the loop over the framed TCP transport, see `tcp`. The stats report `Path::Tcp`.
+/// Otherwise the same as `start_udp_loop`.
+pub fn start_tcp_loop<
+    T: PacketDesc + Send + Sync + 'static,
+    F: Fn(T) -> BypassResult<T> + Send + Sync + 'static,
+>(
+    stream: TcpStream,
+    config: Config,
+    bypass: F,
+) -> (PacketSender<T>, UnboundedReceiver<T>, Arc<ConnectionStats>) {
+    let (to_background, from_foreground) = mpsc::unbounded_channel();
+    let (to_foreground, from_background) = unbounded();
+    let (streams, incoming_streams) = StreamHub::new();
+    let to_background = PacketSender::new(to_background, incoming_streams);
+    let to_background_cloned = to_background.clone();
+    let stats = Arc::new(ConnectionStats::new(Path::Tcp));
+    let stats_cloned = stats.clone();
+    tokio::spawn(async move {
+        let channels = Channels {
+            to_fg: to_foreground,
+            to_bg: to_background_cloned,
+            streams,
+        };
+        tcp::tcp_loop::<T, _>(stream, config, stats_cloned, from_foreground, channels, bypass)
+            .await;
+    });
+    (to_background, from_background, stats)
 }
@@ -1,3 +1,5 @@
+use super::schema::Schema;
+use lz4_flex::block::{compress_into, decompress_into, get_maximum_output_size};
 use std::convert::TryInto;
 use std::mem::size_of;
 
@@ -16,20 +18,62 @@ pub trait PacketDesc: Sized {
     /// Deserialize the data based on the ID and the remaining payload. Data should be the same as
     /// the data written into the writer in `serialize function`.
     fn deserialize(id: u32, data: &[u8]) -> Result<Self, DeserializeError>;
+    /// Return the correlation ID carried by the message, for matching responses to requests in
+    /// `rpc`. `None` if the message carries no correlation ID.
+    fn correlation(&self) -> Option<u64> {
+        None
+    }
+    /// Set the correlation ID carried by the message, ignored if it carries none.
+    fn set_correlation(&mut self, _id: u64) {}
+    /// Return the ID of the request which messages with this ID respond to, if any.
+    fn response_to(_id: u32) -> Option<u32> {
+        None
+    }
+    /// Return if the payload of messages with this ID should be compressed when it is larger
+    /// than `Config::compression_threshold`.
+    fn compress(_id: u32) -> bool {
+        false
+    }
+    /// Return if messages with this ID carry the latest state of something, so that only the
+    /// newest unsent one needs to be sent. See `Config::state_interval`.
+    fn state(_id: u32) -> bool {
+        false
+    }
+    /// Return the description of the packets, whose hash is checked during the handshake when
+    /// the magic comes from `hand_shake::schema_magic`. `None` skips the check, which is the
+    /// default for hand-written implementations.
+    fn schema() -> Option<&'static Schema> {
+        None
+    }
 }
 
 pub struct PacketHeader {
     pub id: u32,
     pub slot: isize,
     pub generation: i64,
+    /// Bit set of `COMPRESSED`, `PARITY` and `CONTROL`.
+    pub flags: u8,
 }
 
+/// Flag of the header marking an LZ4 compressed payload.
+pub const COMPRESSED: u8 = 1;
+/// Flag of the header marking a parity datagram, see `fec`.
+pub const PARITY: u8 = 2;
+/// Flag of the header marking a control datagram of a resumable session, see `session`.
+pub const CONTROL: u8 = 4;
+/// Size of the receive buffer, longer datagrams are truncated.
+pub const RECV_CAPACITY: usize = 1024;
+/// Compressed payloads claiming to decompress into more bytes are rejected.
+const MAX_DECOMPRESSED: usize = 64 * 1024;
+
 #[derive(Debug, Clone)]
 pub struct DeserializeError(pub String);
 
 const SLOT_START: usize = size_of::<u32>();
 const GENERATION_START: usize = SLOT_START + size_of::<isize>();
 const GENERATION_END: usize = GENERATION_START + size_of::<i64>();
+const FLAGS_START: usize = GENERATION_END;
+pub const HEADER_LEN: usize = FLAGS_START + size_of::<u8>();
 
 impl PacketHeader {
     pub fn new(id: u32, slot: isize, generation: i64) -> Self {
@@ -37,17 +81,31 @@ impl PacketHeader {
             id,
             slot,
             generation,
+            flags: 0,
         }
     }
 
+    pub fn is_compressed(&self) -> bool {
+        self.flags & COMPRESSED != 0
+    }
+
+    pub fn is_parity(&self) -> bool {
+        self.flags & PARITY != 0
+    }
+
+    pub fn is_control(&self) -> bool {
+        self.flags & CONTROL != 0
+    }
+
     pub fn serialize(&self, result: &mut Vec<u8>) {
         result.extend(self.id.to_be_bytes().iter());
         result.extend(self.slot.to_be_bytes().iter());
         result.extend(self.generation.to_be_bytes().iter());
+        result.push(self.flags);
     }
 
     pub fn deserialize(data: &[u8]) -> Result<(Self, &[u8]), DeserializeError> {
-        if data.len() < GENERATION_END {
+        if data.len() < HEADER_LEN {
             return Err(DeserializeError(
                 "Data shorter than header length.".to_string(),
             ));
@@ -66,12 +124,14 @@ impl PacketHeader {
             i64::from_be_bytes(data[GENERATION_START..GENERATION_END].try_into().map_err(
                 |_| DeserializeError("Error deserializing generation index.".to_string()),
             )?);
-        let data = &data[GENERATION_END..];
+        let flags = data[FLAGS_START];
+        let data = &data[HEADER_LEN..];
         Ok((
             PacketHeader {
                 id,
                 slot,
                 generation,
+                flags,
             },
             data,
         ))
@@ -84,3 +144,61 @@ pub fn modify_header(data: &mut [u8], id: u32, slot: isize, generation: i64) {
     data[GENERATION_START..GENERATION_END].copy_from_slice(&generation.to_be_bytes());
 }
 
+/// Compression settings of the sender, see `Config::compress_all`.
+#[derive(Clone, Copy)]
+pub struct Compression {
+    pub all: bool,
+    pub threshold: usize,
+}
+
+impl Compression {
+    /// Compress the payload after the header in place if it is large enough and gets smaller,
+    /// and mark it in the header. `scratch` is reused between calls to avoid allocations.
+    pub fn apply(&self, compress: bool, datagram: &mut Vec<u8>, scratch: &mut Vec<u8>) {
+        let payload = &datagram[HEADER_LEN..];
+        if !(compress || self.all) || payload.len() < self.threshold {
+            return;
+        }
+        const SIZE_LEN: usize = size_of::<u32>();
+        scratch.clear();
+        scratch.resize(SIZE_LEN + get_maximum_output_size(payload.len()), 0);
+        scratch[..SIZE_LEN].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        let len = match compress_into(payload, &mut scratch[SIZE_LEN..]) {
+            Ok(len) => SIZE_LEN + len,
+            Err(_) => return,
+        };
+        if len < payload.len() {
+            datagram.truncate(HEADER_LEN);
+            datagram.extend_from_slice(&scratch[..len]);
+            datagram[FLAGS_START] |= COMPRESSED;
+        }
+    }
+}
+
+/// Decompress the payload of a packet with the `COMPRESSED` flag into `output`.
+pub fn decompress(payload: &[u8], output: &mut Vec<u8>) -> Result<(), DeserializeError> {
+    const SIZE_LEN: usize = size_of::<u32>();
+    if payload.len() < SIZE_LEN {
+        return Err(DeserializeError("Compressed payload too short.".to_string()));
+    }
+    let size = u32::from_le_bytes(payload[..SIZE_LEN].try_into().unwrap()) as usize;
+    if size > MAX_DECOMPRESSED {
+        return Err(DeserializeError(format!(
+            "Compressed payload decompresses into {} bytes.",
+            size
+        )));
+    }
+    output.clear();
+    output.resize(size, 0);
+    match decompress_into(&payload[SIZE_LEN..], output) {
+        Ok(len) if len == size => Ok(()),
+        Ok(len) => Err(DeserializeError(format!(
+            "Compressed payload decompressed into {} bytes instead of {}.",
+            len, size
+        ))),
+        Err(e) => Err(DeserializeError(format!(
+            "Error decompressing payload: {}",
+            e
+        ))),
+    }
+}
@@ -1,9 +1,17 @@
 use super::{
-    protocol::{PacketDesc, PacketHeader},
+    batch::RecvBatch,
+    capture::{CaptureWriter, Direction},
+    delivery::PacketSender,
+    fec::Decoder,
+    protocol::{decompress, PacketDesc, PacketHeader, HEADER_LEN},
     sender::Sender,
+    session::Link,
+    stats::ConnectionStats,
+    stream::{self, StreamHub},
+    Channels,
 };
 use futures::channel::mpsc::UnboundedSender;
-use log::warn;
+use log::{debug, warn};
 use std::{
     collections::HashMap,
     num::Wrapping,
@@ -12,15 +20,23 @@ use std::{
         Arc,
     },
 };
-use tokio::{net::UdpSocket, sync::Notify};
+use tokio::sync::mpsc;
 
 pub struct Receiver {
-    inner: Arc<UdpSocket>,
     slots_generation: Arc<Vec<AtomicI64>>,
     recv_generation: Vec<Option<i64>>,
     slots_used: Arc<Vec<AtomicBool>>,
-    notify: Arc<Notify>,
+    // slots freed by ACKs are handed back to the sender
+    freed: mpsc::UnboundedSender<usize>,
     unreliable_generations: HashMap<u32, i64>,
+    capture: Option<Arc<CaptureWriter>>,
+    stats: Arc<ConnectionStats>,
+    streams: Option<StreamHub>,
+    // started by the first parity datagram of the remote
+    fec: Option<Decoder>,
+    // reused for every compressed or recovered datagram
+    decompressed: Vec<u8>,
+    recovered: Vec<u8>,
 }
 
 pub enum BypassResult<T> {
@@ -42,24 +58,61 @@ fn is_new(old: Option<&i64>, current: i64) -> bool {
 }
 
 impl Receiver {
-    pub fn new<T: PacketDesc>(inner: Arc<UdpSocket>, sender: &Sender<T>) -> Self {
+    pub fn new<T: PacketDesc>(sender: &Sender<T>) -> Self {
         let slots_generation = sender.get_slots_generation();
         let slots_used = sender.get_slots_used();
-        let notify = sender.get_notify();
+        let freed = sender.get_freed();
         let mut recv_generation = Vec::with_capacity(slots_generation.len());
         for _ in 0..slots_generation.len() {
             recv_generation.push(None);
         }
         Receiver {
-            inner,
             recv_generation,
             slots_generation,
             slots_used,
-            notify,
+            freed,
             unreliable_generations: HashMap::new(),
+            capture: sender.get_capture(),
+            stats: sender.get_stats(),
+            streams: None,
+            fec: None,
+            decompressed: Vec::new(),
+            recovered: Vec::new(),
         }
     }
 
+    /// Create a receiver which is not attached to any sender, for feeding recorded datagrams
+    /// offline. ACKs would only update the receiver's own copy of the slot states.
+    pub fn detached(capacity: usize) -> Self {
+        let mut slots_generation = Vec::with_capacity(capacity);
+        let mut slots_used = Vec::with_capacity(capacity);
+        let mut recv_generation = Vec::with_capacity(capacity);
+        let (freed, _) = mpsc::unbounded_channel();
+        for _ in 0..capacity {
+            slots_generation.push(AtomicI64::new(0));
+            slots_used.push(AtomicBool::new(false));
+            recv_generation.push(None);
+        }
+        Receiver {
+            recv_generation,
+            slots_generation: Arc::new(slots_generation),
+            slots_used: Arc::new(slots_used),
+            freed,
+            unreliable_generations: HashMap::new(),
+            capture: None,
+            stats: Arc::new(ConnectionStats::default()),
+            streams: None,
+            fec: None,
+            decompressed: Vec::new(),
+            recovered: Vec::new(),
+        }
+    }
+
+    /// Put the chunks of incoming streams into the hub, they are discarded otherwise.
+    pub(crate) fn accept_streams(&mut self, hub: StreamHub) {
+        self.streams = Some(hub);
+    }
+
     /// Handle reliable packet, return true if normal, false if channel closed.
     fn handle_reliable<T: PacketDesc>(&mut self, p: &PacketHeader, data: &[u8]) -> Option<T> {
         if p.slot > self.slots_generation.len() as isize {
@@ -70,6 +123,21 @@ impl Receiver {
             self.recv_generation[p.slot as usize - 1].as_ref(),
             p.generation,
         ) {
+            trace_event!(
+                debug,
+                id = p.id,
+                slot = p.slot,
+                generation = p.generation,
+                reason = "duplicate",
+                "dropped"
+            );
+            return None;
+        }
+        if stream::is_reserved(p.id) {
+            self.recv_generation[p.slot as usize - 1] = Some(p.generation);
+            if let Some(streams) = &mut self.streams {
+                streams.handle(p.id, data);
+            }
             return None;
         }
         if T::ordered(p.id) {
@@ -78,6 +146,14 @@ impl Receiver {
                 self.unreliable_generations.insert(p.id, p.generation);
             } else {
                 // discard it
+                trace_event!(
+                    debug,
+                    id = p.id,
+                    slot = p.slot,
+                    generation = p.generation,
+                    reason = "old",
+                    "dropped"
+                );
                 return None;
             }
         }
@@ -85,6 +161,14 @@ impl Receiver {
         match packet {
             Ok(packet) => {
                 self.recv_generation[p.slot as usize - 1] = Some(p.generation);
+                trace_event!(
+                    trace,
+                    id = p.id,
+                    slot = p.slot,
+                    generation = p.generation,
+                    size = data.len(),
+                    "delivered"
+                );
                 Some(packet)
             }
             Err(e) => {
@@ -95,19 +179,41 @@ impl Receiver {
     }
 
     fn handle_unreliable<T: PacketDesc>(&mut self, p: &PacketHeader, data: &[u8]) -> Option<T> {
+        if stream::is_reserved(p.id) {
+            warn!("Received unreliable packet with reserved ID");
+            return None;
+        }
         if T::ordered(p.id) {
             let old = self.unreliable_generations.get(&p.id);
             if is_new(old, p.generation) {
                 self.unreliable_generations.insert(p.id, p.generation);
             } else {
                 // discard it
+                trace_event!(
+                    debug,
+                    id = p.id,
+                    slot = p.slot,
+                    generation = p.generation,
+                    reason = "old",
+                    "dropped"
+                );
                 return None;
             }
         }
         // just receive it
         let packet = T::deserialize(p.id, data);
         match packet {
-            Ok(packet) => Some(packet),
+            Ok(packet) => {
+                trace_event!(
+                    trace,
+                    id = p.id,
+                    slot = p.slot,
+                    generation = p.generation,
+                    size = data.len(),
+                    "delivered"
+                );
+                Some(packet)
+            }
             Err(e) => {
                 warn!("Deserialization error: {}", e.0);
                 None
@@ -116,12 +222,8 @@ impl Receiver {
     }
 
     fn handle_ack<'a>(&mut self, p: &PacketHeader) {
-        // got ACK
+        // got ACK, the slot is checked by `handle_payload`
         let slot = -p.slot;
-        if slot > self.slots_generation.len() as isize {
-            warn!("Invalid slot ID for ACK message.");
-            return;
-        }
         if self.slots_generation[slot as usize - 1].load(Ordering::Acquire) == p.generation {
             if let Ok(_) = self.slots_used[slot as usize - 1].compare_exchange(
                 true,
@@ -129,36 +231,77 @@ impl Receiver {
                 Ordering::Release,
                 Ordering::Relaxed,
             ) {
-                // only notify when it is originally used
-                self.notify.notify_one();
+                // only hand back the slot when it is originally used
+                self.stats.on_ack();
+                let _ = self.freed.send(slot as usize - 1);
             }
         }
     }
 
+    /// Handle a packet without sending the ACK, return the packet if it should be delivered.
+    pub fn handle_packet<T: PacketDesc>(&mut self, p: &PacketHeader, data: &[u8]) -> Option<T> {
+        // control datagrams are only handled by a `Link` with a session
+        if p.is_parity() || p.is_control() {
+            return None;
+        }
+        if !p.is_compressed() {
+            return self.handle_payload(p, data);
+        }
+        let mut decompressed = std::mem::take(&mut self.decompressed);
+        let packet = match decompress(data, &mut decompressed) {
+            Ok(()) => self.handle_payload(p, &decompressed),
+            Err(e) => {
+                warn!("{}", e.0);
+                None
+            }
+        };
+        self.decompressed = decompressed;
+        packet
+    }
+
+    fn handle_payload<T: PacketDesc>(&mut self, p: &PacketHeader, data: &[u8]) -> Option<T> {
+        if p.slot > 0 {
+            self.handle_reliable(p, data)
+        } else if p.slot == 0 {
+            self.handle_unreliable(p, data)
+        } else if p.slot >= -(self.slots_generation.len() as isize) {
+            self.handle_ack(p);
+            None
+        } else {
+            warn!("Invalid slot ID for ACK message.");
+            None
+        }
+    }
+
     pub async fn recv_loop<T: PacketDesc, F: Fn(T) -> BypassResult<T>>(
         &mut self,
-        ack_channel: &UnboundedSender<(u32, isize, i64)>,
-        channel: &UnboundedSender<T>,
-        to_sender: &UnboundedSender<T>,
+        socket: &Link,
+        ack_channel: &mpsc::UnboundedSender<(u32, isize, i64)>,
+        channels: Channels<T>,
         retry_max: u32,
         drop_percentage: u64,
         bypass: F,
     ) {
-        // packet size for UDP is normally 1500 bytes
-        const CAPACITY: usize = 1024;
+        let Channels {
+            to_fg: channel,
+            to_bg: to_sender,
+            streams,
+        } = channels;
+        self.accept_streams(streams);
         let mut retry_count = 0;
-        let mut recv_buffer = Vec::with_capacity(CAPACITY);
-        for _ in 0..CAPACITY {
-            recv_buffer.push(0);
-        }
+        let mut batch = RecvBatch::new();
         loop {
-            let size = self.inner.recv(recv_buffer.as_mut_slice()).await;
-            let size = match size {
-                Ok(size) if size > 0 => {
-                    retry_count = 0;
-                    size
+            if let Err(e) = socket.recv_batch(&mut batch).await {
+                warn!("Error receiving data: {}", e.to_string());
+                retry_count += 1;
+                if retry_count == retry_max {
+                    return;
                 }
-                Ok(_) => {
+                continue;
+            }
+            for i in 0..batch.len() {
+                let datagram = batch.get(i);
+                if datagram.is_empty() {
                     warn!("Error receiving data: Payload with length 0.");
                     retry_count += 1;
                     if retry_count == retry_max {
@@ -166,51 +309,129 @@ impl Receiver {
                     }
                     continue;
                 }
-                Err(e) => {
-                    warn!("Error receiving data: {}", e.to_string());
-                    retry_count += 1;
-                    if retry_count == retry_max {
-                        return;
-                    }
-                    continue;
-                }
-            };
-            // simulate packet drop
-            if drop_percentage > 0 && rand::random::<u64>() % 100 < drop_percentage {
-                continue;
-            }
-            let result = PacketHeader::deserialize(&recv_buffer[0..size]);
-            let (p, data) = match result {
-                Ok((p, data)) => (p, data),
-                Err(e) => {
-                    warn!("Error deserializing header: {}", e.0);
+                retry_count = 0;
+                // simulate packet drop
+                if drop_percentage > 0 && rand::random::<u64>() % 100 < drop_percentage {
+                    trace_event!(
+                        debug,
+                        size = datagram.len(),
+                        reason = "simulated",
+                        "dropped"
+                    );
                     continue;
                 }
-            };
-            let p = if p.slot > 0 {
-                if ack_channel
-                    .unbounded_send((p.id, -p.slot, p.generation))
-                    .is_err()
-                {
-                    None
-                } else {
-                    self.handle_reliable(&p, &data)
-                }
-            } else if p.slot == 0 {
-                self.handle_unreliable(&p, &data)
-            } else {
-                self.handle_ack(&p);
-                None
-            };
-            if let Some(p) = p {
-                if match bypass(p) {
-                    BypassResult::Discard => false,
-                    BypassResult::ToSender(p) => to_sender.unbounded_send(p).is_err(),
-                    BypassResult::ToUser(p) => channel.unbounded_send(p).is_err(),
-                } {
-                    break;
+                if !self.receive(datagram, ack_channel, &channel, &to_sender, &bypass) {
+                    return;
                 }
             }
         }
     }
+
+    /// Handle a datagram from the socket, return false if the channel is closed.
+    fn receive<T: PacketDesc, F: Fn(T) -> BypassResult<T>>(
+        &mut self,
+        datagram: &[u8],
+        ack_channel: &mpsc::UnboundedSender<(u32, isize, i64)>,
+        channel: &UnboundedSender<T>,
+        to_sender: &PacketSender<T>,
+        bypass: &F,
+    ) -> bool {
+        if let Some(capture) = &self.capture {
+            capture.record(Direction::Received, datagram);
+        }
+        self.stats.on_received(datagram.len());
+        let (p, data) = match PacketHeader::deserialize(datagram) {
+            Ok((p, data)) => (p, data),
+            Err(e) => {
+                warn!("Error deserializing header: {}", e.0);
+                return true;
+            }
+        };
+        if p.is_control() {
+            debug!("Discarded control datagram without a session");
+            return true;
+        }
+        self.handle_datagram(&p, datagram, data, |receiver, p, data| {
+            receiver.deliver(p, data, ack_channel, channel, to_sender, bypass)
+        })
+    }
+
+    /// Feed the datagram to the FEC decoder, and call `f` with the datagram rebuilt from it, if
+    /// any, then with the datagram itself unless it is parity or an unreliable datagram which was
+    /// rebuilt already. Reliable ones are still passed, as they have to be acknowledged again.
+    /// Return false as soon as `f` does.
+    pub(crate) fn handle_datagram<F: FnMut(&mut Self, &PacketHeader, &[u8]) -> bool>(
+        &mut self,
+        p: &PacketHeader,
+        datagram: &[u8],
+        data: &[u8],
+        mut f: F,
+    ) -> bool {
+        if self.recover(p, datagram) {
+            self.stats.on_recovered();
+            let recovered = std::mem::take(&mut self.recovered);
+            let (p, data) = PacketHeader::deserialize(&recovered).unwrap();
+            let open = f(self, &p, data);
+            self.recovered = recovered;
+            if !open {
+                return false;
+            }
+        }
+        if p.is_parity() {
+            return true;
+        }
+        let rebuilt = matches!(&self.fec, Some(fec) if fec.rebuilt(p.generation));
+        if p.slot == 0 && rebuilt {
+            trace_event!(
+                debug,
+                id = p.id,
+                slot = p.slot,
+                generation = p.generation,
+                reason = "rebuilt",
+                "dropped"
+            );
+            return true;
+        }
+        f(self, p, data)
+    }
+
+    /// Feed the datagram to the FEC decoder, return true if it rebuilt a lost datagram into
+    /// `recovered`.
+    fn recover(&mut self, p: &PacketHeader, datagram: &[u8]) -> bool {
+        if p.is_parity() {
+            let payload = &datagram[HEADER_LEN..];
+            self.fec
+                .get_or_insert_with(Decoder::default)
+                .on_parity(p, payload, &mut self.recovered)
+        } else if p.slot >= 0 {
+            match &mut self.fec {
+                Some(fec) => fec.on_datagram(p.generation, datagram, &mut self.recovered),
+                None => false,
+            }
+        } else {
+            false
+        }
+    }
+
+    /// Acknowledge and handle the packet, and pass it on. Return false if the channel is closed.
+    fn deliver<T: PacketDesc, F: Fn(T) -> BypassResult<T>>(
+        &mut self,
+        p: &PacketHeader,
+        data: &[u8],
+        ack_channel: &mpsc::UnboundedSender<(u32, isize, i64)>,
+        channel: &UnboundedSender<T>,
+        to_sender: &PacketSender<T>,
+        bypass: &F,
+    ) -> bool {
+        let p = if p.slot > 0 && ack_channel.send((p.id, -p.slot, p.generation)).is_err() {
+            None
+        } else {
+            self.handle_packet(p, data)
+        };
+        match p.map(bypass) {
+            Some(BypassResult::ToSender(p)) => to_sender.unbounded_send(p).is_ok(),
+            Some(BypassResult::ToUser(p)) => channel.unbounded_send(p).is_ok(),
+            _ => true,
+        }
+    }
 }
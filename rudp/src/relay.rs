@@ -0,0 +1,224 @@
+//! Relay server for peers which could not connect directly.
+//!
+//! Both peers bind to the relay with the same session token. After both are bound, the relay
+//! forwards every datagram from one peer to the other without decoding it, so the handshake and
+//! the rudp protocol work as if the peers were connected directly.
+//!
+//! Control messages are prefixed by `PREFIX` and a type byte, the token is prefixed by its length
+//! in one byte.
+use super::delivery::PacketSender;
+use super::hand_shake::{client_handshake, server_accept};
+use super::protocol::PacketDesc;
+use super::receiver::BypassResult;
+use super::rendezvous::{self, Role};
+use super::stats::{ConnectionStats, Path};
+use super::{start_udp_loop, Config};
+use futures::{channel::mpsc::UnboundedReceiver, future::FutureExt, select};
+use log::{info, warn};
+use std::{collections::HashMap, io, net::SocketAddr, sync::Arc};
+use tokio::{
+    net::UdpSocket,
+    time::{sleep, timeout_at, Duration, Instant},
+};
+
+const PREFIX: &[u8] = b"RUDPRL";
+const BIND: u8 = 1;
+const BOUND: u8 = 2;
+/// Routes and waiting peers would be forgotten after being idle for this duration.
+const IDLE_LIFETIME: Duration = Duration::from_secs(60);
+const RESEND_INTERVAL: Duration = Duration::from_millis(500);
+
+fn bind_message(token: &str) -> Vec<u8> {
+    let token = &token.as_bytes()[..token.len().min(u8::MAX as usize)];
+    let mut buffer = PREFIX.to_vec();
+    buffer.push(BIND);
+    buffer.push(token.len() as u8);
+    buffer.extend_from_slice(token);
+    buffer
+}
+
+fn bound_message(role: Role) -> Vec<u8> {
+    let mut buffer = PREFIX.to_vec();
+    buffer.push(BOUND);
+    buffer.push(match role {
+        Role::Host => 0,
+        Role::Guest => 1,
+    });
+    buffer
+}
+
+fn parse_bind(data: &[u8]) -> Option<&[u8]> {
+    let data = data.strip_prefix(PREFIX)?;
+    let (&kind, data) = data.split_first()?;
+    let (&len, token) = data.split_first()?;
+    if kind == BIND && token.len() == len as usize {
+        Some(token)
+    } else {
+        None
+    }
+}
+
+fn parse_bound(data: &[u8]) -> Option<Role> {
+    match data.strip_prefix(PREFIX)? {
+        [BOUND, 0] => Some(Role::Host),
+        [BOUND, 1] => Some(Role::Guest),
+        _ => None,
+    }
+}
+
+struct Route {
+    peer: SocketAddr,
+    role: Role,
+    last_active: Instant,
+}
+
+/// Run the relay server. Only returns if the socket failed.
+pub async fn serve(bind: &str) -> io::Result<()> {
+    const CAPACITY: usize = 2048;
+    let socket = UdpSocket::bind(bind).await?;
+    info!("Relay server listening on {}", socket.local_addr()?);
+    let mut buffer = vec![0u8; CAPACITY];
+    let mut waiting: HashMap<Vec<u8>, (SocketAddr, Instant)> = HashMap::new();
+    let mut routes: HashMap<SocketAddr, Route> = HashMap::new();
+    let mut last_sweep = Instant::now();
+    loop {
+        let (len, from) = socket.recv_from(&mut buffer).await?;
+        let now = Instant::now();
+        if now.duration_since(last_sweep) > IDLE_LIFETIME {
+            last_sweep = now;
+            waiting.retain(|_, (_, time)| now.duration_since(*time) < IDLE_LIFETIME);
+            routes.retain(|_, route| now.duration_since(route.last_active) < IDLE_LIFETIME);
+        }
+        let data = &buffer[..len];
+        if let Some(token) = parse_bind(data) {
+            if let Some(route) = routes.get(&from) {
+                // the peer did not get our answer
+                if let Err(e) = socket.send_to(&bound_message(route.role), from).await {
+                    warn!("Error answering {}: {}", from, e);
+                }
+                continue;
+            }
+            match waiting.get(token) {
+                Some(&(host, _)) if host != from => {
+                    waiting.remove(token);
+                    info!("Relaying between {} and {}", host, from);
+                    for &(a, b, role) in
+                        [(host, from, Role::Host), (from, host, Role::Guest)].iter()
+                    {
+                        routes.insert(
+                            a,
+                            Route {
+                                peer: b,
+                                role,
+                                last_active: now,
+                            },
+                        );
+                        if let Err(e) = socket.send_to(&bound_message(role), a).await {
+                            warn!("Error answering {}: {}", a, e);
+                        }
+                    }
+                }
+                _ => {
+                    waiting.insert(token.to_vec(), (from, now));
+                }
+            }
+        } else if let Some(route) = routes.get_mut(&from) {
+            route.last_active = now;
+            let peer = route.peer;
+            if let Err(e) = socket.send_to(data, peer).await {
+                warn!("Error forwarding to {}: {}", peer, e);
+            }
+        }
+    }
+}
+
+/// Bind to the relay with the token until the other peer bound too, and perform the normal
+/// handshake through the relay, all within `time_limit`. Returns a socket connected to the relay,
+/// and the role of this peer in the handshake.
+pub async fn connect(
+    bind: &str,
+    relay: SocketAddr,
+    token: &str,
+    magic: &[u8],
+    time_limit: Duration,
+) -> io::Result<(UdpSocket, Role)> {
+    const CAPACITY: usize = 1024;
+    let deadline = Instant::now() + time_limit;
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(relay).await?;
+    let message = bind_message(token);
+    let mut buffer = vec![0u8; CAPACITY];
+    let f = async {
+        loop {
+            socket.send(&message).await?;
+            select! {
+                result = socket.recv(&mut buffer).fuse() => {
+                    if let Some(role) = parse_bound(&buffer[..result?]) {
+                        return Ok::<_, io::Error>(role);
+                    }
+                },
+                _ = sleep(RESEND_INTERVAL).fuse() => (),
+            }
+        }
+    };
+    let role = timeout_at(deadline, f)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "binding to relay timed out"))??;
+    info!("Bound to relay {} as {:?}", relay, role);
+    let socket = match role {
+        Role::Host => timeout_at(deadline, server_accept(socket, magic)).await,
+        Role::Guest => timeout_at(deadline, client_handshake(socket, magic)).await,
+    }
+    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "handshake timed out"))?;
+    Ok((socket, role))
+}
+
+/// A connection to the other peer, either direct or through the relay.
+#[derive(Debug)]
+pub struct Connection {
+    pub socket: UdpSocket,
+    pub role: Role,
+    pub path: Path,
+}
+
+impl Connection {
+    /// Start the loop with `start_udp_loop`, with the path of the connection in the `Config` so
+    /// the stats report it.
+    pub fn start<
+        T: PacketDesc + Send + Sync + 'static,
+        F: Fn(T) -> BypassResult<T> + Send + Sync + 'static,
+    >(
+        self,
+        mut config: Config,
+        bypass: F,
+    ) -> (PacketSender<T>, UnboundedReceiver<T>, Arc<ConnectionStats>) {
+        config.path = self.path;
+        start_udp_loop(self.socket, config, bypass)
+    }
+}
+
+/// Try hole punching through the rendezvous server within half of the time limit, and fall back
+/// to the relay for the rest of it. The session code is used as the relay token as well.
+pub async fn connect_with_fallback(
+    bind: &str,
+    rendezvous_server: SocketAddr,
+    relay: SocketAddr,
+    code: &str,
+    magic: &[u8],
+    time_limit: Duration,
+) -> io::Result<Connection> {
+    let deadline = Instant::now() + time_limit;
+    let ((socket, role), path) =
+        match rendezvous::connect(bind, rendezvous_server, code, magic, time_limit / 2).await {
+            Ok(connected) => (connected, Path::Direct),
+            Err(e) => {
+                warn!("Direct connection failed ({}), falling back to relay", e);
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                (
+                    connect(bind, relay, code, magic, remaining).await?,
+                    Path::Relayed,
+                )
+            }
+        };
+    Ok(Connection { socket, role, path })
+}
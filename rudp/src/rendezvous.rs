@@ -0,0 +1,327 @@
+//! UDP hole punching through a rendezvous server.
+//!
+//! Both peers register at the rendezvous server with the same session code, and their private
+//! endpoint (the address of the local socket). The server observes their public endpoint, and
+//! tells each peer the endpoints of the other one after both registered. The peers then send punch
+//! messages to both endpoints of each other at the same time, which opens the mapping in their
+//! NAT, and continue with the normal handshake on the same socket. The peer registered first
+//! becomes the server of the handshake.
+//!
+//! Messages are prefixed by `PREFIX` and a type byte, strings and addresses are prefixed by their
+//! length in one byte.
+use super::hand_shake::{client_handshake, server_accept};
+use futures::{future::FutureExt, select};
+use log::{info, warn};
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+use tokio::{
+    net::UdpSocket,
+    time::{sleep, timeout_at, Duration, Instant},
+};
+
+const PREFIX: &[u8] = b"RUDPRV";
+const REGISTER: u8 = 1;
+const PEER: u8 = 2;
+const PUNCH: u8 = 3;
+const PUNCH_ACK: u8 = 4;
+/// Registrations would be forgotten after this duration.
+const SESSION_LIFETIME: Duration = Duration::from_secs(60);
+const RESEND_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Role in the handshake after punching.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Registered first, would wait for the handshake.
+    Host,
+    /// Registered second, would start the handshake.
+    Guest,
+}
+
+/// Endpoints of the other peer, as told by the rendezvous server.
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    pub role: Role,
+    pub public: SocketAddr,
+    pub private: SocketAddr,
+}
+
+enum Message {
+    Register { code: String, private: SocketAddr },
+    Peer(PeerInfo),
+    Punch { code: String },
+    PunchAck { code: String },
+}
+
+fn push_str(buffer: &mut Vec<u8>, s: &str) {
+    let bytes = s.as_bytes();
+    let len = bytes.len().min(u8::MAX as usize);
+    buffer.push(len as u8);
+    buffer.extend_from_slice(&bytes[..len]);
+}
+
+fn read_str<'a>(data: &mut &'a [u8]) -> Option<&'a str> {
+    let (&len, rest) = data.split_first()?;
+    if rest.len() < len as usize {
+        return None;
+    }
+    let (s, rest) = rest.split_at(len as usize);
+    *data = rest;
+    std::str::from_utf8(s).ok()
+}
+
+fn read_addr(data: &mut &[u8]) -> Option<SocketAddr> {
+    SocketAddr::from_str(read_str(data)?).ok()
+}
+
+impl Message {
+    fn serialize(&self) -> Vec<u8> {
+        let mut buffer = PREFIX.to_vec();
+        match self {
+            Message::Register { code, private } => {
+                buffer.push(REGISTER);
+                push_str(&mut buffer, code);
+                push_str(&mut buffer, &private.to_string());
+            }
+            Message::Peer(info) => {
+                buffer.push(PEER);
+                buffer.push(match info.role {
+                    Role::Host => 0,
+                    Role::Guest => 1,
+                });
+                push_str(&mut buffer, &info.public.to_string());
+                push_str(&mut buffer, &info.private.to_string());
+            }
+            Message::Punch { code } => {
+                buffer.push(PUNCH);
+                push_str(&mut buffer, code);
+            }
+            Message::PunchAck { code } => {
+                buffer.push(PUNCH_ACK);
+                push_str(&mut buffer, code);
+            }
+        }
+        buffer
+    }
+
+    fn deserialize(data: &[u8]) -> Option<Self> {
+        if data.len() <= PREFIX.len() || &data[..PREFIX.len()] != PREFIX {
+            return None;
+        }
+        let kind = data[PREFIX.len()];
+        let mut data = &data[PREFIX.len() + 1..];
+        match kind {
+            REGISTER => {
+                let code = read_str(&mut data)?.to_string();
+                let private = read_addr(&mut data)?;
+                Some(Message::Register { code, private })
+            }
+            PEER => {
+                let (&role, rest) = data.split_first()?;
+                data = rest;
+                let role = if role == 0 { Role::Host } else { Role::Guest };
+                let public = read_addr(&mut data)?;
+                let private = read_addr(&mut data)?;
+                Some(Message::Peer(PeerInfo {
+                    role,
+                    public,
+                    private,
+                }))
+            }
+            PUNCH => Some(Message::Punch {
+                code: read_str(&mut data)?.to_string(),
+            }),
+            PUNCH_ACK => Some(Message::PunchAck {
+                code: read_str(&mut data)?.to_string(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+struct Registration {
+    public: SocketAddr,
+    private: SocketAddr,
+    time: Instant,
+}
+
+/// Run the rendezvous server. Only returns if the socket failed.
+pub async fn serve(bind: &str) -> io::Result<()> {
+    const CAPACITY: usize = 1024;
+    let socket = UdpSocket::bind(bind).await?;
+    info!("Rendezvous server listening on {}", socket.local_addr()?);
+    let mut buffer = vec![0u8; CAPACITY];
+    // session code -> registered peers, in registration order
+    let mut sessions: HashMap<String, Vec<Registration>> = HashMap::new();
+    loop {
+        let (len, from) = socket.recv_from(&mut buffer).await?;
+        let (code, private) = match Message::deserialize(&buffer[..len]) {
+            Some(Message::Register { code, private }) => (code, private),
+            _ => continue,
+        };
+        let now = Instant::now();
+        sessions.retain(|_, peers| {
+            peers.retain(|p| now.duration_since(p.time) < SESSION_LIFETIME);
+            !peers.is_empty()
+        });
+        let peers = sessions.entry(code).or_default();
+        if let Some(peer) = peers.iter_mut().find(|p| p.public == from) {
+            peer.private = private;
+            peer.time = now;
+        } else if peers.len() < 2 {
+            peers.push(Registration {
+                public: from,
+                private,
+                time: now,
+            });
+        } else {
+            warn!("Session is full, ignoring registration from {}", from);
+            continue;
+        }
+        if peers.len() == 2 {
+            // answer both, so the one registered first would not wait for its next attempt
+            for (i, role) in [(0, Role::Host), (1, Role::Guest)].iter() {
+                let other = &peers[1 - i];
+                let message = Message::Peer(PeerInfo {
+                    role: *role,
+                    public: other.public,
+                    private: other.private,
+                });
+                let to = peers[*i].public;
+                if let Err(e) = socket.send_to(&message.serialize(), to).await {
+                    warn!("Error sending the peer to {}: {}", to, e);
+                }
+            }
+        }
+    }
+}
+
+/// Find the address other hosts could use to reach the socket in the local network, by checking
+/// which local address would be used to reach the server.
+async fn private_endpoint(socket: &UdpSocket, server: SocketAddr) -> io::Result<SocketAddr> {
+    let local = socket.local_addr()?;
+    if !local.ip().is_unspecified() {
+        return Ok(local);
+    }
+    let bind: SocketAddr = match server.ip() {
+        IpAddr::V4(_) => "0.0.0.0:0".parse().unwrap(),
+        IpAddr::V6(_) => "[::]:0".parse().unwrap(),
+    };
+    let probe = UdpSocket::bind(bind).await?;
+    probe.connect(server).await?;
+    Ok(SocketAddr::new(probe.local_addr()?.ip(), local.port()))
+}
+
+/// Register at the rendezvous server until the other peer registered too.
+pub async fn register(socket: &UdpSocket, server: SocketAddr, code: &str) -> io::Result<PeerInfo> {
+    const CAPACITY: usize = 1024;
+    let private = private_endpoint(socket, server).await?;
+    let register = Message::Register {
+        code: code.to_string(),
+        private,
+    }
+    .serialize();
+    let mut buffer = vec![0u8; CAPACITY];
+    loop {
+        socket.send_to(&register, server).await?;
+        select! {
+            result = socket.recv_from(&mut buffer).fuse() => {
+                let (len, from) = result?;
+                if from != server {
+                    continue;
+                }
+                if let Some(Message::Peer(info)) = Message::deserialize(&buffer[..len]) {
+                    return Ok(info);
+                }
+            },
+            _ = sleep(RESEND_INTERVAL * 5).fuse() => (),
+        }
+    }
+}
+
+/// Send punch messages to both endpoints of the peer until one of them answers, return the
+/// endpoint that works.
+pub async fn punch(socket: &UdpSocket, peer: &PeerInfo, code: &str) -> io::Result<SocketAddr> {
+    const CAPACITY: usize = 1024;
+    let punch = Message::Punch {
+        code: code.to_string(),
+    }
+    .serialize();
+    let ack = Message::PunchAck {
+        code: code.to_string(),
+    }
+    .serialize();
+    let candidates = if peer.public == peer.private {
+        vec![peer.public]
+    } else {
+        vec![peer.private, peer.public]
+    };
+    let mut buffer = vec![0u8; CAPACITY];
+    loop {
+        for &candidate in candidates.iter() {
+            socket.send_to(&punch, candidate).await?;
+        }
+        select! {
+            result = socket.recv_from(&mut buffer).fuse() => {
+                let (len, from) = result?;
+                if !candidates.contains(&from) {
+                    continue;
+                }
+                match Message::deserialize(&buffer[..len]) {
+                    Some(Message::Punch { code: c }) if c == code => {
+                        // the peer keeps punching until it gets the ACK, but we would stop
+                        // answering after leaving, so send a few copies in case of packet loss
+                        for _ in 0..3 {
+                            socket.send_to(&ack, from).await?;
+                        }
+                        return Ok(from);
+                    }
+                    Some(Message::PunchAck { code: c }) if c == code => {
+                        return Ok(from);
+                    }
+                    _ => (),
+                }
+            },
+            _ = sleep(RESEND_INTERVAL).fuse() => (),
+        }
+    }
+}
+
+/// Connect to the peer registered with the same session code, and perform the normal handshake,
+/// all within `time_limit`. Returns a socket connected to the peer, and the role of this peer in
+/// the handshake.
+pub async fn connect(
+    bind: &str,
+    server: SocketAddr,
+    code: &str,
+    magic: &[u8],
+    time_limit: Duration,
+) -> io::Result<(UdpSocket, Role)> {
+    let deadline = Instant::now() + time_limit;
+    let socket = UdpSocket::bind(bind).await?;
+    let f = async {
+        let peer = register(&socket, server, code).await?;
+        info!(
+            "Peer endpoints: public {}, private {}",
+            peer.public, peer.private
+        );
+        let address = punch(&socket, &peer, code).await?;
+        info!("Punched through to {}", address);
+        Ok::<_, io::Error>((peer.role, address))
+    };
+    let (role, address) = timeout_at(deadline, f)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "hole punching timed out"))??;
+    let socket = match role {
+        Role::Host => timeout_at(deadline, server_accept(socket, magic)).await,
+        Role::Guest => {
+            socket.connect(address).await?;
+            timeout_at(deadline, client_handshake(socket, magic)).await
+        }
+    }
+    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "handshake timed out"))?;
+    Ok((socket, role))
+}
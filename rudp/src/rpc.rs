@@ -0,0 +1,166 @@
+//! Request/response calls on top of the UDP loop.
+//!
+//! A request variant carries a correlation ID in the field marked `#[packet(correlation)]`, and
+//! the response variant is marked with `#[packet(response_to = "Request")]` and carries the same
+//! correlation ID. `Rpc::call` assigns the ID and waits for the response, which is matched by
+//! passing every received packet through `Rpc::dispatch`, usually in the bypass function:
+//! ```ignore
+//! let rpc = Arc::new(Rpc::new());
+//! let dispatch = rpc.clone();
+//! let (send, recv, _) = start_udp_loop(socket, config, move |p| match dispatch.dispatch(p) {
+//!     Some(Packet::Add { a, b, call }) => {
+//!         BypassResult::ToSender(Packet::Sum { sum: a + b, call })
+//!     }
+//!     Some(p) => BypassResult::ToUser(p),
+//!     None => BypassResult::Discard,
+//! });
+//! let response = rpc.call(&send, request, CallOptions::default()).await;
+//! ```
+use super::{delivery::PacketSender, protocol::PacketDesc};
+use futures::channel::oneshot;
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+use tokio::time::{timeout, Duration};
+
+#[derive(Clone, Copy, Debug)]
+pub struct CallOptions {
+    /// Time to wait for the response before sending the request again.
+    pub timeout: Duration,
+    /// Number of times the request is sent again before giving up.
+    pub retries: u32,
+}
+
+impl Default for CallOptions {
+    fn default() -> Self {
+        CallOptions {
+            timeout: Duration::from_millis(500),
+            retries: 3,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RpcError {
+    /// No response after all retries.
+    Timeout,
+    /// The UDP loop stopped.
+    ConnectionClosed,
+    /// The request has no field for the correlation ID.
+    NoCorrelation,
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Timeout => write!(f, "no response"),
+            RpcError::ConnectionClosed => write!(f, "the connection is closed"),
+            RpcError::NoCorrelation => write!(f, "the request carries no correlation ID"),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+/// Pending calls of one connection.
+pub struct Rpc<T> {
+    next_id: AtomicU64,
+    // correlation ID -> (request packet ID, waiting call)
+    pending: Mutex<HashMap<u64, (u32, oneshot::Sender<T>)>>,
+}
+
+/// Remove the pending call when the call returns or is dropped.
+struct PendingGuard<'a, T> {
+    rpc: &'a Rpc<T>,
+    id: u64,
+}
+
+impl<'a, T> Drop for PendingGuard<'a, T> {
+    fn drop(&mut self) {
+        self.rpc.pending.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl<T: PacketDesc> Default for Rpc<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: PacketDesc> Rpc<T> {
+    pub fn new() -> Self {
+        Rpc {
+            next_id: AtomicU64::new(1),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Send the request and wait for its response. The request is sent again with the same
+    /// correlation ID if there is no response within the timeout, so the remote may handle it
+    /// more than once.
+    pub async fn call(
+        &self,
+        sender: &PacketSender<T>,
+        mut request: T,
+        options: CallOptions,
+    ) -> Result<T, RpcError>
+    where
+        T: Clone,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        request.set_correlation(id);
+        if request.correlation() != Some(id) {
+            return Err(RpcError::NoCorrelation);
+        }
+        let (result, mut response) = oneshot::channel();
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(id, (request.id(), result));
+        let _guard = PendingGuard { rpc: self, id };
+        for _ in 0..=options.retries {
+            if sender.unbounded_send(request.clone()).is_err() {
+                return Err(RpcError::ConnectionClosed);
+            }
+            match timeout(options.timeout, &mut response).await {
+                Ok(Ok(response)) => return Ok(response),
+                Ok(Err(_)) => return Err(RpcError::ConnectionClosed),
+                Err(_) => (),
+            }
+        }
+        Err(RpcError::Timeout)
+    }
+
+    /// Complete the pending call if the packet is its response, otherwise return the packet.
+    /// Responses which match no pending call, for example a second response to a request sent
+    /// again, are returned as well.
+    pub fn dispatch(&self, packet: T) -> Option<T> {
+        let (request_id, correlation) = match (T::response_to(packet.id()), packet.correlation()) {
+            (Some(request_id), Some(correlation)) => (request_id, correlation),
+            _ => return Some(packet),
+        };
+        let mut pending = self.pending.lock().unwrap();
+        match pending.get(&correlation) {
+            Some(&(id, _)) if id == request_id => {
+                let (_, result) = pending.remove(&correlation).unwrap();
+                // the call may have been dropped
+                let _ = result.send(packet);
+                None
+            }
+            _ => Some(packet),
+        }
+    }
+}
+
+/// Copy the correlation ID of the request into the response.
+pub fn respond<T: PacketDesc>(request: &T, mut response: T) -> T {
+    if let Some(id) = request.correlation() {
+        response.set_correlation(id);
+    }
+    response
+}
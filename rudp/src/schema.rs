@@ -0,0 +1,158 @@
+//! Description of the packets, generated by `#[derive(PacketDesc)]` and returned by
+//! `PacketDesc::schema`.
+//!
+//! The schema lists every variant with its ID, delivery flags, and the names and types of its
+//! fields, as written in the source. `Schema::hash` is a stable hash of everything affecting the
+//! wire format, exchanged during the handshake by `hand_shake::schema_magic`, so that builds with
+//! different packets refuse each other instead of failing to decode. `Schema::to_json` dumps the
+//! schema for documentation and dissectors.
+use super::bitpack::Quantization;
+use std::fmt::Write;
+
+pub struct Schema {
+    /// Name of the enum.
+    pub name: &'static str,
+    /// The codec as written in `#[packet(codec = ...)]`, `cbor` by default.
+    pub codec: &'static str,
+    /// The variants in declaration order.
+    pub packets: &'static [PacketSchema],
+}
+
+pub struct PacketSchema {
+    pub name: &'static str,
+    pub id: u32,
+    pub reliable: bool,
+    pub ordered: bool,
+    pub compress: bool,
+    pub state: bool,
+    /// The fields in declaration order, tuple fields are named by their index.
+    pub fields: &'static [FieldSchema],
+}
+
+pub struct FieldSchema {
+    pub name: &'static str,
+    /// The type as written in the source, without whitespace where it is not needed.
+    pub ty: &'static str,
+    /// Set for the fields bit-packed with `#[quantize(...)]`, see `bitpack`.
+    pub quantize: Option<Quantization>,
+}
+
+impl Schema {
+    /// 64-bit FNV-1a of the codec and the packets. The name of the enum does not change the hash.
+    pub fn hash(&self) -> u64 {
+        let mut hash = Fnv::new();
+        hash.write_str(self.codec);
+        for packet in self.packets {
+            hash.write_str(packet.name);
+            hash.write(&packet.id.to_be_bytes());
+            hash.write(&[
+                packet.reliable as u8,
+                packet.ordered as u8,
+                packet.compress as u8,
+                packet.state as u8,
+            ]);
+            hash.write(&(packet.fields.len() as u32).to_be_bytes());
+            for field in packet.fields {
+                hash.write_str(field.name);
+                hash.write_str(field.ty);
+                if let Some(quantization) = field.quantize {
+                    hash.write(&quantization.min.to_bits().to_be_bytes());
+                    hash.write(&quantization.max.to_bits().to_be_bytes());
+                    hash.write(&quantization.bits.to_be_bytes());
+                }
+            }
+        }
+        hash.0
+    }
+
+    /// The schema as pretty printed JSON, with the hash as 16 hex digits.
+    pub fn to_json(&self) -> String {
+        let packets: Vec<String> = self
+            .packets
+            .iter()
+            .map(|packet| {
+                let fields: Vec<String> = packet
+                    .fields
+                    .iter()
+                    .map(|field| {
+                        let quantize = match field.quantize {
+                            Some(q) => format!(
+                                ", \"quantize\": {{ \"min\": {:?}, \"max\": {:?}, \"bits\": {} }}",
+                                q.min, q.max, q.bits
+                            ),
+                            None => String::new(),
+                        };
+                        format!(
+                            "\n        {{ \"name\": {}, \"type\": {}{} }}",
+                            quoted(field.name),
+                            quoted(field.ty),
+                            quantize
+                        )
+                    })
+                    .collect();
+                let mut json = String::from("\n    {\n");
+                let _ = writeln!(json, "      \"name\": {},", quoted(packet.name));
+                let _ = writeln!(json, "      \"id\": {},", packet.id);
+                let _ = writeln!(json, "      \"reliable\": {},", packet.reliable);
+                let _ = writeln!(json, "      \"ordered\": {},", packet.ordered);
+                let _ = writeln!(json, "      \"compress\": {},", packet.compress);
+                let _ = writeln!(json, "      \"state\": {},", packet.state);
+                let _ = write!(json, "      \"fields\": [{}", fields.join(","));
+                if !fields.is_empty() {
+                    json.push_str("\n      ");
+                }
+                json.push_str("]\n    }");
+                json
+            })
+            .collect();
+        let mut json = String::from("{\n");
+        let _ = writeln!(json, "  \"name\": {},", quoted(self.name));
+        let _ = writeln!(json, "  \"codec\": {},", quoted(self.codec));
+        let _ = writeln!(json, "  \"hash\": \"{:016x}\",", self.hash());
+        let _ = write!(json, "  \"packets\": [{}", packets.join(","));
+        if !packets.is_empty() {
+            json.push_str("\n  ");
+        }
+        json.push_str("]\n}\n");
+        json
+    }
+}
+
+/// A JSON string literal.
+fn quoted(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    /// Strings are prefixed by their length, so that moving bytes between them changes the hash.
+    fn write_str(&mut self, s: &str) {
+        self.write(&(s.len() as u32).to_be_bytes());
+        self.write(s.as_bytes());
+    }
+}
@@ -1,20 +1,24 @@
-use super::protocol::{modify_header, PacketDesc, PacketHeader};
+use super::batch::SendBatch;
+use super::capture::{CaptureWriter, Direction};
+use super::delivery::{Delivery, Message, Outgoing, Tracker};
+use super::fec::{Encoder, Redundancy};
+use super::protocol::{modify_header, Compression, PacketDesc, PacketHeader};
+use super::session::Link;
+use super::stats::ConnectionStats;
 use futures::{
-    channel::mpsc::UnboundedReceiver,
     future::{Fuse, FusedFuture, FutureExt},
     pin_mut, select_biased,
-    stream::StreamExt,
 };
 use std::{
-    collections::VecDeque,
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, VecDeque},
     sync::{
         atomic::{AtomicBool, AtomicI64, Ordering},
         Arc,
     },
 };
 use tokio::{
-    net::UdpSocket,
-    sync::Notify,
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
     time::{sleep_until, Duration, Instant, Sleep},
 };
 
@@ -23,27 +27,77 @@ pub struct Sender<T: PacketDesc> {
     retry_max: u32,
     generation: i64,
     timeout: Duration,
-    inner: Arc<UdpSocket>,
+    inner: Arc<Link>,
     slots_generation: Arc<Vec<AtomicI64>>,
     slots_used: Arc<Vec<AtomicBool>>,
-    notify: Arc<Notify>,
-    queue: VecDeque<T>,
-    used_queue: VecDeque<usize>,
+    // the receiver reports the slots freed by ACKs
+    freed: UnboundedSender<usize>,
+    acked: Option<UnboundedReceiver<usize>>,
+    // slots which could be filled, the lowest one last
+    free: Vec<usize>,
+    // retransmission deadline, slot and generation of the packets in flight, the entries of
+    // acknowledged packets are dropped when they reach the top, or all at once before there are
+    // twice as many entries as slots
+    retransmits: BinaryHeap<Reverse<(Instant, usize, i64)>>,
+    queue: VecDeque<Outgoing<T>>,
+    // no receipt in the queue or held back expires before this
+    queue_deadline: Option<Instant>,
+    trackers: Vec<Option<Tracker>>,
+    // receipt deadline, slot and generation of the tracked packets in flight
+    expiries: BinaryHeap<Reverse<(Instant, usize, i64)>>,
+    capture: Option<Arc<CaptureWriter>>,
+    stats: Arc<ConnectionStats>,
+    compression: Compression,
+    state_interval: Duration,
+    // ID of state packets -> last time one was passed on
+    state_sent: HashMap<u32, Instant>,
+    // state packets held back by `state_interval`, only the newest one for each ID
+    held: HashMap<u32, Outgoing<T>>,
+    // unreliable packets to be sent
+    ready: Vec<Outgoing<T>>,
+    fec: Encoder,
+    // reused for compressing and for the parity, so that sending does not allocate
+    scratch: Vec<u8>,
+    parity: Vec<u8>,
+    // datagrams written when the loop would wait or the batch is full
+    outbox: SendBatch,
+    // first send of the packet in each slot, cleared when it is resent as its ACK would not give
+    // an RTT sample
+    #[cfg(feature = "tracing")]
+    sent_at: Vec<Option<Instant>>,
 }
 
-struct Slot(Vec<u8>, Instant);
+struct Slot(Vec<u8>);
+
+/// How the packets are encoded and paced, taken from the `Config`.
+pub struct SendSettings {
+    pub compression: Compression,
+    pub state_interval: Duration,
+    pub redundancy: Redundancy,
+}
 
 impl<T: PacketDesc> Sender<T> {
-    pub fn new(inner: Arc<UdpSocket>, timeout: Duration, capacity: usize, retry_max: u32) -> Self {
+    pub fn new(
+        inner: Arc<Link>,
+        timeout: Duration,
+        capacity: usize,
+        retry_max: u32,
+        capture: Option<Arc<CaptureWriter>>,
+        stats: Arc<ConnectionStats>,
+        settings: SendSettings,
+    ) -> Self {
         let mut slots_generation = Vec::with_capacity(capacity);
         let mut slots_used = Vec::with_capacity(capacity);
+        let mut trackers = Vec::with_capacity(capacity);
         for _ in 0..capacity {
             slots_generation.push(AtomicI64::new(0));
             slots_used.push(AtomicBool::new(false));
+            trackers.push(None);
         }
         let slots_generation = Arc::new(slots_generation);
         let slots_used = Arc::new(slots_used);
-        let notify = Arc::new(Notify::new());
+        let (freed, acked) = mpsc::unbounded_channel();
+        let outbox = SendBatch::new(inner.batch_size());
         Sender {
             retry_count: 0,
             retry_max,
@@ -52,14 +106,32 @@ impl<T: PacketDesc> Sender<T> {
             inner,
             slots_generation,
             slots_used,
-            notify,
+            freed,
+            acked: Some(acked),
+            free: (0..capacity).rev().collect(),
+            retransmits: BinaryHeap::with_capacity(2 * capacity),
             queue: VecDeque::new(),
-            used_queue: VecDeque::with_capacity(capacity),
+            queue_deadline: None,
+            trackers,
+            expiries: BinaryHeap::new(),
+            capture,
+            stats,
+            compression: settings.compression,
+            state_interval: settings.state_interval,
+            state_sent: HashMap::new(),
+            held: HashMap::new(),
+            ready: Vec::new(),
+            fec: Encoder::new(settings.redundancy),
+            scratch: Vec::new(),
+            parity: Vec::new(),
+            outbox,
+            #[cfg(feature = "tracing")]
+            sent_at: vec![None; capacity],
         }
     }
 
-    pub fn get_notify(&self) -> Arc<Notify> {
-        self.notify.clone()
+    pub fn get_freed(&self) -> UnboundedSender<usize> {
+        self.freed.clone()
     }
 
     pub fn get_slots_generation(&self) -> Arc<Vec<AtomicI64>> {
@@ -70,144 +142,455 @@ impl<T: PacketDesc> Sender<T> {
         self.slots_used.clone()
     }
 
-    fn get_oldest<'a>(&mut self, slots: &'a mut [Slot]) -> Option<&'a mut Slot> {
-        let mut oldest: Option<&mut Slot> = None;
-        while let Some(&i) = self.used_queue.front() {
-            let used = self.slots_used[i].load(Ordering::Relaxed);
-            if used {
-                oldest = Some(&mut slots[i]);
-                break;
-            } else {
-                self.used_queue.pop_front();
+    pub fn get_capture(&self) -> Option<Arc<CaptureWriter>> {
+        self.capture.clone()
+    }
+
+    pub fn get_stats(&self) -> Arc<ConnectionStats> {
+        self.stats.clone()
+    }
+
+    /// Return if the slot still holds the packet of this generation.
+    fn in_flight(&self, slot: usize, generation: i64) -> bool {
+        self.slots_used[slot].load(Ordering::Acquire)
+            && self.slots_generation[slot].load(Ordering::Relaxed) == generation
+    }
+
+    /// Return the deadline and slot of the next retransmission.
+    fn next_retransmit(&mut self) -> Option<(Instant, usize)> {
+        while let Some(&Reverse((deadline, slot, generation))) = self.retransmits.peek() {
+            if self.in_flight(slot, generation) {
+                return Some((deadline, slot));
             }
+            self.retransmits.pop();
         }
-        oldest
+        None
     }
 
-    fn find_empty_slot(&self) -> Option<usize> {
-        let mut empty = None;
-        for i in 0..self.slots_used.len() {
-            let used = self.slots_used[i].load(Ordering::Acquire);
-            if !used {
-                empty = Some(i);
-                break;
-            }
+    /// Drop the retransmission entries of the packets which are not in flight anymore.
+    fn compact_retransmits(&mut self) {
+        let (used, generations) = (&self.slots_used, &self.slots_generation);
+        self.retransmits.retain(|&Reverse((_, slot, generation))| {
+            used[slot].load(Ordering::Acquire)
+                && generations[slot].load(Ordering::Relaxed) == generation
+        });
+    }
+
+    /// Take back a slot freed by an ACK.
+    fn on_acked(&mut self, slot: usize) {
+        trace_event!(
+            trace,
+            slot = slot + 1,
+            generation = self.slots_generation[slot].load(Ordering::Relaxed),
+            rtt_us = self.sent_at[slot].map(|sent| sent.elapsed().as_micros() as u64),
+            "acked"
+        );
+        if let Some(tracker) = self.trackers[slot].take() {
+            tracker.resolve(Delivery::Delivered);
         }
-        empty
+        self.free.push(slot);
     }
 
     /// Attempt to send the buffer once, return false if send continuously failed. (reaches the max retry)
+    /// The buffer is written with the batch, when it is full or by `flush`.
     async fn send(&mut self, buffer: &[u8]) -> bool {
-        if self.inner.send(&buffer).await.is_ok() {
-            self.retry_count = 0;
+        self.outbox.push(buffer);
+        if self.outbox.is_full() {
+            self.flush().await
+        } else {
             true
+        }
+    }
+
+    /// Write the batch, return false if send continuously failed.
+    async fn flush(&mut self) -> bool {
+        if self.outbox.is_empty() {
+            return true;
+        }
+        let result = self.inner.send_batch(&self.outbox).await;
+        if result.is_ok() {
+            for datagram in self.outbox.iter() {
+                if let Some(capture) = &self.capture {
+                    capture.record(Direction::Sent, datagram);
+                }
+                self.stats.on_sent(datagram.len());
+            }
+            self.retry_count = 0;
         } else {
             self.retry_count += 1;
-            self.retry_count != self.retry_max
         }
+        self.outbox.clear();
+        result.is_ok() || self.retry_count != self.retry_max
+    }
+
+    /// Send a datagram for the first time, followed by the parity datagram if it completed a
+    /// group. Return false if send continuously failed.
+    async fn send_new(&mut self, buffer: &[u8]) -> bool {
+        if !self.send(buffer).await {
+            return false;
+        }
+        if self.fec.add(buffer, &self.stats, &mut self.parity) {
+            self.send_parity().await
+        } else {
+            true
+        }
+    }
+
+    async fn send_parity(&mut self) -> bool {
+        self.stats.on_parity_sent();
+        let parity = std::mem::take(&mut self.parity);
+        let result = self.send(&parity).await;
+        self.parity = parity;
+        result
     }
 
-    fn put_in<'a>(&mut self, slots: &'a mut [Slot], data: T, empty: usize) -> &'a Vec<u8> {
+    /// Send the parity of the current group if it was not completed on time.
+    async fn close_group(&mut self) -> bool {
+        match self.fec.deadline() {
+            Some(deadline) if deadline <= Instant::now() && self.fec.flush(&mut self.parity) => {
+                self.send_parity().await
+            }
+            _ => true,
+        }
+    }
+
+    fn put_in<'a>(
+        &mut self,
+        slots: &'a mut [Slot],
+        data: Outgoing<T>,
+        empty: usize,
+    ) -> &'a Vec<u8> {
         let generation = self.generation;
         self.generation += 1;
+        if let Some(tracker) = &data.tracker {
+            self.expiries
+                .push(Reverse((tracker.deadline, empty, generation)));
+        }
+        self.trackers[empty] = data.tracker;
+        let data = data.message;
         self.slots_used[empty].store(true, Ordering::Relaxed);
         self.slots_generation[empty].store(generation, Ordering::Release);
-        self.used_queue.push_back(empty);
+        if self.retransmits.len() >= 2 * slots.len() {
+            self.compact_retransmits();
+        }
+        self.retransmits
+            .push(Reverse((Instant::now() + self.timeout, empty, generation)));
+        #[cfg(feature = "tracing")]
+        {
+            self.sent_at[empty] = Some(Instant::now());
+        }
+        self.stats.on_reliable_sent();
         slots[empty].0.clear();
         PacketHeader::new(data.id(), empty as isize + 1, generation).serialize(&mut slots[empty].0);
         data.serialize(&mut slots[empty].0);
+        self.compression
+            .apply(data.compress(), &mut slots[empty].0, &mut self.scratch);
+        trace_event!(
+            trace,
+            id = data.id(),
+            slot = empty + 1,
+            generation,
+            size = slots[empty].0.len(),
+            "sent"
+        );
         &slots[empty].0
     }
 
-    fn resend<'a>(&mut self, slots: &'a mut [Slot]) -> Option<&'a Vec<u8>> {
-        let timeout = self.timeout;
-        let oldest = self.get_oldest(slots);
-        match oldest {
-            Some(mut oldest) if oldest.1.elapsed() > timeout => {
-                let index = self.used_queue.pop_front().unwrap();
-                self.used_queue.push_back(index);
-                oldest.1 = Instant::now();
-                Some(&oldest.0)
+    fn resend<'a>(&mut self, slots: &'a [Slot]) -> Option<&'a Vec<u8>> {
+        let now = Instant::now();
+        match self.next_retransmit() {
+            Some((deadline, slot)) if deadline <= now => {
+                let Reverse((_, _, generation)) = self.retransmits.pop().unwrap();
+                self.retransmits
+                    .push(Reverse((now + self.timeout, slot, generation)));
+                self.stats.on_retransmission();
+                trace_event!(
+                    debug,
+                    id = super::trace::id(&slots[slot].0),
+                    slot = slot + 1,
+                    generation,
+                    size = slots[slot].0.len(),
+                    "retransmitted"
+                );
+                #[cfg(feature = "tracing")]
+                {
+                    self.sent_at[slot] = None;
+                }
+                Some(&slots[slot].0)
             }
             _ => None,
         }
     }
 
-    async fn queue(&mut self, data: T) -> Option<T> {
-        if data.reliable() {
+    /// Give up the packets whose receipts expired, returns the earliest deadline of the remaining
+    /// receipts.
+    fn settle(&mut self) -> Option<Instant> {
+        let now = Instant::now();
+        while let Some(&Reverse((deadline, slot, generation))) = self.expiries.peek() {
+            let tracked = self.trackers[slot].is_some()
+                && self.slots_generation[slot].load(Ordering::Relaxed) == generation;
+            if tracked && deadline > now {
+                break;
+            }
+            self.expiries.pop();
+            // if the ACK came first, the slot is taken back by `on_acked` instead, and a late ACK
+            // would be ignored as the slot is not used anymore
+            if tracked
+                && self.slots_used[slot]
+                    .compare_exchange(true, false, Ordering::Release, Ordering::Relaxed)
+                    .is_ok()
+            {
+                trace_event!(debug, slot = slot + 1, generation, "expired");
+                self.trackers[slot]
+                    .take()
+                    .unwrap()
+                    .resolve(Delivery::Expired);
+                self.free.push(slot);
+            }
+        }
+        let mut next = self
+            .expiries
+            .peek()
+            .map(|&Reverse((deadline, _, _))| deadline);
+        if self.queue_deadline.is_some_and(|deadline| deadline <= now) {
+            let mut queued = None;
+            self.queue.retain_mut(|data| match data.tracker.take() {
+                Some(tracker) if tracker.deadline <= now => {
+                    tracker.resolve(Delivery::Expired);
+                    false
+                }
+                tracker => {
+                    if let Some(tracker) = &tracker {
+                        queued = earliest(queued, tracker.deadline);
+                    }
+                    data.tracker = tracker;
+                    true
+                }
+            });
+            // held state packets are given up as well, and are not passed on anymore
+            self.held.retain(|_, data| match data.tracker.take() {
+                Some(tracker) if tracker.deadline <= now => {
+                    tracker.resolve(Delivery::Expired);
+                    false
+                }
+                tracker => {
+                    if let Some(tracker) = &tracker {
+                        queued = earliest(queued, tracker.deadline);
+                    }
+                    data.tracker = tracker;
+                    true
+                }
+            });
+            self.queue_deadline = queued;
+        }
+        if let Some(queued) = self.queue_deadline {
+            next = earliest(next, queued);
+        }
+        next
+    }
+
+    fn queue(&mut self, data: Outgoing<T>) {
+        if data.message.reliable() {
             // slot and generation are just dummy value, would be set to the actual value when we
             // call `put_in`
             self.queue.push_back(data);
-            None
         } else {
-            Some(data)
+            self.ready.push(data);
+        }
+    }
+
+    /// Take a packet from the application. A state packet replaces the unsent packet with the
+    /// same ID, and is held back if the last one with the same ID was passed on within
+    /// `state_interval`. The receipt of a replaced packet resolves to `Expired` right away, and
+    /// the one of a held packet when it expires before the packet is passed on.
+    fn admit(&mut self, data: Outgoing<T>, now: Instant) {
+        if let (true, Some(tracker)) = (data.message.reliable(), &data.tracker) {
+            self.queue_deadline = earliest(self.queue_deadline, tracker.deadline);
+        }
+        if !data.message.state() {
+            self.queue(data);
+            return;
+        }
+        let id = data.message.id();
+        if let Some(&last) = self.state_sent.get(&id) {
+            if now < last + self.state_interval {
+                if let Some(tracker) = &data.tracker {
+                    self.queue_deadline = earliest(self.queue_deadline, tracker.deadline);
+                }
+                if let Some(old) = self.held.insert(id, data) {
+                    old.expire();
+                }
+                return;
+            }
+        }
+        self.state_sent.insert(id, now);
+        let unsent = if data.message.reliable() {
+            self.queue.iter_mut().find(|p| p.message.id() == id)
+        } else {
+            self.ready.iter_mut().find(|p| p.message.id() == id)
+        };
+        match unsent {
+            Some(unsent) => std::mem::replace(unsent, data).expire(),
+            None => self.queue(data),
         }
     }
 
-    fn prepare_unreliable<'a>(&mut self, payload: &'a mut Vec<u8>, packet: &T) -> &'a [u8] {
+    /// Pass on the held state packets whose interval passed, and return the earliest time the
+    /// remaining ones could be passed on.
+    fn release_held(&mut self) -> Option<Instant> {
+        let now = Instant::now();
+        let interval = self.state_interval;
+        while let Some(&id) = self
+            .held
+            .keys()
+            .find(|id| self.state_sent[id] + interval <= now)
+        {
+            let data = self.held.remove(&id).unwrap();
+            self.admit(data, now);
+        }
+        self.held
+            .keys()
+            .map(|id| self.state_sent[id] + interval)
+            .min()
+    }
+
+    /// Send the unreliable packets, return false if send continuously failed.
+    async fn flush_ready(&mut self, payload: &mut Vec<u8>) -> bool {
+        // keep the capacity of the vector for the next packets
+        let mut ready = std::mem::take(&mut self.ready);
+        for p in ready.drain(..) {
+            let datagram = self.prepare_unreliable(payload, &p.message);
+            if !self.send_new(datagram).await {
+                return false;
+            }
+            p.expire();
+        }
+        self.ready = ready;
+        true
+    }
+
+    fn prepare_unreliable<'a>(
+        &mut self,
+        payload: &'a mut Vec<u8>,
+        packet: &Message<T>,
+    ) -> &'a [u8] {
         let generation = self.generation;
         self.generation += 1;
         payload.clear();
         PacketHeader::new(packet.id(), 0, generation).serialize(payload);
         packet.serialize(payload);
+        self.compression
+            .apply(packet.compress(), payload, &mut self.scratch);
+        trace_event!(
+            trace,
+            id = packet.id(),
+            slot = 0,
+            generation,
+            size = payload.len(),
+            "sent"
+        );
         payload
     }
 
     pub async fn send_loop(
         &mut self,
-        channel: &mut UnboundedReceiver<T>,
+        channel: &mut UnboundedReceiver<Outgoing<T>>,
         ack_channel: &mut UnboundedReceiver<(u32, isize, i64)>,
     ) {
         let mut slots = Vec::with_capacity(self.slots_used.len());
-        let now = Instant::now();
         for _ in 0..self.slots_used.len() {
-            slots.push(Slot(Vec::with_capacity(100), now));
+            slots.push(Slot(Vec::with_capacity(100)));
         }
+        let mut acked = self.acked.take().unwrap();
         let mut ack_payload = Vec::new();
         PacketHeader::new(0, 0, 0).serialize(&mut ack_payload);
         let mut unreliable_payload = Vec::with_capacity(100);
 
         let timeout = Fuse::<Sleep>::terminated();
-        let notify = self.get_notify();
-        let got_ack = notify.notified().fuse();
-        let receive = channel.into_future().fuse();
-        let ack_receive = ack_channel.into_future().fuse();
-        pin_mut!(timeout, got_ack, receive, ack_receive);
+        // wakes up for expired receipts, held state packets and incomplete parity groups
+        let expiry = Fuse::<Sleep>::terminated();
+        let mut expiry_deadline = None;
+        pin_mut!(timeout, expiry);
         loop {
+            while let Ok(slot) = acked.try_recv() {
+                self.on_acked(slot);
+            }
+            let deadline = self.settle();
+            let deadline = match self.release_held() {
+                Some(release) => earliest(deadline, release),
+                None => deadline,
+            };
+            if !self.flush_ready(&mut unreliable_payload).await {
+                return;
+            }
+            // a released state packet could be reliable
+            if !self.queue.is_empty() && !self.send_queued(&mut slots).await {
+                return;
+            }
+            if !self.close_group().await {
+                return;
+            }
+            let deadline = match self.fec.deadline() {
+                Some(close) => earliest(deadline, close),
+                None => deadline,
+            };
+            if deadline != expiry_deadline || expiry.is_terminated() {
+                expiry_deadline = deadline;
+                match deadline {
+                    Some(deadline) => expiry.set(sleep_until(deadline).fuse()),
+                    None => expiry.set(Fuse::terminated()),
+                }
+            }
             if timeout.is_terminated() {
-                if let Some(slot) = self.get_oldest(&mut slots) {
-                    let deadline = slot.1 + self.timeout;
+                if let Some((deadline, _)) = self.next_retransmit() {
                     timeout.set(sleep_until(deadline).fuse());
                 }
             }
+            if !self.flush().await {
+                return;
+            }
             select_biased! {
                 _ = timeout => (),
-                _ = got_ack => {
-                    got_ack.set(notify.notified().fuse());
+                _ = expiry => (),
+                slot = acked.recv().fuse() => {
+                    // never closed, as the sender keeps `freed`
+                    if let Some(slot) = slot {
+                        self.on_acked(slot);
+                    }
                 },
-                (mut item, stream) = receive => {
-                    receive.set(stream.into_future().fuse());
+                item = channel.recv().fuse() => {
+                    let now = Instant::now();
                     match item {
-                        Some(p) => if let Some(p) = self.queue(p).await {
-                            let payload = self.prepare_unreliable(&mut unreliable_payload, &p);
-                            if !self.send(payload).await {
-                                return;
-                            }
-                            continue;
-                        },
+                        Some(p) => self.admit(p, now),
                         None => {
                             return;
                         }
                     }
+                    // take everything already waiting, so that state packets are coalesced
+                    while let Ok(p) = channel.try_recv() {
+                        self.admit(p, now);
+                    }
+                    if !self.flush_ready(&mut unreliable_payload).await {
+                        return;
+                    }
+                    if self.queue.is_empty() {
+                        continue;
+                    }
                 },
-                (p, stream) = ack_receive => {
-                    ack_receive.set(stream.into_future().fuse());
+                p = ack_channel.recv().fuse() => {
                     match p {
                         Some(p) => {
                             modify_header(&mut ack_payload, p.0, p.1, p.2);
                             if !self.send(&ack_payload).await {
                                 return;
                             }
+                            // answer everything already received in the same batch
+                            while let Ok(p) = ack_channel.try_recv() {
+                                modify_header(&mut ack_payload, p.0, p.1, p.2);
+                                if !self.send(&ack_payload).await {
+                                    return;
+                                }
+                            }
                             continue;
                         },
                         None => {
@@ -218,25 +601,36 @@ impl<T: PacketDesc> Sender<T> {
             };
 
             // resend all timeout packets
-            if let Some(p) = self.resend(&mut slots) {
-                if !self.send(&p).await {
+            if let Some(p) = self.resend(&slots) {
+                if !self.send(p).await {
                     return;
                 }
                 continue;
             }
-            // send all packets in queue if there is some slot which is empty...
-            while let Some(empty) = self.find_empty_slot() {
-                let p = self.queue.pop_front();
-                if let Some(p) = p {
-                    let p = self.put_in(&mut slots, p, empty).clone();
-                    if !self.send(&p).await {
-                        return;
-                    }
-                    break;
-                } else {
-                    break;
-                }
+            if !self.send_queued(&mut slots).await {
+                return;
             }
         }
     }
+
+    /// Send the packets in queue while there are empty slots, return false if send continuously
+    /// failed.
+    async fn send_queued(&mut self, slots: &mut [Slot]) -> bool {
+        while !self.queue.is_empty() {
+            let empty = match self.free.pop() {
+                Some(empty) => empty,
+                None => break,
+            };
+            let p = self.queue.pop_front().unwrap();
+            let p = self.put_in(slots, p, empty);
+            if !self.send_new(p).await {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+fn earliest(current: Option<Instant>, deadline: Instant) -> Option<Instant> {
+    Some(current.map_or(deadline, |current| current.min(deadline)))
 }
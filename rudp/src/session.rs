@@ -0,0 +1,334 @@
+//! Session resumption.
+//!
+//! A resumable connection carries a session ID, assigned by the server in the handshake (see
+//! `server_accept_resumable` and `client_handshake_resumable`). The server socket is not
+//! connected, so when the client address changes, for example after the Wi-Fi reconnected or the
+//! NAT mapping changed, the client could send `RESUME` with the session ID from the new address
+//! and the server switches to it. As the UDP loop keeps running, the generations, the in-flight
+//! reliable slots and the receive windows are kept as is.
+//!
+//! The client sends `RESUME` every time the server was silent for a quarter of the resumption
+//! window. Within the window, socket errors are ignored instead of counted towards
+//! `Config::max_retry`, after that the session is considered lost and a resumption is rejected.
+//!
+//! Control datagrams use the normal header with the `CONTROL` flag, the ID is the control type
+//! and the generation is the session ID. A loop without a session discards them.
+use super::batch::{RecvBatch, SendBatch};
+use super::protocol::{PacketHeader, CONTROL};
+use log::{debug, info, warn};
+use std::{io, net::SocketAddr, sync::Mutex};
+use tokio::{
+    io::Interest,
+    net::UdpSocket,
+    time::{timeout, Duration, Instant},
+};
+#[cfg(all(target_os = "linux", feature = "batch"))]
+use {
+    super::batch::{sys, BATCH},
+    socket2::SockAddr,
+    std::sync::atomic::{AtomicBool, Ordering},
+};
+
+const RESUME: u32 = 1;
+const RESUMED: u32 = 2;
+
+/// A resumable session, put it into `Config::session` to enable resumption.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Session {
+    pub id: u64,
+    /// Address of the remote when the session started.
+    pub peer: SocketAddr,
+}
+
+fn control_message(kind: u32, id: u64) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    let mut header = PacketHeader::new(kind, 0, id as i64);
+    header.flags |= CONTROL;
+    header.serialize(&mut buffer);
+    buffer
+}
+
+fn parse_control(data: &[u8]) -> Option<(u32, u64)> {
+    match PacketHeader::deserialize(data) {
+        Ok((p, _)) if p.is_control() => Some((p.id, p.generation as u64)),
+        _ => None,
+    }
+}
+
+struct Resumable {
+    id: u64,
+    window: Duration,
+    /// The client has the socket connected to the server, and sends `RESUME` when the server is
+    /// silent. The server accepts `RESUME` from any address.
+    client: bool,
+    peer: Mutex<SocketAddr>,
+    last_received: Mutex<Instant>,
+}
+
+impl Resumable {
+    fn expired(&self) -> bool {
+        self.last_received.lock().unwrap().elapsed() > self.window
+    }
+
+    fn peer(&self) -> SocketAddr {
+        *self.peer.lock().unwrap()
+    }
+}
+
+/// The socket of the UDP loop, which sends to the current address of the remote.
+pub struct Link {
+    socket: UdpSocket,
+    resumable: Option<Resumable>,
+    /// `sendmmsg` and `recvmmsg` are used, see `batch`.
+    #[cfg(all(target_os = "linux", feature = "batch"))]
+    batched: AtomicBool,
+    #[cfg(all(target_os = "linux", feature = "batch"))]
+    gso: AtomicBool,
+}
+
+impl Link {
+    pub fn new(socket: UdpSocket, session: Option<Session>, window: Duration) -> Self {
+        let client = socket.peer_addr().is_ok();
+        Link {
+            #[cfg(all(target_os = "linux", feature = "batch"))]
+            batched: AtomicBool::new(true),
+            #[cfg(all(target_os = "linux", feature = "batch"))]
+            gso: AtomicBool::new(sys::gso_supported(&socket)),
+            socket,
+            resumable: session.map(|session| Resumable {
+                id: session.id,
+                window,
+                client,
+                peer: Mutex::new(session.peer),
+                last_received: Mutex::new(Instant::now()),
+            }),
+        }
+    }
+
+    pub async fn send(&self, buffer: &[u8]) -> io::Result<usize> {
+        let r = match &self.resumable {
+            None => return self.socket.send(buffer).await,
+            Some(r) => r,
+        };
+        let result = if r.client {
+            self.socket.send(buffer).await
+        } else {
+            self.socket.send_to(buffer, r.peer()).await
+        };
+        match result {
+            Err(e) if !r.expired() => {
+                debug!("Error sending data, waiting for resumption: {}", e);
+                Ok(buffer.len())
+            }
+            result => result,
+        }
+    }
+
+    pub async fn recv(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        let r = match &self.resumable {
+            None => return self.socket.recv(buffer).await,
+            Some(r) => r,
+        };
+        loop {
+            let (len, from) = self
+                .read_resumable(r, || self.socket.try_recv_from(buffer))
+                .await?;
+            if self.screen(r, &buffer[..len], from).await {
+                return Ok(len);
+            }
+        }
+    }
+
+    /// Wait until `read` succeeds on a resumable link. The client sends `RESUME` whenever the
+    /// server was silent for a quarter of the window, and errors are ignored within the window.
+    async fn read_resumable<R>(
+        &self,
+        r: &Resumable,
+        mut read: impl FnMut() -> io::Result<R>,
+    ) -> io::Result<R> {
+        loop {
+            let read = self.socket.async_io(Interest::READABLE, &mut read);
+            let result = if r.client {
+                match timeout(r.window / 4, read).await {
+                    Ok(result) => result,
+                    Err(_) => {
+                        self.probe(r).await;
+                        continue;
+                    }
+                }
+            } else {
+                read.await
+            };
+            match result {
+                Err(e) if !r.expired() && !unsupported(&e) => {
+                    debug!("Error receiving data, waiting for resumption: {}", e);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Return if a datagram read by a resumable link is passed on. The server takes one from
+    /// another address as a resumption request, and control datagrams are not passed on.
+    async fn screen(&self, r: &Resumable, data: &[u8], from: SocketAddr) -> bool {
+        if !r.client && from != r.peer() {
+            self.accept_resume(r, data, from).await;
+            return false;
+        }
+        *r.last_received.lock().unwrap() = Instant::now();
+        // `RESUMED`, or `RESUME` from a client which did not move
+        parse_control(data).is_none()
+    }
+
+    /// Number of datagrams the sender should collect before writing them with `send_batch`.
+    pub(crate) fn batch_size(&self) -> usize {
+        #[cfg(all(target_os = "linux", feature = "batch"))]
+        if self.batched.load(Ordering::Relaxed) {
+            return BATCH;
+        }
+        1
+    }
+
+    pub(crate) async fn send_batch(&self, batch: &SendBatch) -> io::Result<()> {
+        #[cfg(all(target_os = "linux", feature = "batch"))]
+        if self.batched.load(Ordering::Relaxed) {
+            match self.send_mmsg(batch).await {
+                Err(e) if unsupported(&e) => {
+                    warn!("Batched send is not supported, falling back: {}", e);
+                    self.batched.store(false, Ordering::Relaxed);
+                }
+                Err(e) if self.resumable.as_ref().is_some_and(|r| !r.expired()) => {
+                    debug!("Error sending data, waiting for resumption: {}", e);
+                    return Ok(());
+                }
+                result => return result,
+            }
+        }
+        for datagram in batch.iter() {
+            self.send(datagram).await?;
+        }
+        Ok(())
+    }
+
+    #[cfg(all(target_os = "linux", feature = "batch"))]
+    async fn send_mmsg(&self, batch: &SendBatch) -> io::Result<()> {
+        // the server socket is not connected
+        let to = match &self.resumable {
+            Some(r) if !r.client => Some(SockAddr::from(r.peer())),
+            _ => None,
+        };
+        let mut from = 0;
+        while from < batch.len() {
+            let gso = self.gso.load(Ordering::Relaxed);
+            let result = self
+                .socket
+                .async_io(Interest::WRITABLE, || {
+                    sys::send(&self.socket, batch, from, gso, to.as_ref())
+                })
+                .await;
+            match result {
+                Ok(sent) => from += sent,
+                // the device or the kernel could not segment the datagrams
+                Err(e) if gso && matches!(e.raw_os_error(), Some(libc::EIO | libc::EINVAL)) => {
+                    warn!("GSO is not supported, falling back: {}", e);
+                    self.gso.store(false, Ordering::Relaxed);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Read at least one datagram into the batch.
+    pub(crate) async fn recv_batch(&self, batch: &mut RecvBatch) -> io::Result<()> {
+        #[cfg(all(target_os = "linux", feature = "batch"))]
+        if self.batched.load(Ordering::Relaxed) {
+            let result = match &self.resumable {
+                None => self
+                    .socket
+                    .async_io(Interest::READABLE, || sys::recv(&self.socket, batch))
+                    .await
+                    .map(|_| ()),
+                Some(r) => self.recv_mmsg_resumable(r, batch).await,
+            };
+            match result {
+                Err(e) if unsupported(&e) => {
+                    warn!("Batched receive is not supported, falling back: {}", e);
+                    self.batched.store(false, Ordering::Relaxed);
+                }
+                result => return result,
+            }
+        }
+        let len = self.recv(batch.first_mut()).await?;
+        batch.set_one(len);
+        Ok(())
+    }
+
+    /// Read at least one datagram to be passed on into the batch, checking each one as `recv`
+    /// does.
+    #[cfg(all(target_os = "linux", feature = "batch"))]
+    async fn recv_mmsg_resumable(&self, r: &Resumable, batch: &mut RecvBatch) -> io::Result<()> {
+        loop {
+            self.read_resumable(r, || sys::recv(&self.socket, batch))
+                .await?;
+            let mut keep = [false; BATCH];
+            for (i, keep) in keep.iter_mut().enumerate().take(batch.len()) {
+                *keep = match batch.from(i) {
+                    Some(from) => self.screen(r, batch.get(i), from).await,
+                    None => false,
+                };
+            }
+            batch.keep(&keep);
+            if batch.len() > 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Ask the server to resume the session from the current address of the client. The socket
+    /// is connected again so that the source address is picked again after a network change.
+    async fn probe(&self, r: &Resumable) {
+        if r.expired() {
+            return;
+        }
+        let peer = r.peer();
+        if let Err(e) = self.socket.connect(peer).await {
+            debug!("Cannot reconnect to {}: {}", peer, e);
+            return;
+        }
+        if let Err(e) = self.socket.send(&control_message(RESUME, r.id)).await {
+            debug!("Cannot send resumption request: {}", e);
+        }
+    }
+
+    async fn accept_resume(&self, r: &Resumable, data: &[u8], from: SocketAddr) {
+        match parse_control(data) {
+            Some((RESUME, id)) if id == r.id => {
+                if r.expired() {
+                    warn!("Rejected resumption from {}, the session expired", from);
+                    return;
+                }
+                info!("Session resumed from {}, was {}", from, r.peer());
+                *r.peer.lock().unwrap() = from;
+                *r.last_received.lock().unwrap() = Instant::now();
+                let _ = self
+                    .socket
+                    .send_to(&control_message(RESUMED, r.id), from)
+                    .await;
+            }
+            _ => debug!("Discarded datagram from unknown address {}", from),
+        }
+    }
+}
+
+/// If a batched call is not supported by the kernel, so that the link falls back to one syscall
+/// per datagram.
+#[cfg(all(target_os = "linux", feature = "batch"))]
+fn unsupported(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(libc::ENOSYS)
+}
+
+#[cfg(not(all(target_os = "linux", feature = "batch")))]
+fn unsupported(_: &io::Error) -> bool {
+    false
+}
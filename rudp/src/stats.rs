@@ -0,0 +1,116 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// How the datagrams reach the remote.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Path {
+    /// Directly, possibly after hole punching.
+    #[default]
+    Direct,
+    /// Forwarded by a relay server.
+    Relayed,
+    /// Over the framed TCP transport, see `tcp`.
+    Tcp,
+}
+
+/// Counters of a connection, updated by the UDP loop. Counters are only for reporting, so they
+/// are updated with relaxed ordering.
+#[derive(Debug, Default)]
+pub struct ConnectionStats {
+    path: Path,
+    datagrams_sent: AtomicU64,
+    datagrams_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    reliable_sent: AtomicU64,
+    retransmissions: AtomicU64,
+    acks_received: AtomicU64,
+    parity_sent: AtomicU64,
+    recovered: AtomicU64,
+}
+
+impl ConnectionStats {
+    pub fn new(path: Path) -> Self {
+        ConnectionStats {
+            path,
+            ..Default::default()
+        }
+    }
+
+    pub fn path(&self) -> Path {
+        self.path
+    }
+
+    pub fn is_relayed(&self) -> bool {
+        self.path == Path::Relayed
+    }
+
+    pub fn datagrams_sent(&self) -> u64 {
+        self.datagrams_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn datagrams_received(&self) -> u64 {
+        self.datagrams_received.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received.load(Ordering::Relaxed)
+    }
+
+    /// Number of reliable packets sent, excluding retransmissions.
+    pub fn reliable_sent(&self) -> u64 {
+        self.reliable_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn retransmissions(&self) -> u64 {
+        self.retransmissions.load(Ordering::Relaxed)
+    }
+
+    /// Number of ACKs received which freed a slot.
+    pub fn acks_received(&self) -> u64 {
+        self.acks_received.load(Ordering::Relaxed)
+    }
+
+    /// Number of parity datagrams sent, see `fec`.
+    pub fn parity_sent(&self) -> u64 {
+        self.parity_sent.load(Ordering::Relaxed)
+    }
+
+    /// Number of lost datagrams rebuilt from the parity sent by the remote.
+    pub fn recovered(&self) -> u64 {
+        self.recovered.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn on_sent(&self, len: usize) {
+        self.datagrams_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_received(&self, len: usize) {
+        self.datagrams_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_reliable_sent(&self) {
+        self.reliable_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_retransmission(&self) {
+        self.retransmissions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_ack(&self) {
+        self.acks_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_parity_sent(&self) {
+        self.parity_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_recovered(&self) {
+        self.recovered.fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,269 @@
+//! Reliable, ordered byte streams for transfers larger than a datagram, such as replays.
+//!
+//! The bytes are split into chunks of at most `CHUNK_SIZE`, each sent as a reliable message with
+//! a packet ID reserved by the protocol, so streams share the slots with the reliable packets of
+//! the application and the unreliable packets are not delayed at all. A chunk carries the stream
+//! ID and its offset, and the reader puts the chunks back in order. At most `window` chunks of a
+//! stream are unacknowledged at the same time, so the reliable packets of the application wait
+//! behind a bounded number of chunks.
+//!
+//! `DATA` is followed by the stream ID (u32) and the offset (u64), `END` by the stream ID and the
+//! total length (u64), all in big endian.
+use super::delivery::{Delivery, Message, PacketSender, Receipt};
+use futures::{
+    channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+    stream::StreamExt,
+};
+use log::warn;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    convert::TryInto,
+    io,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::time::Duration;
+
+/// Packet IDs from `RESERVED_ID` are used by the protocol and must not be returned by
+/// `PacketDesc::id`.
+pub const RESERVED_ID: u32 = u32::MAX - 15;
+const DATA: u32 = u32::MAX - 1;
+const END: u32 = u32::MAX - 2;
+/// Maximum number of bytes in a chunk, so that the datagram fits into the receive buffer.
+pub const CHUNK_SIZE: usize = 900;
+const DATA_HEADER: usize = 12;
+/// Default number of unacknowledged chunks of a stream.
+const WINDOW: usize = 4;
+/// A chunk not acknowledged within this duration fails the stream.
+const CHUNK_EXPIRY: Duration = Duration::from_secs(30);
+
+pub(crate) fn is_reserved(id: u32) -> bool {
+    id >= RESERVED_ID
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Progress {
+    /// Bytes passed to `write`.
+    pub written: u64,
+    /// Bytes acknowledged by the remote.
+    pub acknowledged: u64,
+}
+
+/// Sending side of a stream, created by `PacketSender::open_stream`.
+pub struct StreamWriter<T> {
+    id: u32,
+    sender: PacketSender<T>,
+    offset: u64,
+    window: usize,
+    in_flight: VecDeque<(Receipt, usize)>,
+    acknowledged: u64,
+}
+
+fn delivery_error(delivery: Delivery) -> io::Error {
+    match delivery {
+        Delivery::Expired => io::Error::new(io::ErrorKind::TimedOut, "chunk not acknowledged"),
+        _ => io::Error::new(io::ErrorKind::BrokenPipe, "the connection is closed"),
+    }
+}
+
+impl<T> StreamWriter<T> {
+    pub(crate) fn new(id: u32, sender: PacketSender<T>) -> Self {
+        StreamWriter {
+            id,
+            sender,
+            offset: 0,
+            window: WINDOW,
+            in_flight: VecDeque::new(),
+            acknowledged: 0,
+        }
+    }
+
+    /// Set the number of unacknowledged chunks, a larger window is faster but delays the reliable
+    /// packets of the application more.
+    pub fn set_window(&mut self, window: usize) {
+        self.window = window.max(1);
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn progress(&self) -> Progress {
+        Progress {
+            written: self.offset,
+            acknowledged: self.acknowledged,
+        }
+    }
+
+    async fn wait_oldest(&mut self) -> io::Result<()> {
+        if let Some((receipt, len)) = self.in_flight.pop_front() {
+            match receipt.await {
+                Delivery::Delivered => self.acknowledged += len as u64,
+                delivery => return Err(delivery_error(delivery)),
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, id: u32, data: Vec<u8>, len: usize) {
+        let receipt = self
+            .sender
+            .send_message(Message::Raw { id, data }, CHUNK_EXPIRY);
+        self.in_flight.push_back((receipt, len));
+    }
+
+    /// Queue the bytes, waiting while the window is full.
+    pub async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        for chunk in data.chunks(CHUNK_SIZE) {
+            while self.in_flight.len() >= self.window {
+                self.wait_oldest().await?;
+            }
+            let mut buffer = Vec::with_capacity(DATA_HEADER + chunk.len());
+            buffer.extend(self.id.to_be_bytes().iter());
+            buffer.extend(self.offset.to_be_bytes().iter());
+            buffer.extend_from_slice(chunk);
+            self.send(DATA, buffer, chunk.len());
+            self.offset += chunk.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Mark the end of the stream, and wait until the remote acknowledged every chunk.
+    pub async fn finish(mut self) -> io::Result<Progress> {
+        let mut buffer = Vec::with_capacity(DATA_HEADER);
+        buffer.extend(self.id.to_be_bytes().iter());
+        buffer.extend(self.offset.to_be_bytes().iter());
+        self.send(END, buffer, 0);
+        while !self.in_flight.is_empty() {
+            self.wait_oldest().await?;
+        }
+        Ok(self.progress())
+    }
+}
+
+/// Receiving side of a stream, returned by `PacketSender::accept_stream`.
+pub struct StreamReader {
+    id: u32,
+    chunks: UnboundedReceiver<Vec<u8>>,
+    received: Arc<AtomicU64>,
+    length: Arc<AtomicU64>,
+    complete: Arc<AtomicBool>,
+}
+
+impl StreamReader {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Bytes received in order so far.
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    /// Total length of the stream, known after the writer finished.
+    pub fn length(&self) -> Option<u64> {
+        match self.length.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            length => Some(length),
+        }
+    }
+
+    /// Return the next bytes in order, or `None` at the end of the stream. Fails if the loop
+    /// stopped before the end.
+    pub async fn read(&mut self) -> io::Result<Option<Vec<u8>>> {
+        match self.chunks.next().await {
+            Some(chunk) => Ok(Some(chunk)),
+            None if self.complete.load(Ordering::Acquire) => Ok(None),
+            None => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the connection is closed",
+            )),
+        }
+    }
+
+    pub async fn read_to_end(mut self) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        while let Some(chunk) = self.read().await? {
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data)
+    }
+}
+
+struct IncomingStream {
+    next_offset: u64,
+    pending: BTreeMap<u64, Vec<u8>>,
+    chunks: UnboundedSender<Vec<u8>>,
+    received: Arc<AtomicU64>,
+    length: Arc<AtomicU64>,
+    complete: Arc<AtomicBool>,
+}
+
+/// Puts the chunks received by the receiver back into streams.
+pub(crate) struct StreamHub {
+    accept: UnboundedSender<StreamReader>,
+    streams: HashMap<u32, IncomingStream>,
+}
+
+impl StreamHub {
+    pub fn new() -> (Self, UnboundedReceiver<StreamReader>) {
+        let (accept, incoming) = unbounded();
+        (
+            StreamHub {
+                accept,
+                streams: HashMap::new(),
+            },
+            incoming,
+        )
+    }
+
+    fn stream(&mut self, id: u32) -> &mut IncomingStream {
+        let accept = &self.accept;
+        self.streams.entry(id).or_insert_with(|| {
+            let (chunks, receiver) = unbounded();
+            let stream = IncomingStream {
+                next_offset: 0,
+                pending: BTreeMap::new(),
+                chunks,
+                received: Arc::new(AtomicU64::new(0)),
+                length: Arc::new(AtomicU64::new(u64::MAX)),
+                complete: Arc::new(AtomicBool::new(false)),
+            };
+            let _ = accept.unbounded_send(StreamReader {
+                id,
+                chunks: receiver,
+                received: stream.received.clone(),
+                length: stream.length.clone(),
+                complete: stream.complete.clone(),
+            });
+            stream
+        })
+    }
+
+    /// Handle a message with a reserved ID, which is delivered exactly once by the receiver.
+    pub fn handle(&mut self, id: u32, data: &[u8]) {
+        if data.len() < DATA_HEADER || (id != DATA && id != END) {
+            warn!("Invalid stream message with ID {}", id);
+            return;
+        }
+        let stream_id = u32::from_be_bytes(data[..4].try_into().unwrap());
+        let offset = u64::from_be_bytes(data[4..DATA_HEADER].try_into().unwrap());
+        let stream = self.stream(stream_id);
+        if id == END {
+            stream.length.store(offset, Ordering::Relaxed);
+        } else {
+            stream.pending.insert(offset, data[DATA_HEADER..].to_vec());
+        }
+        while let Some(chunk) = stream.pending.remove(&stream.next_offset) {
+            stream.next_offset += chunk.len() as u64;
+            stream.received.store(stream.next_offset, Ordering::Relaxed);
+            let _ = stream.chunks.unbounded_send(chunk);
+        }
+        if stream.length.load(Ordering::Relaxed) == stream.next_offset {
+            stream.complete.store(true, Ordering::Release);
+            self.streams.remove(&stream_id);
+        }
+    }
+}
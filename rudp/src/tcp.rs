@@ -0,0 +1,314 @@
+//! Framed TCP transport, for networks blocking UDP.
+//!
+//! Every datagram of the UDP protocol is sent as a frame prefixed by its length (u16, big
+//! endian), with the same header and payload. TCP delivers the frames reliably and in order, so
+//! there are no slots, ACKs or retransmissions: reliable packets are sent with slot 1, unreliable
+//! packets with slot 0, and both are always delivered. The receipt of a reliable packet resolves
+//! to `Delivered` once its frame is written to the socket. Of the `Config`, only the compression
+//! settings apply, capture, FEC, state coalescing and session resumption are for UDP only.
+//!
+//! The handshake is a frame holding the magic, answered with the same frame by the server.
+//! `connect_with_fallback` tries UDP first and switches to TCP after a timeout, and
+//! `server_accept_any` accepts a client over either transport.
+use super::delivery::{Delivery, Outgoing, PacketSender};
+use super::hand_shake::{
+    client_connect_any, is_refusal, refusal, refused_error, schema_mismatch, server_accept,
+};
+use super::protocol::{Compression, PacketDesc, PacketHeader};
+use super::receiver::{BypassResult, Receiver};
+use super::stats::ConnectionStats;
+use super::{start_tcp_loop, start_udp_loop, Channels, Config};
+use futures::{channel::mpsc::UnboundedReceiver, future::FutureExt, select};
+use log::{info, warn};
+use std::{io, sync::Arc};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpStream, UdpSocket,
+    },
+    sync::mpsc,
+    time::{timeout, Duration},
+};
+
+/// Longest frame, longer packets are dropped.
+const MAX_FRAME: usize = u16::MAX as usize;
+/// A connection not sending the magic within this duration is dropped by the server.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, frame: &[u8]) -> io::Result<()> {
+    writer
+        .write_all(&(frame.len() as u16).to_be_bytes())
+        .await?;
+    writer.write_all(frame).await
+}
+
+/// Read a frame into the buffer, replacing its content.
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R, buffer: &mut Vec<u8>) -> io::Result<()> {
+    let mut len = [0u8; 2];
+    reader.read_exact(&mut len).await?;
+    buffer.resize(u16::from_be_bytes(len) as usize, 0);
+    reader.read_exact(buffer).await?;
+    Ok(())
+}
+
+/// Send the magic through a connected stream, and wait for the server to answer.
+pub async fn client_handshake(mut stream: TcpStream, magic: &[u8]) -> io::Result<TcpStream> {
+    stream.set_nodelay(true)?;
+    write_frame(&mut stream, magic).await?;
+    let mut buffer = Vec::new();
+    read_frame(&mut stream, &mut buffer).await?;
+    if is_refusal(magic, &buffer) {
+        return Err(refused_error());
+    }
+    if buffer != magic {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the server answered with another magic",
+        ));
+    }
+    Ok(stream)
+}
+
+/// Connect to the server, which could be a hostname with port, and perform the handshake within
+/// `time_limit`. Every resolved address is tried in turn.
+pub async fn connect(server: &str, magic: &[u8], time_limit: Duration) -> io::Result<TcpStream> {
+    let f = async {
+        let stream = TcpStream::connect(server).await?;
+        info!("Connected to {} over TCP", stream.peer_addr()?);
+        client_handshake(stream, magic).await
+    };
+    timeout(time_limit, f)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "TCP handshake timed out"))?
+}
+
+/// Accept connections until one sends the magic, and answer it.
+pub async fn accept(listener: &TcpListener, magic: &[u8]) -> io::Result<TcpStream> {
+    let mut buffer = Vec::new();
+    loop {
+        let (mut stream, from) = listener.accept().await?;
+        match timeout(HANDSHAKE_TIMEOUT, read_frame(&mut stream, &mut buffer)).await {
+            Ok(Ok(())) if buffer == magic => {
+                stream.set_nodelay(true)?;
+                write_frame(&mut stream, magic).await?;
+                info!("Accepted client from {} over TCP", from);
+                return Ok(stream);
+            }
+            Ok(Ok(())) if schema_mismatch(magic, &buffer) => {
+                warn!("Refused client from {} with another packet schema", from);
+                let _ = write_frame(&mut stream, &refusal(&buffer)).await;
+            }
+            _ => warn!("Dropped connection from {} without the magic", from),
+        }
+    }
+}
+
+/// A connection over either transport.
+pub enum Transport {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+impl Transport {
+    /// Start the loop with `start_udp_loop` or `start_tcp_loop`.
+    pub fn start<
+        T: PacketDesc + Send + Sync + 'static,
+        F: Fn(T) -> BypassResult<T> + Send + Sync + 'static,
+    >(
+        self,
+        config: Config,
+        bypass: F,
+    ) -> (PacketSender<T>, UnboundedReceiver<T>, Arc<ConnectionStats>) {
+        match self {
+            Transport::Udp(socket) => start_udp_loop(socket, config, bypass),
+            Transport::Tcp(stream) => start_tcp_loop(stream, config, bypass),
+        }
+    }
+}
+
+/// Try the UDP handshake with every resolved address of the server, each within
+/// `attempt_timeout`, and fall back to TCP on the same port if none answered, where the
+/// connection and the handshake have to complete within `attempt_timeout` as well. Other errors,
+/// such as the server refusing the magic or the packet schema, are returned as is.
+pub async fn connect_with_fallback(
+    server: &str,
+    magic: &[u8],
+    attempt_timeout: Duration,
+) -> io::Result<Transport> {
+    match client_connect_any(server, magic, attempt_timeout).await {
+        Ok((socket, _)) => Ok(Transport::Udp(socket)),
+        Err(e) if udp_blocked(&e) => {
+            warn!("UDP connection failed ({}), falling back to TCP", e);
+            Ok(Transport::Tcp(
+                connect(server, magic, attempt_timeout).await?,
+            ))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// If the UDP handshake failed in a way TCP could get around, as when UDP is blocked on the way
+/// or the server does not listen for it.
+fn udp_blocked(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::HostUnreachable
+            | io::ErrorKind::NetworkUnreachable
+    )
+}
+
+/// Wait for a client over UDP on the socket or over TCP on the listener, which usually listen on
+/// the same port.
+pub async fn server_accept_any(
+    socket: UdpSocket,
+    listener: &TcpListener,
+    magic: &[u8],
+) -> io::Result<Transport> {
+    select! {
+        socket = server_accept(socket, magic).fuse() => Ok(Transport::Udp(socket)),
+        stream = accept(listener, magic).fuse() => Ok(Transport::Tcp(stream?)),
+    }
+}
+
+/// Write the packets as frames, flushing whenever the channel is empty.
+async fn send_loop<T: PacketDesc>(
+    writer: OwnedWriteHalf,
+    channel: &mut mpsc::UnboundedReceiver<Outgoing<T>>,
+    compression: Compression,
+    stats: &ConnectionStats,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    let mut generation = 0;
+    let mut frame = Vec::new();
+    let mut scratch = Vec::new();
+    // reliable packets whose receipts resolve once the frames are flushed
+    let mut written = Vec::new();
+    while let Some(p) = channel.recv().await {
+        let mut next = Some(p);
+        while let Some(p) = next {
+            let reliable = p.message.reliable();
+            frame.clear();
+            PacketHeader::new(p.message.id(), reliable as isize, generation).serialize(&mut frame);
+            p.message.serialize(&mut frame);
+            compression.apply(p.message.compress(), &mut frame, &mut scratch);
+            trace_event!(
+                trace,
+                id = p.message.id(),
+                slot = reliable as isize,
+                generation,
+                size = frame.len(),
+                "sent"
+            );
+            generation += 1;
+            if frame.len() > MAX_FRAME {
+                warn!("Dropped packet {} longer than a frame", p.message.id());
+                p.expire();
+            } else {
+                write_frame(&mut writer, &frame).await?;
+                stats.on_sent(frame.len());
+                if reliable {
+                    stats.on_reliable_sent();
+                    written.push(p);
+                } else {
+                    p.expire();
+                }
+            }
+            next = channel.try_recv().ok();
+        }
+        writer.flush().await?;
+        for p in written.drain(..) {
+            if let Some(tracker) = p.tracker {
+                tracker.resolve(Delivery::Delivered);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read the frames and pass the packets on, until the connection or the channel is closed.
+async fn recv_loop<T: PacketDesc, F: Fn(T) -> BypassResult<T>>(
+    reader: OwnedReadHalf,
+    stats: &ConnectionStats,
+    channels: Channels<T>,
+    bypass: F,
+) -> io::Result<()> {
+    let Channels {
+        to_fg: channel,
+        to_bg: to_sender,
+        streams,
+    } = channels;
+    let mut reader = BufReader::new(reader);
+    // everything arrives in order, so slot 1 is enough for checking the reliable packets
+    let mut receiver = Receiver::detached(1);
+    receiver.accept_streams(streams);
+    let mut frame = Vec::new();
+    loop {
+        read_frame(&mut reader, &mut frame).await?;
+        stats.on_received(frame.len());
+        let (p, data) = match PacketHeader::deserialize(&frame) {
+            Ok((p, data)) => (p, data),
+            Err(e) => {
+                warn!("Error deserializing header: {}", e.0);
+                continue;
+            }
+        };
+        let open = match receiver.handle_packet::<T>(&p, data).map(&bypass) {
+            Some(BypassResult::ToSender(p)) => to_sender.unbounded_send(p).is_ok(),
+            Some(BypassResult::ToUser(p)) => channel.unbounded_send(p).is_ok(),
+            _ => true,
+        };
+        if !open {
+            return Ok(());
+        }
+    }
+}
+
+pub(crate) async fn tcp_loop<
+    T: PacketDesc + Send + Sync + 'static,
+    F: Fn(T) -> BypassResult<T> + Send + Sync + 'static,
+>(
+    stream: TcpStream,
+    config: Config,
+    stats: Arc<ConnectionStats>,
+    from_fg: mpsc::UnboundedReceiver<Outgoing<T>>,
+    channels: Channels<T>,
+    bypass: F,
+) {
+    #[cfg(feature = "tracing")]
+    let span = super::trace::connection(stream.peer_addr().ok(), stats.path());
+    let (reader, writer) = stream.into_split();
+    let compression = Compression {
+        all: config.compress_all,
+        threshold: config.compression_threshold,
+    };
+    let send_stats = stats.clone();
+    let send_task = async move {
+        let mut from_fg = from_fg;
+        if let Err(e) = send_loop(writer, &mut from_fg, compression, &send_stats).await {
+            warn!("Error sending data: {}", e);
+        }
+    };
+    let recv_task = async move {
+        match recv_loop(reader, &stats, channels, bypass).await {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => info!("Connection closed"),
+            Err(e) => warn!("Error receiving data: {}", e),
+            Ok(()) => (),
+        }
+    };
+    #[cfg(feature = "tracing")]
+    let (send_task, recv_task) = (
+        tracing::Instrument::instrument(send_task, span.clone()),
+        tracing::Instrument::instrument(recv_task, span),
+    );
+    let send_task = tokio::spawn(send_task);
+    let recv_task = tokio::spawn(recv_task);
+    // Close the task when any finishes.
+    select!(
+        _ = send_task.fuse() => (),
+        _ = recv_task.fuse() => (),
+    );
+}
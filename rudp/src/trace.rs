@@ -0,0 +1,33 @@
+//! Structured events for `tracing`, with the `tracing` feature. Without the feature the macro
+//! expands to nothing, so the fields are not even evaluated.
+//!
+//! Both tasks of a loop run in a `connection` span with the address of the peer and the path.
+//! Packets sent, acknowledged and delivered are `TRACE` events, and retransmissions, expired
+//! receipts and dropped packets are `DEBUG` events. They carry the packet ID, slot, generation
+//! and size where known, and an ACK carries the RTT sample in microseconds unless the packet was
+//! resent. Events below the level enabled by the subscriber cost a check of a cached flag, and
+//! the `max_level_*` features of `tracing` remove them at compile time.
+#[cfg(feature = "tracing")]
+use super::stats::Path;
+#[cfg(feature = "tracing")]
+use std::net::SocketAddr;
+
+/// Emit a `tracing` event at the level, with the same arguments as the macro of `tracing`.
+macro_rules! trace_event {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($arg)+);
+    };
+}
+
+/// Span for the tasks of a connection.
+#[cfg(feature = "tracing")]
+pub(crate) fn connection(peer: Option<SocketAddr>, path: Path) -> tracing::Span {
+    tracing::info_span!("connection", ?peer, ?path)
+}
+
+/// Packet ID of a serialized datagram.
+#[cfg(feature = "tracing")]
+pub(crate) fn id(datagram: &[u8]) -> u32 {
+    super::protocol::PacketHeader::deserialize(datagram).map_or(0, |(p, _)| p.id)
+}
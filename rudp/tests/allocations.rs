@@ -0,0 +1,72 @@
+use rudp::{start_udp_loop, BypassResult, Config, ConnectionStats};
+use rudp_derive::PacketDesc;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tokio::{net::UdpSocket, task::yield_now};
+
+/// Counts the heap allocations of the whole test binary.
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const WARMUP: u32 = 1_000;
+const PACKETS: u32 = 10_000;
+const BURST: u32 = 32;
+
+#[derive(serde::Serialize, serde::Deserialize, PacketDesc)]
+enum Packet {
+    #[packet(unreliable)]
+    Position { x: f32, y: f32 },
+}
+
+/// Send the packets in bursts, each after the previous one was sent.
+async fn send(send: &rudp::PacketSender<Packet>, stats: &ConnectionStats, count: u32) {
+    for _ in 0..count / BURST {
+        let target = stats.datagrams_sent() + BURST as u64;
+        for i in 0..BURST {
+            let _ = send.unbounded_send(Packet::Position {
+                x: i as f32,
+                y: 0.0,
+            });
+        }
+        while stats.datagrams_sent() < target {
+            yield_now().await;
+        }
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn unreliable_sends_do_not_allocate() {
+    let local = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    // never reads, so nothing is received either
+    let remote = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    local.connect(remote.local_addr().unwrap()).await.unwrap();
+    let (sender, _recv, stats) =
+        start_udp_loop::<Packet, _>(local, Config::default(), BypassResult::ToUser);
+    send(&sender, &stats, WARMUP).await;
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    send(&sender, &stats, PACKETS).await;
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    assert_eq!(allocations, 0, "allocations for {} packets", PACKETS);
+}
@@ -0,0 +1,59 @@
+//! The fallbacks of batched socket I/O, see `batch`. The tests pass without the `batch` feature
+//! as well, where every datagram is written by its own syscall anyway.
+#![cfg(target_os = "linux")]
+use futures::StreamExt;
+use rudp::{start_udp_loop, BypassResult, Config};
+use rudp_derive::PacketDesc;
+use std::{mem, os::unix::io::AsRawFd};
+use tokio::{
+    net::UdpSocket,
+    time::{timeout, Duration},
+};
+
+#[derive(serde::Serialize, serde::Deserialize, PacketDesc, PartialEq, Debug)]
+enum Packet {
+    #[packet(unreliable)]
+    Position { x: f32 },
+}
+
+#[tokio::test]
+async fn gso_falls_back_when_the_kernel_refuses() {
+    let local = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let remote = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    local.connect(remote.local_addr().unwrap()).await.unwrap();
+    remote.connect(local.local_addr().unwrap()).await.unwrap();
+    // the kernel rejects GSO buffers with EINVAL if UDP checksums are disabled
+    let enabled: libc::c_int = 1;
+    let result = unsafe {
+        libc::setsockopt(
+            local.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_NO_CHECK,
+            &enabled as *const _ as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    assert_eq!(result, 0);
+    let (send, _recv, _) =
+        start_udp_loop::<Packet, _>(local, Config::default(), BypassResult::ToUser);
+    let (_send, mut recv, _) =
+        start_udp_loop::<Packet, _>(remote, Config::default(), BypassResult::ToUser);
+    // runs of datagrams with the same length, sent as GSO buffers
+    for burst in 0..4 {
+        for i in 0..16 {
+            send.unbounded_send(Packet::Position {
+                x: (burst * 16 + i) as f32,
+            })
+            .unwrap();
+        }
+        for i in 0..16 {
+            let received = timeout(Duration::from_secs(1), recv.next()).await.unwrap();
+            assert_eq!(
+                received,
+                Some(Packet::Position {
+                    x: (burst * 16 + i) as f32
+                })
+            );
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! Batched calls failing with ENOSYS, as under a seccomp filter of a container. The filter
+//! applies to the whole process, so this is a test binary of its own.
+#![cfg(target_os = "linux")]
+use futures::StreamExt;
+use rudp::{start_udp_loop, BypassResult, Config};
+use rudp_derive::PacketDesc;
+use tokio::{
+    net::UdpSocket,
+    time::{timeout, Duration},
+};
+
+#[derive(serde::Serialize, serde::Deserialize, PacketDesc, PartialEq, Debug)]
+enum Packet {
+    #[packet(reliable)]
+    Hello { index: u32 },
+    #[packet(unreliable)]
+    Position { x: f32 },
+}
+
+/// Make `sendmmsg` and `recvmmsg` fail with ENOSYS in every thread of the process.
+fn forbid_batched_calls() {
+    let errno = libc::SECCOMP_RET_ERRNO | libc::ENOSYS as u32;
+    let mut filter = unsafe {
+        [
+            // the syscall number is the first field of `seccomp_data`
+            libc::BPF_STMT((libc::BPF_LD | libc::BPF_W | libc::BPF_ABS) as u16, 0),
+            libc::BPF_JUMP(
+                (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16,
+                libc::SYS_sendmmsg as u32,
+                2,
+                0,
+            ),
+            libc::BPF_JUMP(
+                (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16,
+                libc::SYS_recvmmsg as u32,
+                1,
+                0,
+            ),
+            libc::BPF_STMT(libc::BPF_RET as u16, libc::SECCOMP_RET_ALLOW),
+            libc::BPF_STMT(libc::BPF_RET as u16, errno),
+        ]
+    };
+    let program = libc::sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_mut_ptr(),
+    };
+    unsafe {
+        assert_eq!(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0), 0);
+        assert_eq!(
+            libc::syscall(
+                libc::SYS_seccomp,
+                libc::SECCOMP_SET_MODE_FILTER,
+                libc::SECCOMP_FILTER_FLAG_TSYNC,
+                &program as *const libc::sock_fprog,
+            ),
+            0
+        );
+    }
+}
+
+#[tokio::test]
+async fn falls_back_without_batched_calls() {
+    forbid_batched_calls();
+    let a = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let b = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    a.connect(b.local_addr().unwrap()).await.unwrap();
+    b.connect(a.local_addr().unwrap()).await.unwrap();
+    let (a_send, mut a_recv, _) =
+        start_udp_loop::<Packet, _>(a, Config::default(), BypassResult::ToUser);
+    let (b_send, mut b_recv, _) =
+        start_udp_loop::<Packet, _>(b, Config::default(), BypassResult::ToUser);
+    for (send, recv) in [(&a_send, &mut b_recv), (&b_send, &mut a_recv)] {
+        for index in 0..20 {
+            send.unbounded_send(Packet::Hello { index }).unwrap();
+        }
+        let mut received = Vec::new();
+        while received.len() < 20 {
+            match timeout(Duration::from_secs(1), recv.next()).await {
+                Ok(Some(Packet::Hello { index })) => received.push(index),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        received.sort_unstable();
+        assert_eq!(received, (0..20).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,89 @@
+use futures::StreamExt;
+use rudp::{start_udp_loop, BypassResult, Config, PacketDesc, PacketHeader};
+use rudp_derive::PacketDesc;
+use tokio::{
+    net::UdpSocket,
+    time::{timeout, Duration},
+};
+
+#[derive(serde::Serialize, serde::Deserialize, PacketDesc, PartialEq, Debug)]
+enum Packet {
+    #[packet(unreliable)]
+    Blob { data: Vec<u8> },
+}
+
+/// Append a length of the LZ4 block format, the first 15 of which are in the token.
+fn extend_length(block: &mut Vec<u8>, mut rest: usize) {
+    while rest >= 255 {
+        block.push(255);
+        rest -= 255;
+    }
+    block.push(rest as u8);
+}
+
+/// Compress the payload, whose bytes from `literals` on are zeros, as the size (u32, little
+/// endian) followed by an LZ4 block repeating the first zero.
+fn compressed(size: u32, payload: &[u8], literals: usize) -> Vec<u8> {
+    let mut block = size.to_le_bytes().to_vec();
+    // the last 5 bytes of a block are literals
+    let (literals, repeated) = (literals + 1, payload.len() - literals - 6);
+    let (literal_nibble, match_nibble) = (literals.min(15), (repeated - 4).min(15));
+    block.push((literal_nibble << 4 | match_nibble) as u8);
+    if literal_nibble == 15 {
+        extend_length(&mut block, literals - 15);
+    }
+    block.extend_from_slice(&payload[..literals]);
+    block.extend_from_slice(&1u16.to_le_bytes());
+    if match_nibble == 15 {
+        extend_length(&mut block, repeated - 4 - 15);
+    }
+    block.push(5 << 4);
+    block.extend_from_slice(&[0; 5]);
+    block
+}
+
+/// A compressed datagram carrying `Blob` with `len` zeros.
+fn datagram(generation: i64, len: usize, claimed: Option<u32>) -> Vec<u8> {
+    let packet = Packet::Blob { data: vec![0; len] };
+    let mut data = Vec::new();
+    let mut header = PacketHeader::new(packet.id(), 0, generation);
+    header.flags = 1;
+    header.serialize(&mut data);
+    let mut payload = Vec::new();
+    packet.serialize(&mut payload);
+    let literals = payload.len() - len;
+    let size = claimed.unwrap_or(payload.len() as u32);
+    data.extend(compressed(size, &payload, literals));
+    data
+}
+
+#[tokio::test]
+async fn rejects_oversized_payloads() {
+    let local = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let remote = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    local.connect(remote.local_addr().unwrap()).await.unwrap();
+    remote.connect(local.local_addr().unwrap()).await.unwrap();
+    let (_send, mut recv, _) =
+        start_udp_loop::<Packet, _>(local, Config::default(), BypassResult::ToUser);
+    for data in [
+        // decompresses into more than 64 KiB
+        datagram(0, 70_000, None),
+        datagram(1, 70_000, Some(u32::MAX)),
+        // into more than it claims
+        datagram(2, 1000, Some(100)),
+        datagram(3, 1000, None),
+    ] {
+        assert!(data.len() < 1400);
+        remote.send(&data).await.unwrap();
+    }
+    let received = timeout(Duration::from_secs(1), recv.next()).await.unwrap();
+    assert_eq!(
+        received,
+        Some(Packet::Blob {
+            data: vec![0; 1000]
+        })
+    );
+    assert!(timeout(Duration::from_millis(100), recv.next())
+        .await
+        .is_err());
+}
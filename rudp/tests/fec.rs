@@ -0,0 +1,92 @@
+use rudp::{
+    capture::{CaptureRecord, Direction, Replay},
+    PacketDesc, PacketHeader,
+};
+use rudp_derive::PacketDesc;
+use tokio::time::Duration;
+
+#[derive(serde::Serialize, serde::Deserialize, PacketDesc, PartialEq, Debug)]
+enum Packet {
+    #[packet(reliable)]
+    Hello { index: u32 },
+    #[packet(unreliable)]
+    Position { x: f32 },
+}
+
+fn datagram(packet: &Packet, slot: isize, generation: i64) -> Vec<u8> {
+    let mut data = Vec::new();
+    PacketHeader::new(packet.id(), slot, generation).serialize(&mut data);
+    packet.serialize(&mut data);
+    data
+}
+
+/// The parity of datagrams with consecutive generations from `first`.
+fn parity(first: i64, group: &[&Vec<u8>]) -> Vec<u8> {
+    let mut header = PacketHeader::new(group.len() as u32, 0, first);
+    header.flags = 2;
+    let mut data = Vec::new();
+    header.serialize(&mut data);
+    let length = group.iter().fold(0, |length, d| length ^ d.len() as u16);
+    data.extend_from_slice(&length.to_be_bytes());
+    let mut payload = vec![0u8; group.iter().map(|d| d.len()).max().unwrap()];
+    for d in group {
+        for (p, b) in payload.iter_mut().zip(d.iter()) {
+            *p ^= b;
+        }
+    }
+    data.extend(payload);
+    data
+}
+
+fn record(data: &[u8]) -> CaptureRecord {
+    CaptureRecord {
+        time: Duration::from_millis(0),
+        direction: Direction::Received,
+        data: data.to_vec(),
+    }
+}
+
+#[test]
+fn late_originals_are_delivered_once() {
+    let first = [
+        datagram(&Packet::Position { x: 0.0 }, 0, 0),
+        datagram(&Packet::Position { x: 1.0 }, 0, 1),
+    ];
+    // an unreliable and a reliable datagram lost, and arriving after they were rebuilt
+    let unreliable = [
+        datagram(&Packet::Position { x: 2.0 }, 0, 2),
+        datagram(&Packet::Position { x: 3.0 }, 0, 3),
+    ];
+    let reliable = [
+        datagram(&Packet::Hello { index: 4 }, 1, 4),
+        datagram(&Packet::Position { x: 5.0 }, 0, 5),
+    ];
+    let records = [
+        // the receiver keeps datagrams from the first parity on
+        first[0].clone(),
+        first[1].clone(),
+        parity(0, &[&first[0], &first[1]]),
+        unreliable[1].clone(),
+        parity(2, &[&unreliable[0], &unreliable[1]]),
+        unreliable[0].clone(),
+        reliable[1].clone(),
+        parity(4, &[&reliable[0], &reliable[1]]),
+        reliable[0].clone(),
+    ];
+    let mut replay = Replay::new(4);
+    let delivered: Vec<Packet> = records
+        .iter()
+        .flat_map(|data| replay.feed(&record(data)))
+        .collect();
+    assert_eq!(
+        delivered,
+        vec![
+            Packet::Position { x: 0.0 },
+            Packet::Position { x: 1.0 },
+            Packet::Position { x: 3.0 },
+            Packet::Position { x: 2.0 },
+            Packet::Position { x: 5.0 },
+            Packet::Hello { index: 4 },
+        ]
+    );
+}
@@ -0,0 +1,54 @@
+use rudp::capture::Direction;
+use rudp::inspect::{Hex, Inspector};
+use rudp::PacketHeader;
+use std::time::Duration;
+
+fn datagram(id: u32, slot: isize, generation: i64, payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    PacketHeader::new(id, slot, generation).serialize(&mut data);
+    data.extend_from_slice(payload);
+    data
+}
+
+#[test]
+fn ack_latency_without_direction() {
+    let mut inspector = Inspector::new(Hex);
+    let data = datagram(3, 2, 7, &[0xab]);
+    let line = inspector.inspect(Duration::from_millis(10), None, &data);
+    assert!(line.contains("data"), "{}", line);
+    let line = inspector.inspect(Duration::from_millis(25), None, &data);
+    assert!(line.contains("retransmit"), "{}", line);
+    let ack = datagram(3, -2, 7, &[]);
+    let line = inspector.inspect(Duration::from_millis(40), None, &ack);
+    assert!(line.contains("latency=30.000ms"), "{}", line);
+    // the packet is no longer in flight
+    let line = inspector.inspect(Duration::from_millis(50), None, &ack);
+    assert!(!line.contains("latency"), "{}", line);
+}
+
+#[test]
+fn ack_latency_with_direction() {
+    let mut inspector = Inspector::new(Hex);
+    let data = datagram(1, 1, 0, &[]);
+    inspector.inspect(Duration::from_millis(0), Some(Direction::Sent), &data);
+    // an ACK sent by us answers a packet of the remote, not ours
+    let ack = datagram(1, -1, 0, &[]);
+    let line = inspector.inspect(Duration::from_millis(5), Some(Direction::Sent), &ack);
+    assert!(!line.contains("latency"), "{}", line);
+    let line = inspector.inspect(Duration::from_millis(8), Some(Direction::Received), &ack);
+    assert!(line.contains("latency=8.000ms"), "{}", line);
+    assert!(inspector.summary().contains("in    1"));
+}
+
+#[test]
+fn control_and_invalid_slots() {
+    let mut inspector = Inspector::new(Hex);
+    let mut control = datagram(1, 0, 7, &[]);
+    // the `CONTROL` flag is the last byte of the header
+    *control.last_mut().unwrap() = 4;
+    let line = inspector.inspect(Duration::from_millis(0), None, &control);
+    assert!(line.contains("control"), "{}", line);
+    let invalid = datagram(1, isize::MIN, 7, &[]);
+    let line = inspector.inspect(Duration::from_millis(1), None, &invalid);
+    assert!(line.contains("invalid slot"), "{}", line);
+}
@@ -0,0 +1,208 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rudp::bitpack::{BitReader, BitWriter, Quantization, Quantize};
+use rudp::PacketDesc;
+use rudp_derive::PacketDesc;
+use std::f64::consts::PI;
+
+const SAMPLES: usize = 10_000;
+
+#[derive(PacketDesc, PartialEq, Debug)]
+enum Packet {
+    #[packet(unreliable, ordered, state)]
+    Paddle {
+        #[quantize(min = 0.0, max = 100.0, bits = 12)]
+        position: f32,
+        #[quantize(min = -3.2, max = 3.2, bits = 10)]
+        rotation: f32,
+    },
+    #[packet(unreliable, ordered)]
+    Ball {
+        generation: u32,
+        #[quantize(bits = 40)]
+        timestamp: u128,
+        #[quantize(min = 0, max = 100, bits = 12)]
+        position: [f32; 2],
+        name: String,
+    },
+    #[packet(reliable)]
+    Score(#[quantize(min = -8, bits = 4)] i8, u8),
+}
+
+fn round_trip<T: Quantize>(value: T, quantization: &Quantization) -> T {
+    let mut buffer = Vec::new();
+    let mut writer = BitWriter::new(&mut buffer);
+    value.quantize(quantization, &mut writer);
+    writer.finish();
+    assert_eq!(buffer.len(), (quantization.bits as usize).div_ceil(8));
+    T::dequantize(quantization, &mut BitReader::new(&buffer)).unwrap()
+}
+
+#[test]
+fn bits_round_trip() {
+    let mut rng = StdRng::seed_from_u64(49);
+    let widths: Vec<u32> = (0..SAMPLES).map(|_| rng.gen_range(1..=64)).collect();
+    let values: Vec<u64> = widths
+        .iter()
+        .map(|&bits| rng.gen::<u64>() >> (64 - bits))
+        .collect();
+    let mut buffer = vec![0xab];
+    let mut writer = BitWriter::new(&mut buffer);
+    for (&value, &bits) in values.iter().zip(widths.iter()) {
+        writer.write(value, bits);
+    }
+    writer.finish();
+    let total: u32 = widths.iter().sum();
+    assert_eq!(buffer.len(), 1 + (total as usize).div_ceil(8));
+
+    let mut reader = BitReader::new(&buffer[1..]);
+    for (&value, &bits) in values.iter().zip(widths.iter()) {
+        assert_eq!(reader.read(bits).unwrap(), value);
+    }
+    assert!(reader.remaining().is_empty());
+    assert!(reader.read(8).is_err());
+}
+
+#[test]
+fn float_precision() {
+    let mut rng = StdRng::seed_from_u64(49);
+    for &(min, max, bits) in &[
+        (0.0, 100.0, 12),
+        (-PI, PI, 10),
+        (-100.0, 100.0, 16),
+        (0.0, 1.0, 1),
+        (-1e6, 1e6, 32),
+    ] {
+        let quantization = Quantization { min, max, bits };
+        let precision = quantization.precision();
+        assert!((precision - (max - min) / ((1u64 << bits) - 1) as f64 / 2.0).abs() < 1e-12);
+        for _ in 0..SAMPLES {
+            let value = rng.gen_range(min..=max);
+            let error = (round_trip(value, &quantization) - value).abs();
+            assert!(
+                error <= precision * (1.0 + 1e-9),
+                "{} off by {}",
+                value,
+                error
+            );
+            // f32 adds its own rounding on top
+            let value = value as f32;
+            let error = (round_trip(value, &quantization) - value).abs() as f64;
+            let tolerance = precision + f32::EPSILON as f64 * min.abs().max(max.abs());
+            assert!(error <= tolerance, "{} off by {}", value, error);
+        }
+        assert_eq!(round_trip(min, &quantization), min);
+        assert_eq!(round_trip(max, &quantization), max);
+    }
+}
+
+#[test]
+fn float_clamped() {
+    let quantization = Quantization {
+        min: -1.0,
+        max: 1.0,
+        bits: 8,
+    };
+    assert_eq!(round_trip(-5.0f32, &quantization), -1.0);
+    assert_eq!(round_trip(5.0f32, &quantization), 1.0);
+    assert_eq!(round_trip(f32::INFINITY, &quantization), 1.0);
+    assert_eq!(round_trip(f32::NAN, &quantization), -1.0);
+}
+
+#[test]
+fn integers_exact() {
+    let mut rng = StdRng::seed_from_u64(49);
+    let quantization = Quantization {
+        min: -1000.0,
+        max: -1000.0 + 4095.0,
+        bits: 12,
+    };
+    for _ in 0..SAMPLES {
+        let value: i32 = rng.gen_range(-1000..=3095);
+        assert_eq!(round_trip(value, &quantization), value);
+    }
+    assert_eq!(round_trip(-5000i32, &quantization), -1000);
+    assert_eq!(round_trip(5000i32, &quantization), 3095);
+
+    let quantization = Quantization {
+        min: 0.0,
+        max: 18446744073709551615.0,
+        bits: 64,
+    };
+    for &value in &[0u128, 1, u64::MAX as u128 - 1, u64::MAX as u128] {
+        assert_eq!(round_trip(value, &quantization), value);
+    }
+    assert_eq!(round_trip(u128::MAX, &quantization), u64::MAX as u128);
+}
+
+#[test]
+fn packets_within_precision() {
+    let mut rng = StdRng::seed_from_u64(49);
+    for _ in 0..SAMPLES {
+        let packet = Packet::Paddle {
+            position: rng.gen_range(0.0..=100.0),
+            rotation: rng.gen_range(-3.2..=3.2),
+        };
+        let mut payload = Vec::new();
+        packet.serialize(&mut payload);
+        // 12 + 10 bits
+        assert_eq!(payload.len(), 3);
+        match (&packet, Packet::deserialize(packet.id(), &payload).unwrap()) {
+            (
+                Packet::Paddle { position, rotation },
+                Packet::Paddle {
+                    position: decoded_position,
+                    rotation: decoded_rotation,
+                },
+            ) => {
+                assert!((position - decoded_position).abs() <= 100.0 / 4095.0 / 2.0 + 1e-5);
+                assert!((rotation - decoded_rotation).abs() <= 6.4 / 1023.0 / 2.0 + 1e-6);
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[test]
+fn packets_mixed_with_codec() {
+    let packet = Packet::Ball {
+        generation: 7,
+        timestamp: 1_234_567_890,
+        // on the grid of 12 bits, so that they are decoded exactly
+        position: [0.0, 100.0],
+        name: "ball".to_string(),
+    };
+    let mut payload = Vec::new();
+    packet.serialize(&mut payload);
+    // 40 + 2 * 12 bits, followed by the CBOR tuple of the other fields
+    assert_eq!(&payload[8..], &[0x82, 0x07, 0x64, b'b', b'a', b'l', b'l']);
+    assert_eq!(Packet::deserialize(packet.id(), &payload).unwrap(), packet);
+    assert!(Packet::deserialize(packet.id(), &payload[..7]).is_err());
+
+    let packet = Packet::Score(-8, 200);
+    payload.clear();
+    packet.serialize(&mut payload);
+    assert_eq!(Packet::deserialize(packet.id(), &payload).unwrap(), packet);
+    payload.clear();
+    Packet::Score(10, 200).serialize(&mut payload);
+    assert_eq!(
+        Packet::deserialize(packet.id(), &payload).unwrap(),
+        Packet::Score(7, 200)
+    );
+}
+
+#[test]
+fn schema_records_quantization() {
+    let schema = Packet::schema().unwrap();
+    let rotation = &schema.packets[0].fields[1];
+    assert_eq!(
+        rotation.quantize,
+        Some(Quantization {
+            min: -3.2,
+            max: 3.2,
+            bits: 10
+        })
+    );
+    let timestamp = &schema.packets[1].fields[1];
+    assert_eq!(timestamp.quantize.unwrap().max, (1u64 << 40) as f64 - 1.0);
+    assert_eq!(schema.packets[1].fields[0].quantize, None);
+}
@@ -0,0 +1,105 @@
+use futures::StreamExt;
+use rudp::{relay, rendezvous, rendezvous::Role, BypassResult, Config, Delivery, Path};
+use rudp_derive::PacketDesc;
+use std::{io, net::SocketAddr};
+use tokio::{
+    join,
+    net::UdpSocket,
+    time::{sleep, Duration, Instant},
+};
+
+const MAGIC: &[u8] = b"HOLEPUNCH";
+const TIME_LIMIT: Duration = Duration::from_secs(2);
+
+#[derive(serde::Serialize, serde::Deserialize, PacketDesc, PartialEq, Debug)]
+enum Packet {
+    #[packet(reliable)]
+    Hello { host: bool },
+}
+
+/// Connect both peers with the code, and exchange a reliable packet. Returns the path of each.
+async fn connect_pair(rendezvous_server: SocketAddr, relay_server: SocketAddr) -> [Path; 2] {
+    let peer = || async move {
+        let connection = relay::connect_with_fallback(
+            "127.0.0.1:0",
+            rendezvous_server,
+            relay_server,
+            "test",
+            MAGIC,
+            TIME_LIMIT,
+        )
+        .await
+        .unwrap();
+        let role = connection.role;
+        let (send, mut recv, stats) =
+            connection.start::<Packet, _>(Config::default(), BypassResult::ToUser);
+        let receipt = send.send_with_receipt(
+            Packet::Hello {
+                host: role == Role::Host,
+            },
+            Duration::from_secs(1),
+        );
+        assert_eq!(
+            recv.next().await,
+            Some(Packet::Hello {
+                host: role == Role::Guest
+            })
+        );
+        assert_eq!(receipt.await, Delivery::Delivered);
+        stats.path()
+    };
+    let (a, b) = join!(peer(), peer());
+    [a, b]
+}
+
+#[tokio::test]
+async fn punches_through_on_loopback() {
+    tokio::spawn(rendezvous::serve("127.0.0.1:47210"));
+    tokio::spawn(relay::serve("127.0.0.1:47211"));
+    sleep(Duration::from_millis(100)).await;
+    let paths = connect_pair(
+        "127.0.0.1:47210".parse().unwrap(),
+        "127.0.0.1:47211".parse().unwrap(),
+    )
+    .await;
+    assert_eq!(paths, [Path::Direct, Path::Direct]);
+}
+
+#[tokio::test]
+async fn falls_back_to_relay() {
+    // no rendezvous server, so hole punching times out
+    tokio::spawn(relay::serve("127.0.0.1:47213"));
+    sleep(Duration::from_millis(100)).await;
+    let start = Instant::now();
+    let paths = connect_pair(
+        "127.0.0.1:47212".parse().unwrap(),
+        "127.0.0.1:47213".parse().unwrap(),
+    )
+    .await;
+    assert_eq!(paths, [Path::Relayed, Path::Relayed]);
+    assert!(start.elapsed() < TIME_LIMIT + Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn gives_up_within_the_time_limit() {
+    // servers which never answer, so both attempts run until their deadline
+    let rendezvous_server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let relay_server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let start = Instant::now();
+    let result = relay::connect_with_fallback(
+        "127.0.0.1:0",
+        rendezvous_server.local_addr().unwrap(),
+        relay_server.local_addr().unwrap(),
+        "test",
+        MAGIC,
+        TIME_LIMIT,
+    )
+    .await;
+    assert_eq!(result.err().unwrap().kind(), io::ErrorKind::TimedOut);
+    let elapsed = start.elapsed();
+    assert!(
+        elapsed < TIME_LIMIT + Duration::from_millis(500),
+        "{:?}",
+        elapsed
+    );
+}
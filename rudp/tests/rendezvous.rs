@@ -0,0 +1,71 @@
+use futures::StreamExt;
+use rudp::{rendezvous, rendezvous::Role, start_udp_loop, BypassResult, Config, Delivery};
+use rudp_derive::PacketDesc;
+use std::{io, net::SocketAddr};
+use tokio::{
+    join,
+    net::UdpSocket,
+    time::{sleep, Duration, Instant},
+};
+
+const MAGIC: &[u8] = b"RENDEZVOUS";
+const TIME_LIMIT: Duration = Duration::from_secs(2);
+
+#[derive(serde::Serialize, serde::Deserialize, PacketDesc, PartialEq, Debug)]
+enum Packet {
+    #[packet(reliable)]
+    Hello { host: bool },
+}
+
+async fn peer(server: SocketAddr) -> Role {
+    let (socket, role) = rendezvous::connect("127.0.0.1:0", server, "test", MAGIC, TIME_LIMIT)
+        .await
+        .unwrap();
+    let (send, mut recv, _) =
+        start_udp_loop::<Packet, _>(socket, Config::default(), BypassResult::ToUser);
+    let receipt = send.send_with_receipt(
+        Packet::Hello {
+            host: role == Role::Host,
+        },
+        Duration::from_secs(1),
+    );
+    assert_eq!(
+        recv.next().await,
+        Some(Packet::Hello {
+            host: role == Role::Guest
+        })
+    );
+    assert_eq!(receipt.await, Delivery::Delivered);
+    role
+}
+
+#[tokio::test]
+async fn punches_through_on_loopback() {
+    tokio::spawn(rendezvous::serve("127.0.0.1:47220"));
+    sleep(Duration::from_millis(100)).await;
+    let server = "127.0.0.1:47220".parse().unwrap();
+    let (a, b) = join!(peer(server), peer(server));
+    assert_ne!(a, b);
+}
+
+#[tokio::test]
+async fn gives_up_within_the_time_limit() {
+    // a server which never answers
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let start = Instant::now();
+    let result = rendezvous::connect(
+        "127.0.0.1:0",
+        server.local_addr().unwrap(),
+        "test",
+        MAGIC,
+        TIME_LIMIT,
+    )
+    .await;
+    assert_eq!(result.err().unwrap().kind(), io::ErrorKind::TimedOut);
+    let elapsed = start.elapsed();
+    assert!(
+        elapsed < TIME_LIMIT + Duration::from_millis(500),
+        "{:?}",
+        elapsed
+    );
+}
@@ -0,0 +1,83 @@
+use rudp::{start_udp_loop, BypassResult, Config, PacketHeader};
+use rudp_derive::PacketDesc;
+use tokio::{
+    net::UdpSocket,
+    time::{sleep, timeout, Duration},
+};
+
+#[derive(serde::Serialize, serde::Deserialize, PacketDesc, PartialEq, Debug)]
+enum Packet {
+    #[packet(reliable)]
+    Hello { index: u32 },
+}
+
+const TIMEOUT: Duration = Duration::from_millis(300);
+
+/// Receive a datagram, return its slot and generation.
+async fn next(remote: &UdpSocket) -> (isize, i64) {
+    let mut buffer = [0u8; 1500];
+    let len = timeout(TIMEOUT, remote.recv(&mut buffer))
+        .await
+        .unwrap()
+        .unwrap();
+    let (header, _) = PacketHeader::deserialize(&buffer[..len]).unwrap();
+    (header.slot, header.generation)
+}
+
+async fn ack(remote: &UdpSocket, (slot, generation): (isize, i64)) {
+    let mut data = Vec::new();
+    PacketHeader::new(0, -slot, generation).serialize(&mut data);
+    remote.send(&data).await.unwrap();
+}
+
+#[tokio::test]
+async fn acked_slots_are_reused_last_in_first_out() {
+    let local = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let remote = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    local.connect(remote.local_addr().unwrap()).await.unwrap();
+    remote.connect(local.local_addr().unwrap()).await.unwrap();
+    let config = Config {
+        timeout: TIMEOUT,
+        slot_capacity: 3,
+        ..Default::default()
+    };
+    let (send, _recv, stats) = start_udp_loop::<Packet, _>(local, config, BypassResult::ToUser);
+    let mut sent = Vec::new();
+    for index in 0..3 {
+        send.unbounded_send(Packet::Hello { index }).unwrap();
+        sent.push(next(&remote).await);
+    }
+    assert_eq!(
+        sent.iter().map(|&(slot, _)| slot).collect::<Vec<_>>(),
+        [1, 2, 3]
+    );
+    ack(&remote, sent[2]).await;
+    ack(&remote, sent[0]).await;
+    sleep(Duration::from_millis(20)).await;
+    for index in 3..5 {
+        send.unbounded_send(Packet::Hello { index }).unwrap();
+        sent.push(next(&remote).await);
+    }
+    assert_eq!(sent[3].0, 1);
+    assert_eq!(sent[4].0, 3);
+    ack(&remote, sent[4]).await;
+    ack(&remote, sent[3]).await;
+    sleep(Duration::from_millis(20)).await;
+    // many more entries than slots for the retransmissions, while the second packet is in flight
+    for index in 5..20 {
+        send.unbounded_send(Packet::Hello { index }).unwrap();
+        let datagram = next(&remote).await;
+        assert_eq!(datagram.0, 1);
+        ack(&remote, datagram).await;
+        sleep(Duration::from_millis(5)).await;
+    }
+    assert_eq!(next(&remote).await, sent[1]);
+    assert_eq!(stats.retransmissions(), 1);
+    ack(&remote, sent[1]).await;
+    // the deadlines of the earlier packets in the reused slots pass without a retransmission
+    let mut buffer = [0u8; 1500];
+    assert!(timeout(3 * TIMEOUT, remote.recv(&mut buffer))
+        .await
+        .is_err());
+    assert_eq!(stats.retransmissions(), 1);
+}
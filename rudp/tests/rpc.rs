@@ -0,0 +1,129 @@
+use futures::StreamExt;
+use rudp::{
+    rpc::{respond, CallOptions, Rpc, RpcError},
+    start_udp_loop, BypassResult, Config,
+};
+use rudp_derive::PacketDesc;
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+};
+use tokio::{
+    net::UdpSocket,
+    time::{sleep, timeout, Duration, Instant},
+};
+
+#[derive(serde::Serialize, serde::Deserialize, PacketDesc, Clone, PartialEq, Debug)]
+enum Packet {
+    Add {
+        a: i32,
+        b: i32,
+        #[packet(correlation)]
+        call: u64,
+    },
+    #[packet(response_to = "Add")]
+    Sum {
+        sum: i32,
+        #[packet(correlation)]
+        call: u64,
+    },
+}
+
+const OPTIONS: CallOptions = CallOptions {
+    timeout: Duration::from_millis(100),
+    retries: 2,
+};
+
+#[tokio::test]
+async fn retries_after_a_timeout_and_completes_once() {
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.connect(server.local_addr().unwrap()).await.unwrap();
+    server.connect(client.local_addr().unwrap()).await.unwrap();
+    let (server_send, mut requests, _) =
+        start_udp_loop::<Packet, _>(server, Config::default(), BypassResult::ToUser);
+    let handled = Arc::new(AtomicU32::new(0));
+    let count = handled.clone();
+    // the first request is answered too late, after the client sent it again
+    tokio::spawn(async move {
+        while let Some(request) = requests.next().await {
+            let delay = match count.fetch_add(1, Ordering::Relaxed) {
+                0 => Duration::from_millis(150),
+                _ => Duration::from_millis(0),
+            };
+            let send = server_send.clone();
+            tokio::spawn(async move {
+                sleep(delay).await;
+                if let Packet::Add { a, b, .. } = request {
+                    let response = respond(
+                        &request,
+                        Packet::Sum {
+                            sum: a + b,
+                            call: 0,
+                        },
+                    );
+                    let _ = send.unbounded_send(response);
+                }
+            });
+        }
+    });
+    let rpc = Arc::new(Rpc::new());
+    let dispatch = rpc.clone();
+    let (send, mut stray, _) = start_udp_loop::<Packet, _>(client, Config::default(), move |p| {
+        match dispatch.dispatch(p) {
+            Some(p) => BypassResult::ToUser(p),
+            None => BypassResult::Discard,
+        }
+    });
+    let request = Packet::Add {
+        a: 1,
+        b: 2,
+        call: 0,
+    };
+    let response = rpc.call(&send, request, OPTIONS).await.unwrap();
+    assert_eq!(response, Packet::Sum { sum: 3, call: 1 });
+    assert_eq!(handled.load(Ordering::Relaxed), 2);
+    // the late answer to the first request completes nothing
+    let late = timeout(Duration::from_secs(1), stray.next()).await.unwrap();
+    assert_eq!(late, Some(Packet::Sum { sum: 3, call: 1 }));
+    // the next call gets its own answer
+    let request = Packet::Add {
+        a: 2,
+        b: 2,
+        call: 0,
+    };
+    let response = rpc.call(&send, request, OPTIONS).await.unwrap();
+    assert_eq!(response, Packet::Sum { sum: 4, call: 2 });
+    assert_eq!(handled.load(Ordering::Relaxed), 3);
+    assert!(timeout(Duration::from_millis(200), stray.next())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn gives_up_after_the_retries() {
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    // never answers
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.connect(server.local_addr().unwrap()).await.unwrap();
+    let rpc = Arc::new(Rpc::new());
+    let dispatch = rpc.clone();
+    let (send, _recv, _) = start_udp_loop::<Packet, _>(client, Config::default(), move |p| {
+        match dispatch.dispatch(p) {
+            Some(p) => BypassResult::ToUser(p),
+            None => BypassResult::Discard,
+        }
+    });
+    let start = Instant::now();
+    let request = Packet::Add {
+        a: 1,
+        b: 2,
+        call: 0,
+    };
+    assert_eq!(
+        rpc.call(&send, request, OPTIONS).await,
+        Err(RpcError::Timeout)
+    );
+    let elapsed = start.elapsed();
+    assert!(elapsed >= 3 * OPTIONS.timeout && elapsed < 4 * OPTIONS.timeout);
+}
@@ -0,0 +1,159 @@
+use futures::StreamExt;
+use rudp::{
+    hand_shake::{
+        client_handshake, client_handshake_resumable, server_accept, server_accept_resumable,
+    },
+    start_udp_loop, BypassResult, Config, Delivery, PacketDesc, PacketHeader, Session,
+};
+use rudp_derive::PacketDesc;
+use tokio::{
+    net::UdpSocket,
+    time::{sleep, timeout, Duration},
+};
+
+const MAGIC: &[u8] = b"SESSION";
+
+#[derive(serde::Serialize, serde::Deserialize, PacketDesc, PartialEq, Debug)]
+enum Packet {
+    #[packet(reliable)]
+    Hello { index: u32 },
+    #[packet(unreliable)]
+    Position { x: f32 },
+}
+
+fn datagram(id: u32, slot: isize, generation: i64, flags: u8) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut header = PacketHeader::new(id, slot, generation);
+    header.flags = flags;
+    header.serialize(&mut data);
+    data
+}
+
+#[tokio::test]
+async fn invalid_ack_slots_are_discarded() {
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let remote = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    server.connect(remote.local_addr().unwrap()).await.unwrap();
+    remote.connect(server.local_addr().unwrap()).await.unwrap();
+    let (_send, mut recv, _) =
+        start_udp_loop::<Packet, _>(server, Config::default(), BypassResult::ToUser);
+    // the slot used for control datagrams before they had a flag, an ACK beyond the slot
+    // capacity, and `RESUME` to a loop without a session
+    for data in [
+        datagram(1, isize::MIN, 7, 0),
+        datagram(0, -1000, 0, 0),
+        datagram(1, 0, 7, 4),
+    ] {
+        remote.send(&data).await.unwrap();
+    }
+    let mut data = datagram(Packet::Position { x: 1.0 }.id(), 0, 0, 0);
+    Packet::Position { x: 1.0 }.serialize(&mut data);
+    remote.send(&data).await.unwrap();
+    let received = timeout(Duration::from_secs(1), recv.next()).await.unwrap();
+    assert_eq!(received, Some(Packet::Position { x: 1.0 }));
+}
+
+#[tokio::test]
+async fn resumable_client_with_a_server_without_session() {
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let server_address = server.local_addr().unwrap();
+    let accept = tokio::spawn(async move { server_accept(server, MAGIC).await });
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.connect(server_address).await.unwrap();
+    let client = client_handshake(client, MAGIC).await;
+    // the client probes with `RESUME` every 50ms while the server is silent
+    let config = Config {
+        session: Some(Session {
+            id: 7,
+            peer: server_address,
+        }),
+        resumption_window: Duration::from_millis(200),
+        ..Default::default()
+    };
+    let (client_send, mut client_recv, _) =
+        start_udp_loop::<Packet, _>(client, config, BypassResult::ToUser);
+    // the server finishes the handshake with the first datagram of the client
+    let server = accept.await.unwrap();
+    let (server_send, mut server_recv, _) =
+        start_udp_loop::<Packet, _>(server, Config::default(), BypassResult::ToUser);
+    sleep(Duration::from_millis(150)).await;
+    let receipt = client_send.send_with_receipt(Packet::Hello { index: 1 }, Duration::from_secs(1));
+    assert_eq!(
+        timeout(Duration::from_secs(1), server_recv.next())
+            .await
+            .unwrap(),
+        Some(Packet::Hello { index: 1 })
+    );
+    assert_eq!(receipt.await, Delivery::Delivered);
+    let receipt = server_send.send_with_receipt(Packet::Hello { index: 2 }, Duration::from_secs(1));
+    assert_eq!(
+        timeout(Duration::from_secs(1), client_recv.next())
+            .await
+            .unwrap(),
+        Some(Packet::Hello { index: 2 })
+    );
+    assert_eq!(receipt.await, Delivery::Delivered);
+}
+
+#[tokio::test]
+async fn resumes_with_batches() {
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let server_address = server.local_addr().unwrap();
+    let accept = tokio::spawn(async move { server_accept_resumable(server, MAGIC).await });
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.connect(server_address).await.unwrap();
+    let (client, session) = client_handshake_resumable(client, MAGIC).await.unwrap();
+    let config = |session| Config {
+        session: Some(session),
+        slot_capacity: 64,
+        ..Default::default()
+    };
+    let (client_send, mut client_recv, _) =
+        start_udp_loop::<Packet, _>(client, config(session), BypassResult::ToUser);
+    let (server, server_session) = accept.await.unwrap().unwrap();
+    let (server_send, mut server_recv, _) =
+        start_udp_loop::<Packet, _>(server, config(server_session), BypassResult::ToUser);
+    // bursts fill whole batches in both directions
+    for (send, recv) in [
+        (&client_send, &mut server_recv),
+        (&server_send, &mut client_recv),
+    ] {
+        for index in 0..100 {
+            send.unbounded_send(Packet::Hello { index }).unwrap();
+        }
+        let mut received = Vec::new();
+        while received.len() < 100 {
+            match timeout(Duration::from_secs(1), recv.next()).await {
+                Ok(Some(Packet::Hello { index })) => received.push(index),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        received.sort_unstable();
+        assert_eq!(received, (0..100).collect::<Vec<_>>());
+    }
+    // the client moved, the server answers `RESUME` and sends to the new address from then on
+    let moved = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    moved.connect(server_address).await.unwrap();
+    moved
+        .send(&datagram(1, 0, session.id as i64, 4))
+        .await
+        .unwrap();
+    let mut buffer = [0u8; 1500];
+    let len = timeout(Duration::from_secs(1), moved.recv(&mut buffer))
+        .await
+        .unwrap()
+        .unwrap();
+    let (header, _) = PacketHeader::deserialize(&buffer[..len]).unwrap();
+    assert!(header.is_control());
+    assert_eq!(header.id, 2);
+    server_send
+        .unbounded_send(Packet::Hello { index: 100 })
+        .unwrap();
+    let len = timeout(Duration::from_secs(1), moved.recv(&mut buffer))
+        .await
+        .unwrap()
+        .unwrap();
+    let (header, _) = PacketHeader::deserialize(&buffer[..len]).unwrap();
+    assert_eq!(header.id, Packet::Hello { index: 100 }.id());
+    assert!(header.slot > 0);
+}
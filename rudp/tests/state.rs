@@ -0,0 +1,48 @@
+use futures::StreamExt;
+use rudp::{start_udp_loop, BypassResult, Config, Delivery, PacketDesc};
+use rudp_derive::PacketDesc;
+use tokio::{
+    net::UdpSocket,
+    time::{timeout, Duration, Instant},
+};
+
+#[derive(serde::Serialize, serde::Deserialize, PacketDesc, PartialEq, Debug)]
+enum Packet {
+    #[packet(reliable, state)]
+    Score { points: u32 },
+}
+
+#[tokio::test]
+async fn held_receipts_resolve_in_time() {
+    let a = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let b = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    a.connect(b.local_addr().unwrap()).await.unwrap();
+    b.connect(a.local_addr().unwrap()).await.unwrap();
+    let config = Config {
+        state_interval: Duration::from_secs(1),
+        ..Default::default()
+    };
+    let (send, _recv, _) = start_udp_loop::<Packet, _>(a, config, BypassResult::ToUser);
+    let (_send, mut recv, _) =
+        start_udp_loop::<Packet, _>(b, Config::default(), BypassResult::ToUser);
+    let first = send.send_with_receipt(Packet::Score { points: 1 }, Duration::from_secs(1));
+    assert_eq!(first.await, Delivery::Delivered);
+    assert_eq!(recv.next().await, Some(Packet::Score { points: 1 }));
+    // held back until the interval passed, and replaced before that
+    let replaced = send.send_with_receipt(Packet::Score { points: 2 }, Duration::from_secs(5));
+    let expiring = send.send_with_receipt(Packet::Score { points: 3 }, Duration::from_millis(100));
+    let start = Instant::now();
+    assert_eq!(
+        timeout(Duration::from_millis(50), replaced).await,
+        Ok(Delivery::Expired)
+    );
+    assert_eq!(
+        timeout(Duration::from_millis(500), expiring).await,
+        Ok(Delivery::Expired)
+    );
+    assert!(start.elapsed() < Duration::from_millis(500));
+    // an expired packet is not passed on after the interval
+    let last = send.send_with_receipt(Packet::Score { points: 4 }, Duration::from_secs(5));
+    assert_eq!(last.await, Delivery::Delivered);
+    assert_eq!(recv.next().await, Some(Packet::Score { points: 4 }));
+}
@@ -0,0 +1,64 @@
+use rudp::{start_udp_loop, stream::CHUNK_SIZE, BypassResult, Config};
+use rudp_derive::PacketDesc;
+use tokio::{
+    net::UdpSocket,
+    time::{timeout, Duration},
+};
+
+#[derive(serde::Serialize, serde::Deserialize, PacketDesc, PartialEq, Debug)]
+enum Packet {
+    #[packet(reliable)]
+    Hello { index: u32 },
+}
+
+const WINDOW: usize = 3;
+
+#[tokio::test]
+async fn round_trip_with_losses() {
+    let a = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let b = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    a.connect(b.local_addr().unwrap()).await.unwrap();
+    b.connect(a.local_addr().unwrap()).await.unwrap();
+    // lost chunks are retransmitted, so the reader gets them out of order
+    let config = || Config {
+        drop_percentage: 20,
+        ..Default::default()
+    };
+    let (a_send, _a_recv, _) = start_udp_loop::<Packet, _>(a, config(), BypassResult::ToUser);
+    let (b_send, _b_recv, _) = start_udp_loop::<Packet, _>(b, config(), BypassResult::ToUser);
+    let data: Vec<u8> = (0..20 * CHUNK_SIZE + 123)
+        .map(|i| (i * 7 % 251) as u8)
+        .collect();
+    let mut writer = a_send.open_stream();
+    writer.set_window(WINDOW);
+    let id = writer.id();
+    let expected = data.clone();
+    let read = tokio::spawn(async move {
+        let mut reader = b_send.accept_stream().await.unwrap();
+        assert_eq!(reader.id(), id);
+        let mut received = Vec::new();
+        while let Some(chunk) = reader.read().await.unwrap() {
+            received.extend_from_slice(&chunk);
+        }
+        // the end is only reported once `END` arrived
+        assert_eq!(reader.length(), Some(received.len() as u64));
+        received
+    });
+    // writes of odd sizes, split into chunks across the window
+    for part in data.chunks(2 * CHUNK_SIZE + 50) {
+        writer.write(part).await.unwrap();
+        let progress = writer.progress();
+        assert!(progress.written - progress.acknowledged <= (WINDOW * CHUNK_SIZE) as u64);
+    }
+    let progress = timeout(Duration::from_secs(10), writer.finish())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(progress.written, data.len() as u64);
+    assert_eq!(progress.acknowledged, data.len() as u64);
+    let received = timeout(Duration::from_secs(10), read)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(received, expected);
+}
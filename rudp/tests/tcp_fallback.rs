@@ -0,0 +1,30 @@
+use rudp::tcp;
+use std::io;
+use tokio::{
+    net::TcpListener,
+    time::{Duration, Instant},
+};
+
+const MAGIC: &[u8] = b"TCPFALLBACK";
+
+#[tokio::test]
+async fn fallback_gives_up_on_a_silent_server() {
+    // nothing listens for UDP on the port, so the client falls back to TCP, where the connection
+    // is accepted but the magic is never answered
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        let mut accepted = Vec::new();
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            accepted.push(stream);
+        }
+    });
+    let start = Instant::now();
+    let result = tcp::connect_with_fallback(&server, MAGIC, Duration::from_millis(200)).await;
+    match result {
+        Err(e) => assert_eq!(e.kind(), io::ErrorKind::TimedOut),
+        Ok(_) => panic!("connected to a server which never answered"),
+    }
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
@@ -1,6 +1,6 @@
 use proc_macro::TokenStream;
-use syn::{DeriveInput, NestedMeta, Meta, Ident, DataEnum, Fields};
-use quote::quote;
+use syn::{punctuated::Punctuated, Attribute, DeriveInput, NestedMeta, Meta, Ident, DataEnum, Field, Fields, Lit, LitInt, LitStr, Member, Index, Path, Token};
+use quote::{format_ident, quote};
 
 enum FieldType {
     Struct,
@@ -9,88 +9,269 @@ enum FieldType {
 }
 
 struct Packet {
+    /// The ID in the header, the index of the variant unless given by `#[packet(id = N)]`.
+    id: u32,
+    explicit_id: Option<LitInt>,
     reliable: bool,
     ordered: bool,
+    compress: bool,
+    state: bool,
     name: Ident,
     field: FieldType,
+    fields: Vec<Field>,
+    /// How each field is bit-packed, if marked with `#[quantize(...)]`.
+    quantize: Vec<Option<Quantization>>,
+    /// The field holding the correlation ID, and the number of fields of the variant.
+    correlation: Option<(Member, usize)>,
+    /// Name of the request variant this variant responds to.
+    response_to: Option<LitStr>,
 }
 
-#[proc_macro_derive(PacketDesc, attributes(packet))]
+/// Same as `rudp::bitpack::Quantization`.
+#[derive(Clone, Copy)]
+struct Quantization {
+    min: f64,
+    max: f64,
+    bits: u32,
+}
+
+#[proc_macro_derive(PacketDesc, attributes(packet, quantize))]
 pub fn packet_desc_derive(input: TokenStream) -> TokenStream {
     let input = syn::parse_macro_input!(input as DeriveInput);
-    expand_desc_input(input).into()
+    expand_desc_input(input).unwrap_or_else(|err| err.to_compile_error()).into()
 }
 
-fn expand_desc_input(derive_input: DeriveInput) -> proc_macro2::TokenStream {
-    if let syn::Data::Enum(data) = derive_input.data {
-        let packets = data_to_packet_vec(data);
-        let name = &derive_input.ident;
-        let (id_stream, reliable_stream, ordered_stream) =
-            token_streams(name, &packets);
-        let gen = quote! {
-            impl rudp::PacketDesc for #name {
-                fn id(&self) -> u32 {
-                    match self {
-                        #id_stream
-                     }
+fn expand_desc_input(derive_input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let data = match derive_input.data {
+        syn::Data::Enum(data) => data,
+        syn::Data::Struct(data) => {
+            return Err(syn::Error::new(data.struct_token.span, "PacketDesc can only be derived for an enum, with a variant for each packet"))
+        }
+        syn::Data::Union(data) => {
+            return Err(syn::Error::new(data.union_token.span, "PacketDesc can only be derived for an enum, with a variant for each packet"))
+        }
+    };
+    if !derive_input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(&derive_input.generics, "PacketDesc cannot be derived for a generic enum"));
+    }
+    let EnumOptions { codec, codec_name, reserved } = enum_options(&derive_input.attrs)?;
+    let mut packets = data_to_packet_vec(data)?;
+    assign_ids(&mut packets, &reserved)?;
+    assign_quantizations(&mut packets)?;
+    let name = &derive_input.ident;
+    let (id_stream, reliable_stream, ordered_stream) =
+        token_streams(name, &packets);
+    let (correlation_stream, set_correlation_stream, response_to_stream) =
+        rpc_token_streams(name, &packets)?;
+    let (serialize_stream, deserialize_stream) = payload_token_streams(name, &codec, &packets);
+    let schema_stream = schema_token_stream(name, &codec_name, &packets);
+    let compress_ids = packets
+        .iter()
+        .filter(|packet| packet.compress)
+        .map(|packet| packet.id);
+    let state_ids = packets
+        .iter()
+        .filter(|packet| packet.state)
+        .map(|packet| packet.id);
+    let gen = quote! {
+        // the bounds of `#[quantize]` could be close to constants such as PI
+        #[allow(clippy::approx_constant)]
+        impl rudp::PacketDesc for #name {
+            fn id(&self) -> u32 {
+                match self {
+                    #id_stream
+                 }
+            }
+
+            fn serialize(&self, writer: &mut Vec<u8>) {
+                match self {
+                    #serialize_stream
                 }
+            }
 
-                fn serialize(&self, writer: &mut Vec<u8>) {
-                    use serde_cbor::to_writer;
-                    to_writer(writer, self).unwrap();
+            fn reliable(&self) -> bool {
+                match self {
+                    #reliable_stream
                 }
+            }
 
-                fn reliable(&self) -> bool {
-                    match self {
-                        #reliable_stream
-                    }
+            fn ordered(id: u32) -> bool {
+                match id {
+                    #ordered_stream
                 }
+            }
 
-                fn ordered(id: u32) -> bool {
-                    match id {
-                        #ordered_stream
-                    }
+            fn deserialize(id: u32, data: &[u8]) -> Result<Self, rudp::DeserializeError> {
+                match id {
+                    #deserialize_stream
+                    _ => Err(rudp::DeserializeError(format!("Unknown packet ID {}", id))),
                 }
+            }
 
-                fn deserialize(_: u32, data: &[u8]) -> Result<Self, rudp::DeserializeError> {
-                    use serde_cbor::from_slice;
-                    use rudp::DeserializeError;
-                    from_slice(data).map_err(|err| {
-                        rudp::DeserializeError(format!("serde_cbor error: {:?}", err))
-                    })
+            #[allow(unreachable_patterns)]
+            fn correlation(&self) -> Option<u64> {
+                match self {
+                    #correlation_stream
+                    _ => None,
                 }
             }
-        };
-        gen
-    } else {
-        panic!("This derive macro is designed only for enum type!");
+
+            #[allow(unreachable_patterns)]
+            fn set_correlation(&mut self, id: u64) {
+                match self {
+                    #set_correlation_stream
+                    _ => (),
+                }
+            }
+
+            fn response_to(id: u32) -> Option<u32> {
+                match id {
+                    #response_to_stream
+                    _ => None,
+                }
+            }
+
+            fn compress(id: u32) -> bool {
+                match id {
+                    #(#compress_ids => true,)*
+                    _ => false,
+                }
+            }
+
+            fn state(id: u32) -> bool {
+                match id {
+                    #(#state_ids => true,)*
+                    _ => false,
+                }
+            }
+
+            fn schema() -> Option<&'static rudp::schema::Schema> {
+                static SCHEMA: rudp::schema::Schema = #schema_stream;
+                Some(&SCHEMA)
+            }
+        }
+    };
+    Ok(gen)
+}
+
+/// Attributes of the enum.
+struct EnumOptions {
+    /// The codec chosen by `#[packet(codec = "...")]`, CBOR by default. The built-in codecs are
+    /// referred to by name, anything else is the path of a type implementing `rudp::codec::Codec`.
+    codec: proc_macro2::TokenStream,
+    /// The codec as written, for the schema.
+    codec_name: String,
+    /// IDs retired by `#[packet(reserved(3, 5))]`, which no variant could use anymore.
+    reserved: Vec<LitInt>,
+}
+
+fn enum_options(attrs: &[Attribute]) -> syn::Result<EnumOptions> {
+    let mut codec = quote! { rudp::codec::Cbor };
+    let mut codec_name = None;
+    let mut reserved = Vec::new();
+    misplaced_quantize(attrs)?;
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("packet")) {
+        for nested in packet_options(attr)? {
+            match &nested {
+                NestedMeta::Meta(Meta::List(list)) if list.path.is_ident("reserved") => {
+                    for id in list.nested.iter() {
+                        match id {
+                            NestedMeta::Lit(Lit::Int(id)) => reserved.push(id.clone()),
+                            _ => return Err(syn::Error::new_spanned(id, "reserved should list the retired IDs, such as reserved(3, 5)")),
+                        }
+                    }
+                }
+                NestedMeta::Meta(Meta::NameValue(value)) if value.path.is_ident("codec") => {
+                    if codec_name.is_some() {
+                        return Err(syn::Error::new_spanned(&value.path, "duplicate `codec`"));
+                    }
+                    let name = match &value.lit {
+                        Lit::Str(name) => name,
+                        lit => return Err(syn::Error::new_spanned(lit, "codec should be a string, such as \"bincode\"")),
+                    };
+                    codec = match name.value().as_str() {
+                        "cbor" => quote! { rudp::codec::Cbor },
+                        "bincode" => quote! { rudp::codec::Bincode },
+                        "postcard" => quote! { rudp::codec::Postcard },
+                        _ => {
+                            let path: Path = name.parse().map_err(|_| {
+                                syn::Error::new(name.span(), "codec should be cbor, bincode, postcard or the path of a type implementing rudp::codec::Codec")
+                            })?;
+                            quote! { #path }
+                        }
+                    };
+                    codec_name = Some(name.value());
+                }
+                _ => return Err(unexpected_option(&nested, &["codec = \"...\"", "reserved(...)"])),
+            }
+        }
     }
+    let codec_name = codec_name.unwrap_or_else(|| String::from("cbor"));
+    Ok(EnumOptions { codec, codec_name, reserved })
 }
 
-fn data_to_packet_vec(data: DataEnum) -> Vec<Packet> {
+fn data_to_packet_vec(data: DataEnum) -> syn::Result<Vec<Packet>> {
     let mut packets = Vec::with_capacity(data.variants.len());
     for var in data.variants.iter() {
-        let attr =
-            var.attrs.iter().find(|attr| {
-                attr.path.is_ident("packet")
-            });
-        let mut ordered = false;
-        let mut reliable = false;
-        if let Some(attr) = attr {
-            let meta = attr.parse_meta().unwrap();
-            if let Meta::List(list) = meta {
-                for nested in list.nested {
-                    if let NestedMeta::Meta(Meta::Path(path)) = nested {
-                        if path.is_ident("ordered") {
-                            ordered = true;
-                        } else if path.is_ident("reliable") {
-                            reliable = true;
-                        } else if path.is_ident("unreliable") {
-                            reliable = false;
-                        } else if path.is_ident("unordered") {
-                            ordered = false;
+        misplaced_quantize(&var.attrs)?;
+        let mut ordered = Flag::default();
+        let mut reliable = Flag::default();
+        let mut compress = Flag::default();
+        let mut state = Flag::default();
+        let mut response_to: Option<LitStr> = None;
+        let mut explicit_id: Option<LitInt> = None;
+        for attr in var.attrs.iter().filter(|attr| attr.path.is_ident("packet")) {
+            for nested in packet_options(attr)? {
+                match &nested {
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("reliable") => reliable.set(true, path)?,
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("unreliable") => reliable.set(false, path)?,
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("ordered") => ordered.set(true, path)?,
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("unordered") => ordered.set(false, path)?,
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("compress") => compress.set(true, path)?,
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("state") => state.set(true, path)?,
+                    NestedMeta::Meta(Meta::NameValue(value)) if value.path.is_ident("response_to") => {
+                        if response_to.is_some() {
+                            return Err(syn::Error::new_spanned(&value.path, "duplicate `response_to`"));
+                        }
+                        match &value.lit {
+                            Lit::Str(request) => response_to = Some(request.clone()),
+                            lit => return Err(syn::Error::new_spanned(lit, "response_to should be the name of the request variant, such as \"Ping\"")),
                         }
                     }
+                    NestedMeta::Meta(Meta::NameValue(value)) if value.path.is_ident("id") => {
+                        if explicit_id.is_some() {
+                            return Err(syn::Error::new_spanned(&value.path, "duplicate `id`"));
+                        }
+                        match &value.lit {
+                            Lit::Int(id) => explicit_id = Some(id.clone()),
+                            lit => return Err(syn::Error::new_spanned(lit, "id should be an integer")),
+                        }
+                    }
+                    _ => {
+                        return Err(unexpected_option(
+                            &nested,
+                            &["reliable", "unreliable", "ordered", "unordered", "compress", "state", "id = N", "response_to = \"...\""],
+                        ))
+                    }
+                }
+            }
+        }
+        let mut correlation = None;
+        for (i, field) in var.fields.iter().enumerate() {
+            for attr in field.attrs.iter().filter(|attr| attr.path.is_ident("packet")) {
+                for nested in packet_options(attr)? {
+                    let path = match &nested {
+                        NestedMeta::Meta(Meta::Path(path)) if path.is_ident("correlation") => path,
+                        _ => return Err(unexpected_option(&nested, &["correlation"])),
+                    };
+                    if correlation.is_some() {
+                        return Err(syn::Error::new_spanned(path, "only one field could be the correlation ID"));
+                    }
+                    let member = match &field.ident {
+                        Some(ident) => Member::Named(ident.clone()),
+                        None => Member::Unnamed(Index::from(i)),
+                    };
+                    correlation = Some((member, var.fields.len()));
                 }
             }
         }
@@ -100,13 +281,154 @@ fn data_to_packet_vec(data: DataEnum) -> Vec<Packet> {
             Fields::Unnamed(_) => FieldType::Tuple,
         };
         packets.push(Packet {
-            reliable,
-            ordered,
+            id: packets.len() as u32,
+            explicit_id,
+            reliable: reliable.value(),
+            ordered: ordered.value(),
+            compress: compress.value(),
+            state: state.value(),
             name: var.ident.clone(),
             field: field_type,
+            fields: var.fields.iter().cloned().collect(),
+            quantize: Vec::new(),
+            correlation,
+            response_to,
         });
     }
-    packets
+    Ok(packets)
+}
+
+/// The options listed in `#[packet(...)]`.
+fn packet_options(attr: &Attribute) -> syn::Result<Punctuated<NestedMeta, Token![,]>> {
+    match attr.parse_meta()? {
+        Meta::List(list) => Ok(list.nested),
+        meta => Err(syn::Error::new_spanned(meta, "expected a list of options, such as #[packet(reliable)]")),
+    }
+}
+
+/// An option which is not one of `expected` at this place, with a suggestion for typos such as
+/// `relaible`.
+fn unexpected_option(nested: &NestedMeta, expected: &[&str]) -> syn::Error {
+    let names: Vec<&str> = expected
+        .iter()
+        .map(|usage| usage.split([' ', '(']).next().unwrap_or(usage))
+        .collect();
+    let path = match nested {
+        NestedMeta::Meta(meta) => meta.path(),
+        NestedMeta::Lit(lit) => {
+            return syn::Error::new_spanned(lit, format!("expected one of {}", expected.join(", ")))
+        }
+    };
+    let name = quote! { #path }.to_string();
+    if let Some(i) = names.iter().position(|known| *known == name) {
+        return syn::Error::new_spanned(nested, format!("expected `{}`", expected[i]));
+    }
+    let closest = names
+        .iter()
+        .map(|known| (edit_distance(&name, known), known))
+        .min()
+        .filter(|(distance, _)| *distance <= 2 && *distance < name.len());
+    match closest {
+        Some((_, known)) => syn::Error::new_spanned(path, format!("unknown option `{}`, did you mean `{}`?", name, known)),
+        None => syn::Error::new_spanned(path, format!("unexpected option `{}`, expected one of {}", name, expected.join(", "))),
+    }
+}
+
+/// Levenshtein distance.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + (ca != *cb) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// `#[quantize(...)]` only applies to fields, it would be silently ignored on the enum or a
+/// variant.
+fn misplaced_quantize(attrs: &[Attribute]) -> syn::Result<()> {
+    match attrs.iter().find(|attr| attr.path.is_ident("quantize")) {
+        Some(attr) => Err(syn::Error::new_spanned(attr, "quantize applies to the fields of a variant")),
+        None => Ok(()),
+    }
+}
+
+/// A flag of a variant given by a pair of options such as `reliable` and `unreliable`, which
+/// could only be given once.
+#[derive(Default)]
+struct Flag(Option<(bool, Path)>);
+
+impl Flag {
+    fn set(&mut self, value: bool, path: &Path) -> syn::Result<()> {
+        match &self.0 {
+            Some((old, _)) if *old == value => Err(syn::Error::new_spanned(path, format!("duplicate `{}`", quote! { #path }))),
+            Some((_, old)) => Err(syn::Error::new_spanned(path, format!("`{}` contradicts `{}`", quote! { #path }, quote! { #old }))),
+            None => {
+                self.0 = Some((value, path.clone()));
+                Ok(())
+            }
+        }
+    }
+
+    /// Unset flags are false, such as unreliable and unordered.
+    fn value(&self) -> bool {
+        matches!(self.0, Some((true, _)))
+    }
+}
+
+/// Packet IDs from `rudp::stream::RESERVED_ID` are used by the protocol.
+const RESERVED_ID: u32 = u32::MAX - 15;
+
+/// Check the IDs given by `#[packet(id = N)]`. Once a variant has an explicit ID or some IDs are
+/// reserved, every variant needs an explicit ID, so that inserting a variant never renumbers the
+/// others.
+fn assign_ids(packets: &mut [Packet], reserved: &[LitInt]) -> syn::Result<()> {
+    if reserved.is_empty() && packets.iter().all(|packet| packet.explicit_id.is_none()) {
+        return Ok(());
+    }
+    let mut retired = Vec::with_capacity(reserved.len());
+    for lit in reserved {
+        let id = lit.base10_parse::<u32>()?;
+        if retired.contains(&id) {
+            return Err(syn::Error::new(lit.span(), format!("ID {} is reserved twice", id)));
+        }
+        retired.push(id);
+    }
+    for i in 0..packets.len() {
+        let lit = match &packets[i].explicit_id {
+            Some(lit) => lit,
+            None => {
+                return Err(syn::Error::new(
+                    packets[i].name.span(),
+                    "every variant needs an explicit ID once any variant has one or IDs are reserved, add #[packet(id = N)]",
+                ))
+            }
+        };
+        let id = lit.base10_parse::<u32>()?;
+        if id >= RESERVED_ID {
+            return Err(syn::Error::new(
+                lit.span(),
+                format!("IDs from {} are used by the protocol", RESERVED_ID),
+            ));
+        }
+        if retired.contains(&id) {
+            return Err(syn::Error::new(lit.span(), format!("ID {} is reserved", id)));
+        }
+        if let Some(other) = packets[..i].iter().find(|packet| packet.id == id) {
+            return Err(syn::Error::new(
+                lit.span(),
+                format!("ID {} is already used by {}", id, other.name),
+            ));
+        }
+        packets[i].id = id;
+    }
+    Ok(())
 }
 
 /// ## Return
@@ -115,8 +437,8 @@ fn token_streams(ident: &Ident, packets: &Vec<Packet>) -> (proc_macro2::TokenStr
     let mut id_list: Vec<proc_macro2::TokenStream> = Vec::with_capacity(packets.len());
     let mut reliable_list: Vec<proc_macro2::TokenStream> = Vec::with_capacity(packets.len());
     let mut ordered_list: Vec<proc_macro2::TokenStream> = Vec::with_capacity(packets.len());
-    for (id, packet) in packets.iter().enumerate() {
-        let id = id as u32;
+    for packet in packets.iter() {
+        let id = packet.id;
         let name = &packet.name;
         let reliable = packet.reliable;
         let ordered = packet.ordered;
@@ -133,7 +455,7 @@ fn token_streams(ident: &Ident, packets: &Vec<Packet>) -> (proc_macro2::TokenStr
             },
             FieldType::Tuple => {
                 quote! {
-                    #name(_) => #id,
+                    #name(..) => #id,
                 }
             },
         };
@@ -150,7 +472,7 @@ fn token_streams(ident: &Ident, packets: &Vec<Packet>) -> (proc_macro2::TokenStr
             },
             FieldType::Tuple => {
                 quote! {
-                    #name(_) => #reliable,
+                    #name(..) => #reliable,
                 }
             },
         };
@@ -161,8 +483,9 @@ fn token_streams(ident: &Ident, packets: &Vec<Packet>) -> (proc_macro2::TokenStr
         reliable_list.push(match_reliable);
         ordered_list.push(match_ordered);
     }
+    // unknown IDs, such as retired ones sent by older builds, are discarded when deserializing
     let placeholder = quote! {
-        _ => panic!("Invalid ID!"),
+        _ => false,
     };
     ordered_list.push(placeholder);
     let id_gen = quote! {
@@ -176,3 +499,350 @@ fn token_streams(ident: &Ident, packets: &Vec<Packet>) -> (proc_macro2::TokenStr
     };
     (id_gen, reliable_gen, ordered_gen)
 }
+
+/// ## Return
+/// (correlation, set_correlation, response_to)
+fn rpc_token_streams(ident: &Ident, packets: &Vec<Packet>) -> syn::Result<(proc_macro2::TokenStream, proc_macro2::TokenStream, proc_macro2::TokenStream)> {
+    let mut correlation_list: Vec<proc_macro2::TokenStream> = Vec::new();
+    let mut set_correlation_list: Vec<proc_macro2::TokenStream> = Vec::new();
+    let mut response_to_list: Vec<proc_macro2::TokenStream> = Vec::new();
+    for packet in packets.iter() {
+        let id = packet.id;
+        let name = &packet.name;
+        if let Some((member, len)) = &packet.correlation {
+            let pattern = match member {
+                Member::Named(field) => quote! { #ident::#name { #field: correlation, .. } },
+                Member::Unnamed(index) => {
+                    let fields = (0..*len).map(|i| {
+                        if i == index.index as usize {
+                            quote! { correlation }
+                        } else {
+                            quote! { _ }
+                        }
+                    });
+                    quote! { #ident::#name(#(#fields),*) }
+                }
+            };
+            correlation_list.push(quote! {
+                #pattern => Some(*correlation),
+            });
+            set_correlation_list.push(quote! {
+                #pattern => *correlation = id,
+            });
+        }
+        if let Some(request) = &packet.response_to {
+            let request_id = match packets.iter().find(|p| p.name == request.value()) {
+                Some(p) => p.id,
+                None => {
+                    let names: Vec<String> = packets.iter().map(|p| p.name.to_string()).collect();
+                    let closest = names
+                        .iter()
+                        .min_by_key(|name| edit_distance(&request.value(), name))
+                        .filter(|name| edit_distance(&request.value(), name) <= 2);
+                    let message = match closest {
+                        Some(name) => format!("no variant named `{}` to respond to, did you mean `{}`?", request.value(), name),
+                        None => format!("no variant named `{}` to respond to", request.value()),
+                    };
+                    return Err(syn::Error::new(request.span(), message));
+                }
+            };
+            response_to_list.push(quote! {
+                #id => Some(#request_id),
+            });
+        }
+    }
+    Ok((
+        quote! { #(#correlation_list)* },
+        quote! { #(#set_correlation_list)* },
+        quote! { #(#response_to_list)* },
+    ))
+}
+
+/// The payload only holds the fields of the variant, as the ID in the header tells the variant.
+/// Fields marked with `#[quantize(...)]` are bit-packed first, see `rudp::bitpack`. Of the other
+/// fields, a single field is serialized as is and several fields as a tuple, so a variant without
+/// fields has an empty payload.
+/// ## Return
+/// (serialize, deserialize)
+fn payload_token_streams(ident: &Ident, codec: &proc_macro2::TokenStream, packets: &[Packet]) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+    let mut serialize_list: Vec<proc_macro2::TokenStream> = Vec::with_capacity(packets.len());
+    let mut deserialize_list: Vec<proc_macro2::TokenStream> = Vec::with_capacity(packets.len());
+    for packet in packets.iter() {
+        let id = packet.id;
+        let name = &packet.name;
+        // bind the fields to generated names, so that they never shadow `writer` or `data`
+        let bindings: Vec<Ident> = (0..packet.fields.len())
+            .map(|i| format_ident!("__field{}", i))
+            .collect();
+        let pattern = match packet.field {
+            FieldType::Flat => quote! { #ident::#name },
+            FieldType::Struct => {
+                let names = packet.fields.iter().map(|field| &field.ident);
+                quote! { #ident::#name { #(#names: #bindings),* } }
+            }
+            FieldType::Tuple => quote! { #ident::#name(#(#bindings),*) },
+        };
+        let (mut packed, mut rest) = (Vec::new(), Vec::new());
+        for (i, quantization) in packet.quantize.iter().enumerate() {
+            let field = (&bindings[i], &packet.fields[i].ty);
+            match quantization {
+                Some(quantization) => packed.push((field, quantization_tokens(quantization))),
+                None => rest.push(field),
+            }
+        }
+        let rest_bindings: Vec<&Ident> = rest.iter().map(|(binding, _)| *binding).collect();
+        let rest_types = rest.iter().map(|(_, ty)| ty);
+        let (serialize_rest, deserialize_rest) = match rest.len() {
+            0 => (quote! {}, quote! {}),
+            1 => (
+                quote! { <#codec as rudp::codec::Codec>::serialize(#(#rest_bindings)*, writer); },
+                quote! {
+                    let #(#rest_bindings)*: #(#rest_types)* = <#codec as rudp::codec::Codec>::deserialize(data)?;
+                },
+            ),
+            _ => (
+                quote! { <#codec as rudp::codec::Codec>::serialize(&(#(#rest_bindings),*), writer); },
+                quote! {
+                    let (#(#rest_bindings),*): (#(#rest_types),*) = <#codec as rudp::codec::Codec>::deserialize(data)?;
+                },
+            ),
+        };
+        let (serialize, deserialize) = if packed.is_empty() {
+            (
+                quote! {{ #serialize_rest }},
+                quote! {{
+                    #deserialize_rest
+                    Ok(#pattern)
+                }},
+            )
+        } else {
+            let packed_bindings: Vec<&Ident> = packed.iter().map(|((binding, _), _)| *binding).collect();
+            let packed_types = packed.iter().map(|((_, ty), _)| ty);
+            let quantizations: Vec<&proc_macro2::TokenStream> = packed.iter().map(|(_, q)| q).collect();
+            let remaining = if rest.is_empty() {
+                quote! {}
+            } else {
+                quote! { let data = __bits.remaining(); }
+            };
+            (
+                quote! {{
+                    let mut __bits = rudp::bitpack::BitWriter::new(writer);
+                    #(rudp::bitpack::Quantize::quantize(#packed_bindings, &#quantizations, &mut __bits);)*
+                    __bits.finish();
+                    #serialize_rest
+                }},
+                quote! {{
+                    let mut __bits = rudp::bitpack::BitReader::new(data);
+                    #(let #packed_bindings: #packed_types = rudp::bitpack::Quantize::dequantize(&#quantizations, &mut __bits)?;)*
+                    #remaining
+                    #deserialize_rest
+                    Ok(#pattern)
+                }},
+            )
+        };
+        serialize_list.push(quote! {
+            #pattern => #serialize,
+        });
+        deserialize_list.push(quote! {
+            #id => #deserialize,
+        });
+    }
+    (
+        quote! { #(#serialize_list)* },
+        quote! { #(#deserialize_list)* },
+    )
+}
+
+/// The `rudp::bitpack::Quantization` literal.
+fn quantization_tokens(quantization: &Quantization) -> proc_macro2::TokenStream {
+    let float = |value: f64| {
+        let literal = proc_macro2::Literal::f64_suffixed(value.abs());
+        if value < 0.0 {
+            quote! { -#literal }
+        } else {
+            quote! { #literal }
+        }
+    };
+    let (min, max, bits) = (float(quantization.min), float(quantization.max), quantization.bits);
+    quote! {
+        rudp::bitpack::Quantization { min: #min, max: #max, bits: #bits }
+    }
+}
+
+enum Scalar {
+    Float,
+    Integer,
+}
+
+/// The kind of the numbers in the type, for a number or an array of numbers.
+fn scalar(ty: &syn::Type) -> Option<Scalar> {
+    match ty {
+        syn::Type::Path(path) if path.qself.is_none() => {
+            match path.path.get_ident()?.to_string().as_str() {
+                "f32" | "f64" => Some(Scalar::Float),
+                "u8" | "u16" | "u32" | "u64" | "u128" | "usize" | "i8" | "i16" | "i32" | "i64"
+                | "i128" | "isize" => Some(Scalar::Integer),
+                _ => None,
+            }
+        }
+        syn::Type::Array(array) => scalar(&array.elem),
+        syn::Type::Group(group) => scalar(&group.elem),
+        syn::Type::Paren(paren) => scalar(&paren.elem),
+        _ => None,
+    }
+}
+
+/// Parse `#[quantize(min = .., max = .., bits = ..)]` on every field.
+fn assign_quantizations(packets: &mut [Packet]) -> syn::Result<()> {
+    for packet in packets.iter_mut() {
+        let mut quantize = Vec::with_capacity(packet.fields.len());
+        for field in packet.fields.iter() {
+            let mut attrs = field.attrs.iter().filter(|attr| attr.path.is_ident("quantize"));
+            let attr = match attrs.next() {
+                Some(attr) => attr,
+                None => {
+                    quantize.push(None);
+                    continue;
+                }
+            };
+            if let Some(duplicate) = attrs.next() {
+                return Err(syn::Error::new_spanned(duplicate, "duplicate quantize attribute"));
+            }
+            quantize.push(Some(field_quantization(field, attr)?));
+        }
+        packet.quantize = quantize;
+    }
+    Ok(())
+}
+
+fn field_quantization(field: &Field, attr: &Attribute) -> syn::Result<Quantization> {
+    let scalar = scalar(&field.ty).ok_or_else(|| {
+        syn::Error::new_spanned(
+            &field.ty,
+            "quantize only applies to floats, integers, and arrays of them",
+        )
+    })?;
+    let (mut min, mut max, mut bits) = (None, None, None);
+    let list = match attr.parse_meta()? {
+        Meta::List(list) => list,
+        meta => {
+            return Err(syn::Error::new_spanned(
+                meta,
+                "expected #[quantize(min = .., max = .., bits = ..)]",
+            ))
+        }
+    };
+    for nested in list.nested.iter() {
+        let value = match nested {
+            NestedMeta::Meta(Meta::NameValue(value)) => value,
+            _ => return Err(syn::Error::new_spanned(nested, "expected min, max or bits with a value")),
+        };
+        let number = match (&value.lit, &scalar) {
+            (Lit::Int(int), _) => int.base10_parse::<f64>()?,
+            (Lit::Float(float), Scalar::Float) => float.base10_parse::<f64>()?,
+            (Lit::Float(_), Scalar::Integer) => {
+                return Err(syn::Error::new_spanned(&value.lit, "the bounds of an integer should be integers"))
+            }
+            _ => return Err(syn::Error::new_spanned(&value.lit, "expected a number")),
+        };
+        if !number.is_finite() {
+            return Err(syn::Error::new_spanned(&value.lit, "expected a finite number"));
+        }
+        if value.path.is_ident("min") {
+            min = Some(number);
+        } else if value.path.is_ident("max") {
+            max = Some(number);
+        } else if value.path.is_ident("bits") {
+            if !(1.0..=64.0).contains(&number) {
+                return Err(syn::Error::new_spanned(&value.lit, "bits should be within 1..=64"));
+            }
+            bits = Some(number as u32);
+        } else {
+            return Err(syn::Error::new_spanned(&value.path, "expected min, max or bits"));
+        }
+    }
+    let bits = bits.ok_or_else(|| syn::Error::new_spanned(attr, "quantize needs the number of bits"))?;
+    let quantization = match scalar {
+        Scalar::Float => match (min, max) {
+            (Some(min), Some(max)) if min < max => Quantization { min, max, bits },
+            (Some(_), Some(_)) => return Err(syn::Error::new_spanned(attr, "min should be less than max")),
+            _ => return Err(syn::Error::new_spanned(attr, "quantizing a float needs min and max")),
+        },
+        Scalar::Integer => {
+            let min = min.unwrap_or(0.0);
+            let largest = min + ((bits as f64).exp2() - 1.0);
+            match max {
+                Some(max) if max < min => return Err(syn::Error::new_spanned(attr, "min should not be more than max")),
+                Some(max) if max > largest => {
+                    return Err(syn::Error::new_spanned(attr, format!("{} bits cannot hold the range from min to max", bits)))
+                }
+                Some(max) => Quantization { min, max, bits },
+                None => Quantization { min, max: largest, bits },
+            }
+        }
+    };
+    Ok(quantization)
+}
+
+/// The `rudp::schema::Schema` of the packets.
+fn schema_token_stream(ident: &Ident, codec_name: &str, packets: &[Packet]) -> proc_macro2::TokenStream {
+    let name = ident.to_string();
+    let packet_list = packets.iter().map(|packet| {
+        let name = packet.name.to_string();
+        let id = packet.id;
+        let (reliable, ordered, compress, state) =
+            (packet.reliable, packet.ordered, packet.compress, packet.state);
+        let fields = packet.fields.iter().enumerate().map(|(i, field)| {
+            let name = match &field.ident {
+                Some(ident) => ident.to_string(),
+                None => i.to_string(),
+            };
+            let ty = type_name(&field.ty);
+            let quantize = match &packet.quantize[i] {
+                Some(quantization) => {
+                    let quantization = quantization_tokens(quantization);
+                    quote! { Some(#quantization) }
+                }
+                None => quote! { None },
+            };
+            quote! {
+                rudp::schema::FieldSchema { name: #name, ty: #ty, quantize: #quantize }
+            }
+        });
+        quote! {
+            rudp::schema::PacketSchema {
+                name: #name,
+                id: #id,
+                reliable: #reliable,
+                ordered: #ordered,
+                compress: #compress,
+                state: #state,
+                fields: &[#(#fields),*],
+            }
+        }
+    });
+    quote! {
+        rudp::schema::Schema {
+            name: #name,
+            codec: #codec_name,
+            packets: &[#(#packet_list),*],
+        }
+    }
+}
+
+/// The type as written, keeping only the spaces between two words, such as `Vec<[u8;4]>` or
+/// `&'static dyn Any`.
+fn type_name(ty: &syn::Type) -> String {
+    let tokens = quote! { #ty }.to_string();
+    let chars: Vec<char> = tokens.chars().collect();
+    let word = |c: &char| c.is_alphanumeric() || *c == '_';
+    let mut name = String::with_capacity(chars.len());
+    for (i, c) in chars.iter().enumerate() {
+        if *c != ' ' {
+            name.push(*c);
+        } else if i > 0 && i + 1 < chars.len() && word(&chars[i - 1]) && word(&chars[i + 1]) {
+            name.push(' ');
+        }
+    }
+    name
+}
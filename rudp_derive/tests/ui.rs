@@ -0,0 +1,7 @@
+/// Misuses of `#[derive(PacketDesc)]` should fail with a spanned error, the expected messages are
+/// in `tests/ui/*.stderr`. Run with `TRYBUILD=overwrite` to update them.
+#[test]
+fn ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
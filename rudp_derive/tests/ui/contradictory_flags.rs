@@ -0,0 +1,11 @@
+use rudp_derive::PacketDesc;
+
+#[derive(PacketDesc)]
+enum Packet {
+    #[packet(reliable, ordered)]
+    Hello { name: String },
+    #[packet(ordered, unordered)]
+    Position { x: f32, y: f32 },
+}
+
+fn main() {}
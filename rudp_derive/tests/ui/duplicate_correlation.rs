@@ -0,0 +1,8 @@
+use rudp_derive::PacketDesc;
+
+#[derive(PacketDesc)]
+enum Packet {
+    Ping(#[packet(correlation)] u64, #[packet(correlation)] u64),
+}
+
+fn main() {}
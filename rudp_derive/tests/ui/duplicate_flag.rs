@@ -0,0 +1,10 @@
+use rudp_derive::PacketDesc;
+
+#[derive(PacketDesc)]
+enum Packet {
+    #[packet(reliable)]
+    #[packet(compress, reliable)]
+    Map(Vec<u8>),
+}
+
+fn main() {}
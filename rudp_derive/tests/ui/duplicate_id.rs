@@ -0,0 +1,11 @@
+use rudp_derive::PacketDesc;
+
+#[derive(PacketDesc)]
+enum Packet {
+    #[packet(reliable, id = 0)]
+    Hello { name: String },
+    #[packet(unreliable, id = 0)]
+    Position { x: f32, y: f32 },
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+use rudp_derive::PacketDesc;
+
+#[derive(PacketDesc)]
+enum Packet {
+    Position {
+        #[quantize(min = 0.0, max = 100.0, bits = 8)]
+        #[quantize(min = 0.0, max = 10.0, bits = 4)]
+        x: f32,
+    },
+}
+
+fn main() {}
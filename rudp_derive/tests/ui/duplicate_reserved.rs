@@ -0,0 +1,10 @@
+use rudp_derive::PacketDesc;
+
+#[derive(PacketDesc)]
+#[packet(reserved(3, 5, 3))]
+enum Packet {
+    #[packet(id = 1)]
+    Ping,
+}
+
+fn main() {}
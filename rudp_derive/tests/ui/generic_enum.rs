@@ -0,0 +1,9 @@
+use rudp_derive::PacketDesc;
+
+#[derive(PacketDesc)]
+enum Packet<T> {
+    #[packet(reliable)]
+    Value(T),
+}
+
+fn main() {}
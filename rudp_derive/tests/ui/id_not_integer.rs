@@ -0,0 +1,9 @@
+use rudp_derive::PacketDesc;
+
+#[derive(PacketDesc)]
+enum Packet {
+    #[packet(reliable, id = "0")]
+    Hello { name: String },
+}
+
+fn main() {}
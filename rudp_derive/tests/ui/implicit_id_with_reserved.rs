@@ -0,0 +1,10 @@
+use rudp_derive::PacketDesc;
+
+#[derive(PacketDesc)]
+#[packet(reserved(1))]
+enum Packet {
+    Ping,
+    Pong,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use rudp_derive::PacketDesc;
+
+#[derive(PacketDesc)]
+#[packet(codec = "json!")]
+enum Packet {
+    Hello { name: String },
+}
+
+fn main() {}
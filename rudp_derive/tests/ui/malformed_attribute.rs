@@ -0,0 +1,9 @@
+use rudp_derive::PacketDesc;
+
+#[derive(PacketDesc)]
+enum Packet {
+    #[packet]
+    Hello { name: String },
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use rudp_derive::PacketDesc;
+
+#[derive(PacketDesc)]
+enum Packet {
+    #[quantize(min = 0.0, max = 100.0, bits = 12)]
+    Position { x: f32, y: f32 },
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use rudp_derive::PacketDesc;
+
+#[derive(PacketDesc)]
+enum Packet {
+    #[packet(relaible)]
+    Hello { name: String },
+}
+
+fn main() {}
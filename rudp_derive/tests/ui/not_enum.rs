@@ -0,0 +1,8 @@
+use rudp_derive::PacketDesc;
+
+#[derive(PacketDesc)]
+struct Packet {
+    number: u32,
+}
+
+fn main() {}
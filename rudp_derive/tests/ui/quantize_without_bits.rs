@@ -0,0 +1,11 @@
+use rudp_derive::PacketDesc;
+
+#[derive(PacketDesc)]
+enum Packet {
+    Position {
+        #[quantize(min = 0.0, max = 100.0)]
+        x: f32,
+    },
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+use rudp_derive::PacketDesc;
+
+#[derive(PacketDesc)]
+#[packet(reserved(1))]
+enum Packet {
+    #[packet(id = 0)]
+    Ping,
+    #[packet(id = 1)]
+    Pong,
+}
+
+fn main() {}
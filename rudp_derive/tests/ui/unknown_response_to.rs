@@ -0,0 +1,16 @@
+use rudp_derive::PacketDesc;
+
+#[derive(PacketDesc)]
+enum Packet {
+    Ping {
+        #[packet(correlation)]
+        call: u64,
+    },
+    #[packet(response_to = "Pnig")]
+    Pong {
+        #[packet(correlation)]
+        call: u64,
+    },
+}
+
+fn main() {}